@@ -0,0 +1,269 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use image::DynamicImage;
+use rustface::FaceInfo;
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::quality;
+
+/// Category every detection is filed under.
+const CATEGORY: &str = "face";
+
+/// Annotation schema for `--annotations-format`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum AnnotationFormat {
+    /// One COCO object-detection JSON file for the whole run
+    Coco,
+    /// One Pascal VOC XML file per image
+    Voc,
+}
+
+/// Bounding boxes of every detected face, collected for `--annotations`.
+///
+/// VOC files are written as each image is detected; the COCO file needs
+/// every image first and is written by `finish`.
+pub struct Annotations {
+    format: AnnotationFormat,
+    /// The COCO JSON file, or the directory VOC files are written under.
+    path: PathBuf,
+    root: PathBuf,
+    coco_images: Mutex<Vec<AnnotatedImage>>,
+}
+
+struct AnnotatedImage {
+    /// Path relative to the input root, with `/` separators.
+    file_name: String,
+    width: u32,
+    height: u32,
+    faces: Vec<FaceBox>,
+}
+
+/// A detection clipped to the image, in 0-based pixels.
+struct FaceBox {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    score: f64,
+    /// The detector's box reached past the image edge.
+    truncated: bool,
+}
+
+impl Annotations {
+    /// Annotations for images under `root`, the input directory (or the
+    /// directory of a single input file), which file names are relative to.
+    pub fn create(format: AnnotationFormat, path: &Path, root: &Path) -> Result<Self> {
+        if format == AnnotationFormat::Voc {
+            fs::create_dir_all(path).context("Failed to create annotations directory")?;
+        }
+        Ok(Annotations {
+            format,
+            path: path.to_path_buf(),
+            root: root.to_path_buf(),
+            coco_images: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Record the faces found in `img`, read from `input_path`.
+    pub fn add(&self, input_path: &Path, img: &DynamicImage, faces: &[FaceInfo]) -> Result<()> {
+        let relative = input_path.strip_prefix(&self.root).unwrap_or(input_path);
+        let image = AnnotatedImage {
+            file_name: relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            width: img.width(),
+            height: img.height(),
+            faces: faces
+                .iter()
+                .filter_map(|face| {
+                    let bbox = face.bbox();
+                    let (x, y, width, height) =
+                        quality::clip_to_image(bbox, img.width(), img.height())?;
+                    Some(FaceBox {
+                        x,
+                        y,
+                        width,
+                        height,
+                        score: face.score(),
+                        truncated: (width, height) != (bbox.width(), bbox.height()),
+                    })
+                })
+                .collect(),
+        };
+
+        match self.format {
+            AnnotationFormat::Coco => self.coco_images.lock().unwrap().push(image),
+            AnnotationFormat::Voc => {
+                let xml = voc_xml(&image, input_path, img.color().channel_count());
+                let xml_path = self.path.join(relative).with_extension("xml");
+                if let Some(parent) = xml_path.parent() {
+                    fs::create_dir_all(parent).context("Failed to create annotations directory")?;
+                }
+                fs::write(&xml_path, xml)
+                    .with_context(|| format!("Failed to write annotation {:?}", xml_path))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the COCO file. Nothing to do for VOC.
+    pub fn finish(self) -> Result<()> {
+        if self.format != AnnotationFormat::Coco {
+            return Ok(());
+        }
+
+        let mut images = self.coco_images.into_inner().unwrap();
+        // Parallel runs finish images in any order; keep ids reproducible.
+        images.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+
+        let json = serde_json::to_string_pretty(&coco_dataset(&images))
+            .context("Failed to render annotations")?;
+        fs::write(&self.path, json + "\n").context("Failed to write annotations file")
+    }
+}
+
+#[derive(Serialize)]
+struct CocoDataset<'a> {
+    info: CocoInfo,
+    licenses: [(); 0],
+    images: Vec<CocoImage<'a>>,
+    annotations: Vec<CocoAnnotation>,
+    categories: [CocoCategory; 1],
+}
+
+#[derive(Serialize)]
+struct CocoInfo {
+    description: &'static str,
+    version: &'static str,
+    date_created: String,
+}
+
+#[derive(Serialize)]
+struct CocoImage<'a> {
+    id: u64,
+    file_name: &'a str,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Serialize)]
+struct CocoAnnotation {
+    id: u64,
+    image_id: u64,
+    category_id: u64,
+    /// `[x, y, width, height]` from the top-left corner.
+    bbox: [u32; 4],
+    area: u64,
+    segmentation: [(); 0],
+    iscrowd: u8,
+    score: f64,
+}
+
+#[derive(Serialize)]
+struct CocoCategory {
+    id: u64,
+    name: &'static str,
+    supercategory: &'static str,
+}
+
+fn coco_dataset(images: &[AnnotatedImage]) -> CocoDataset<'_> {
+    let mut annotations = Vec::new();
+    for (image_id, image) in (1..).zip(images) {
+        for face in &image.faces {
+            annotations.push(CocoAnnotation {
+                id: annotations.len() as u64 + 1,
+                image_id,
+                category_id: 1,
+                bbox: [face.x, face.y, face.width, face.height],
+                area: face.width as u64 * face.height as u64,
+                segmentation: [],
+                iscrowd: 0,
+                score: face.score,
+            });
+        }
+    }
+
+    CocoDataset {
+        info: CocoInfo {
+            description: "Faces detected by face_cropper",
+            version: env!("CARGO_PKG_VERSION"),
+            date_created: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        },
+        licenses: [],
+        images: (1..)
+            .zip(images)
+            .map(|(id, image)| CocoImage {
+                id,
+                file_name: &image.file_name,
+                width: image.width,
+                height: image.height,
+            })
+            .collect(),
+        annotations,
+        categories: [CocoCategory {
+            id: 1,
+            name: CATEGORY,
+            supercategory: "person",
+        }],
+    }
+}
+
+/// The Pascal VOC annotation for `image`. VOC boxes are 1-based and
+/// inclusive of both corners.
+fn voc_xml(image: &AnnotatedImage, input_path: &Path, depth: u8) -> String {
+    let folder = input_path
+        .parent()
+        .and_then(Path::file_name)
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let filename = input_path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+
+    let mut xml = String::from("<annotation>\n");
+    let _ = writeln!(xml, "\t<folder>{}</folder>", escape(&folder));
+    let _ = writeln!(xml, "\t<filename>{}</filename>", escape(&filename));
+    let _ = writeln!(
+        xml,
+        "\t<path>{}</path>",
+        escape(&input_path.to_string_lossy())
+    );
+    xml.push_str("\t<source>\n\t\t<database>Unknown</database>\n\t</source>\n");
+    let _ = writeln!(
+        xml,
+        "\t<size>\n\t\t<width>{}</width>\n\t\t<height>{}</height>\n\t\t<depth>{}</depth>\n\t</size>",
+        image.width, image.height, depth
+    );
+    xml.push_str("\t<segmented>0</segmented>\n");
+
+    for face in &image.faces {
+        let _ = writeln!(
+            xml,
+            "\t<object>\n\t\t<name>{}</name>\n\t\t<pose>Unspecified</pose>\n\t\t<truncated>{}</truncated>\n\t\t<difficult>0</difficult>\n\t\t<bndbox>\n\t\t\t<xmin>{}</xmin>\n\t\t\t<ymin>{}</ymin>\n\t\t\t<xmax>{}</xmax>\n\t\t\t<ymax>{}</ymax>\n\t\t</bndbox>\n\t</object>",
+            CATEGORY,
+            face.truncated as u8,
+            face.x + 1,
+            face.y + 1,
+            face.x + face.width,
+            face.y + face.height
+        );
+    }
+
+    xml.push_str("</annotation>\n");
+    xml
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
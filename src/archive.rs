@@ -0,0 +1,122 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Component, Path};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Zip archive that crops are written into with `--output-zip`.
+///
+/// Entries are written one at a time as images finish, so only the crop
+/// being added is held in memory. Shared behind a mutex for parallel runs.
+pub struct ZipOutput {
+    writer: Mutex<ZipWriter<BufWriter<File>>>,
+}
+
+impl ZipOutput {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path).context("Failed to create output zip")?;
+        Ok(ZipOutput {
+            writer: Mutex::new(ZipWriter::new(BufWriter::new(file))),
+        })
+    }
+
+    /// Add `data` as the entry `name`. Images are already compressed, so
+    /// they are stored as is; pass `deflate` for text such as the manifest.
+    pub fn add(&self, name: &str, data: &[u8], deflate: bool) -> Result<()> {
+        let method = if deflate {
+            CompressionMethod::Deflated
+        } else {
+            CompressionMethod::Stored
+        };
+        let options = SimpleFileOptions::default().compression_method(method);
+
+        let mut writer = self.writer.lock().unwrap();
+        writer
+            .start_file(name, options)
+            .with_context(|| format!("Failed to add {} to output zip", name))?;
+        writer
+            .write_all(data)
+            .with_context(|| format!("Failed to add {} to output zip", name))
+    }
+
+    /// Write the central directory. Without this the archive is unreadable.
+    pub fn finish(self) -> Result<()> {
+        let writer = self.writer.into_inner().unwrap();
+        writer
+            .finish()
+            .context("Failed to finish output zip")?
+            .flush()
+            .context("Failed to finish output zip")
+    }
+}
+
+/// Uncompressed tar stream that crops are written into with `--output-tar`,
+/// to a file or to stdout (`-`).
+///
+/// Like `ZipOutput`, entries go out as images finish, so memory stays
+/// bounded however long the run. Tar has no central directory: the stream
+/// can be unpacked while it is still being written.
+pub struct TarOutput {
+    builder: Mutex<tar::Builder<Box<dyn Write + Send>>>,
+    /// Modification time given to every entry: the start of the run.
+    mtime: u64,
+}
+
+impl TarOutput {
+    pub fn create(path: &Path) -> Result<Self> {
+        let writer: Box<dyn Write + Send> = if path == Path::new("-") {
+            Box::new(BufWriter::new(io::stdout()))
+        } else {
+            Box::new(BufWriter::new(
+                File::create(path).context("Failed to create output tar")?,
+            ))
+        };
+        let mtime = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        Ok(TarOutput {
+            builder: Mutex::new(tar::Builder::new(writer)),
+            mtime,
+        })
+    }
+
+    /// Add `data` as the regular file `name`.
+    pub fn add(&self, name: &str, data: &[u8]) -> Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(self.mtime);
+        self.builder
+            .lock()
+            .unwrap()
+            .append_data(&mut header, name, data)
+            .with_context(|| format!("Failed to add {} to output tar", name))
+    }
+
+    /// Write the end-of-archive marker and flush.
+    pub fn finish(self) -> Result<()> {
+        let builder = self.builder.into_inner().unwrap();
+        builder
+            .into_inner()
+            .context("Failed to finish output tar")?
+            .flush()
+            .context("Failed to finish output tar")
+    }
+}
+
+/// Entry name for `path` relative to `root`, with `/` separators as zip
+/// requires.
+pub fn entry_name(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
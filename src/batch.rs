@@ -0,0 +1,906 @@
+use anyhow::{Context, Result, anyhow};
+use clap::ValueEnum;
+use image::DynamicImage;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::detection::{DetectionTimeout, DetectorConfig, FaceDetector};
+use crate::filename::{self, Numbering};
+use crate::interrupt;
+use crate::manifest::{ManifestRecord, ManifestWriter};
+use crate::prefetch::{self, Prefetched};
+use crate::report::{self, HtmlReport, ReportEntry};
+use crate::stats::{self, RunStats, Status};
+use crate::{
+    Args, ImageOutcome, RunContext, archive, create_detector, crop_options, crop_suffix,
+    expand_output, face_output_path, face_region_path, generate_cropped_filename,
+    generate_default_output_path, has_output_tokens, input_root, is_image_extension, is_up_to_date,
+    measure_crops, metrics, numbered, open_image, process_image, same_path, say, sidecar,
+    sized_path, summary, template, video, with_mask_format,
+};
+
+/// What each parallel task covers when `--jobs` is greater than one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ParallelGranularity {
+    /// Every image is its own task (best balance for flat or uneven trees)
+    #[default]
+    File,
+    /// Each top-level subdirectory is one task, processed sequentially by a
+    /// single worker (fewer, larger tasks; keeps each worker on one directory)
+    Directory,
+}
+
+/// How recursive runs lay out files in the output directory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputNaming {
+    /// Recreate the input's subdirectories
+    #[default]
+    Mirror,
+    /// Write everything into the output directory itself, prefixing each
+    /// name with its subdirectory path (`a/b/img.jpg` -> `a__b__img_cropped.jpg`)
+    ParentPrefixed,
+}
+
+/// Joins subdirectory names in `OutputNaming::ParentPrefixed` file names.
+const PREFIX_SEPARATOR: &str = "__";
+
+/// Per-image notifications from `process_directory`, so a front-end can show
+/// its own progress instead of parsing stdout.
+pub enum ProgressEvent<'a> {
+    /// `path` is about to be processed, out of `total` images in the run.
+    Started { path: &'a Path, total: usize },
+    /// `path` produced its crop (or placeholder).
+    Completed {
+        path: &'a Path,
+        outcome: &'a ImageOutcome,
+    },
+    /// `path` was rejected or failed; it has no crop.
+    Failed {
+        path: &'a Path,
+        error: &'a anyhow::Error,
+    },
+}
+
+/// Callback for `ProgressEvent`s. Called from worker threads with `--jobs`,
+/// one event at a time.
+pub type OnProgress<'a> = &'a (dyn Fn(ProgressEvent) + Sync);
+
+/// Returned by `process_entry` to stop the run once --limit is reached or
+/// Ctrl-C was pressed with --interrupt-safe.
+#[derive(Debug)]
+struct Stopped;
+
+impl fmt::Display for Stopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "run stopped early")
+    }
+}
+
+impl std::error::Error for Stopped {}
+
+/// Everything collected during a directory run. Shared behind a mutex when
+/// images are processed in parallel.
+struct Tally {
+    stats: RunStats,
+    manifest: Option<ManifestWriter>,
+    report: Option<HtmlReport>,
+    timed_out: Vec<PathBuf>,
+    /// Inputs skipped by --skip-on-existing-sidecar.
+    up_to_date: usize,
+    /// Images started and images cropped, counted for --limit.
+    attempts: u64,
+    successes: u64,
+}
+
+/// How long a worker waits for images in progress to free a --limit slot.
+const SLOT_POLL: Duration = Duration::from_millis(10);
+
+/// The --limit slots of a directory run. A worker takes one before
+/// processing an image, so parallel workers never start more images than
+/// could still count; an image that turns out not to count gives its slot
+/// back for the next one.
+struct Limit {
+    limit: Option<u64>,
+    /// Slots held by images in progress or counted.
+    taken: AtomicU64,
+    /// Slots of images that counted towards the limit.
+    counted: AtomicU64,
+}
+
+impl Limit {
+    fn new(limit: Option<u64>) -> Self {
+        Limit {
+            limit,
+            taken: AtomicU64::new(0),
+            counted: AtomicU64::new(0),
+        }
+    }
+
+    /// Take a slot, waiting while all of them are held by images in
+    /// progress. `false` once the limit is reached (or Ctrl-C was pressed
+    /// with --interrupt-safe).
+    fn take(&self) -> bool {
+        let Some(limit) = self.limit else {
+            return !interrupt::requested();
+        };
+        loop {
+            if interrupt::requested() || self.counted.load(Ordering::SeqCst) >= limit {
+                return false;
+            }
+            let free = self
+                .taken
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |taken| {
+                    (taken < limit).then_some(taken + 1)
+                });
+            if free.is_ok() {
+                return true;
+            }
+            thread::sleep(SLOT_POLL);
+        }
+    }
+
+    /// Settle a slot taken by `take`: keep it if its image `counts`,
+    /// otherwise free it.
+    fn settle(&self, counts: bool) {
+        if self.limit.is_none() {
+            return;
+        }
+        if counts {
+            self.counted.fetch_add(1, Ordering::SeqCst);
+        } else {
+            self.taken.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Tally {
+    /// Count and log the result of `path`, whose crop is at `output` and
+    /// whose --html-report picture is `thumbnail`.
+    fn record(
+        &mut self,
+        args: &Args,
+        on_progress: OnProgress,
+        (path, output): (&Path, Option<PathBuf>),
+        thumbnail: Option<String>,
+        result: Result<ImageOutcome>,
+        duration: Duration,
+    ) -> Result<()> {
+        self.stats.record(&result);
+
+        let status = Status::of(&result);
+        if matches!(status, Status::Ok | Status::Placeholder) {
+            self.successes += 1;
+        }
+        let detail = match &result {
+            Ok(outcome) => outcome.warning.clone().unwrap_or_default(),
+            Err(e) => e.to_string(),
+        };
+
+        if let Some(report) = &mut self.report {
+            report.add(ReportEntry {
+                input: display_name(args, path).to_path_buf(),
+                status,
+                score: result.as_ref().ok().and_then(|outcome| outcome.score),
+                detail: detail.clone(),
+                thumbnail,
+            });
+        }
+
+        if let Some(manifest) = &mut self.manifest {
+            manifest.write(&ManifestRecord {
+                input: path.to_path_buf(),
+                output,
+                status,
+                faces: stats::faces_found(&result),
+                duration,
+                detail,
+                blurhash: result
+                    .as_ref()
+                    .ok()
+                    .and_then(|outcome| outcome.blurhash.clone()),
+                quality: result.as_ref().ok().and_then(|outcome| outcome.quality),
+            })?;
+        }
+
+        match &result {
+            Ok(outcome) => on_progress(ProgressEvent::Completed { path, outcome }),
+            Err(error) => {
+                if error.is::<DetectionTimeout>() {
+                    self.timed_out.push(path.to_path_buf());
+                }
+                on_progress(ProgressEvent::Failed { path, error })
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Crop every image in the input directory, reporting each one to
+/// `on_progress`.
+pub fn process_directory(
+    args: &Args,
+    finder: &mut dyn FaceDetector,
+    config: &DetectorConfig,
+    ctx: &RunContext,
+    on_progress: OnProgress,
+) -> Result<()> {
+    let started = Instant::now();
+
+    let (images, others) = select_images(args)?;
+
+    let manifest = args
+        .manifest
+        .as_deref()
+        .map(|path| ManifestWriter::create(path, args.manifest_format))
+        .transpose()?;
+
+    // If output dir is specified, create it if it doesn't exist. Templated
+    // ones are created per image, once their dates are known.
+    if let Some(out_dir) = args
+        .output
+        .as_ref()
+        .filter(|_| !args.prints_only() && !has_output_tokens(args))
+    {
+        fs::create_dir_all(out_dir).context("Failed to create output directory")?;
+    }
+
+    // Pass-through copies only make sense into a separate directory.
+    let copy_dir = match &args.output {
+        Some(out_dir) if args.copy_unprocessed && !same_path(args.input(), out_dir) => {
+            Some(out_dir)
+        }
+        _ => None,
+    };
+
+    if let Some(copy_dir) = copy_dir {
+        for path in &others {
+            copy_unprocessed(args, ctx, path, copy_dir);
+        }
+    }
+
+    if args.resize_crops_to_median {
+        say(
+            args,
+            format!("Measuring crops of {} image(s)...", images.len()),
+        );
+        match median_crop_size(args, &images, finder, config)? {
+            Some((width, height)) => {
+                say(
+                    args,
+                    format!("Resizing crops to the median size {}x{}.", width, height),
+                );
+                ctx.crop_size.set((width, height)).unwrap();
+            }
+            None => say(args, "No crops to measure; sizes are left as they are."),
+        }
+    }
+
+    let tally = Mutex::new(Tally {
+        stats: RunStats::default(),
+        manifest,
+        report: args.html_report.as_deref().map(|path| {
+            HtmlReport::new(path, args.html_report_thumb_size, args.html_report_columns)
+        }),
+        timed_out: Vec::new(),
+        up_to_date: 0,
+        attempts: 0,
+        successes: 0,
+    });
+    let limit = Limit::new(args.limit);
+
+    let stopped = if args.jobs <= 1 {
+        let mut run = |path: &Path, prefetched| {
+            process_entry(
+                args,
+                (path, prefetched),
+                images.len(),
+                finder,
+                ctx,
+                (&tally, &limit),
+                on_progress,
+            )
+        };
+        let finished = match args.prefetch {
+            Some(depth) => prefetch::for_each_decoded(
+                &images,
+                depth as usize,
+                args.retry_decode_with_fallback_backend,
+                run,
+            ),
+            None => images.iter().try_for_each(|path| run(path, None)),
+        };
+        until_stopped(finished)?
+    } else {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(args.jobs)
+            .build()
+            .context("Failed to start worker threads")?;
+
+        // Each worker builds its own detector: rustface detectors are not
+        // thread-safe, and construction is cheap next to a batch.
+        let new_finder = || create_detector(args, config);
+        let run = |finder: &mut Result<Box<dyn FaceDetector>>, path: &PathBuf| -> Result<()> {
+            let finder = finder.as_mut().map_err(|e| anyhow!("{:#}", e))?;
+            process_entry(
+                args,
+                (path, None),
+                images.len(),
+                finder.as_mut(),
+                ctx,
+                (&tally, &limit),
+                on_progress,
+            )
+        };
+
+        let finished = pool.install(|| match args.parallel_granularity {
+            ParallelGranularity::File => images.par_iter().try_for_each_init(new_finder, run),
+            ParallelGranularity::Directory => group_by_top_directory(args.input(), &images)
+                .par_iter()
+                .try_for_each_init(new_finder, |finder, group| {
+                    group.iter().try_for_each(|path| run(finder, path))
+                }),
+        });
+        until_stopped(finished)?
+    };
+
+    let mut tally = tally.into_inner().unwrap();
+    tally.stats.duration = started.elapsed();
+    tally.stats.non_images = others.len() as u64;
+
+    if stopped {
+        let message = if interrupt::requested() {
+            let not_started = images.len() - tally.attempts as usize - tally.up_to_date;
+            format!(
+                "Interrupted: stopped after {} image(s), {} cropped; {} not started.",
+                tally.attempts, tally.successes, not_started
+            )
+        } else if args.limit_attempts {
+            format!("Limit reached: stopped after {} image(s).", tally.attempts)
+        } else {
+            format!(
+                "Limit reached: stopped after {} successful crop(s).",
+                tally.successes
+            )
+        };
+        say(args, message);
+    }
+
+    if !tally.timed_out.is_empty() {
+        tally.timed_out.sort();
+        eprintln!(
+            "Timed out ({}), retry these manually:",
+            tally.timed_out.len()
+        );
+        for path in &tally.timed_out {
+            eprintln!("  {}", path.display());
+        }
+    }
+
+    if tally.up_to_date > 0 {
+        say(
+            args,
+            format!(
+                "Skipped {} image(s) with an existing sidecar.",
+                tally.up_to_date
+            ),
+        );
+    }
+
+    if tally.stats.duplicates > 0 {
+        say(
+            args,
+            format!("Skipped {} duplicate image(s).", tally.stats.duplicates),
+        );
+    }
+
+    if args.warn_skipped && tally.stats.non_images > 0 {
+        say(
+            args,
+            format!(
+                "Skipped {} file(s) that are not images.",
+                tally.stats.non_images
+            ),
+        );
+    }
+
+    if tally.stats.duplicate_crops > 0 {
+        say(
+            args,
+            format!("Skipped {} duplicate crop(s).", tally.stats.duplicate_crops),
+        );
+    }
+
+    if let Some(report) = tally.report.take() {
+        report.finish()?;
+    }
+
+    if let Some(metrics_path) = &args.metrics_file {
+        metrics::write_metrics_file(metrics_path, &tally.stats)?;
+    }
+
+    if args.json_summary || args.summary_file.is_some() {
+        let mut parameters = config.describe();
+        parameters.extend(crop_options(args).describe());
+        let json = summary::render(&tally.stats, tally.up_to_date, parameters);
+
+        match &args.summary_file {
+            Some(path) => fs::write(path, json + "\n").context("Failed to write summary file")?,
+            None => println!("{}", json),
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the files a run with `args` would write, as a tree, for --plan.
+/// Only their names are worked out: no image is opened and nothing is
+/// created.
+pub fn print_plan(args: &Args, ctx: &RunContext) -> Result<()> {
+    let (images, _) = select_images(args)?;
+
+    let mut planned = Vec::new();
+    let mut up_to_date = 0;
+    for path in &images {
+        let output_path = output_path_for(args, ctx, path)?;
+        if is_up_to_date(args, &output_path) {
+            up_to_date += 1;
+            continue;
+        }
+        // Numbered as if every image will be cropped.
+        let output_path = numbered(output_path, ctx);
+        planned.extend(planned_files(args, &output_path));
+    }
+
+    // Crops collected into an archive or bucket are listed by entry name.
+    let container = match (&args.output_zip, &args.output_tar, &ctx.s3) {
+        (Some(zip), _, _) => Some(format!("{}:", zip.display())),
+        (None, Some(tar), _) => Some(format!("{}:", tar.display())),
+        (None, None, Some(_)) => Some("S3 bucket:".to_string()),
+        (None, None, None) => None,
+    };
+    if container.is_some() {
+        for path in &mut planned {
+            *path = PathBuf::from(archive::entry_name(input_root(args), path));
+        }
+    }
+    planned.sort();
+
+    println!(
+        "{} image(s), {} file(s) planned",
+        images.len() - up_to_date,
+        planned.len()
+    );
+    if up_to_date > 0 {
+        println!("{} image(s) skipped with an existing sidecar", up_to_date);
+    }
+    if let Some(container) = container {
+        println!("{}", container);
+    }
+    for line in tree_lines(&planned) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// Every file written for the crop at `output_path`: the crop, or one per
+/// face up to --largest-n, each with its --face-region-export box, --sizes
+/// variants and sidecar.
+fn planned_files(args: &Args, output_path: &Path) -> Vec<PathBuf> {
+    let crops: Vec<PathBuf> = match args.largest_n {
+        Some(n) => (1..=n as usize)
+            .map(|rank| face_output_path(output_path, rank, args))
+            .collect(),
+        None => vec![output_path.to_path_buf()],
+    };
+
+    let mut files = Vec::new();
+    for crop in crops {
+        if args.face_region_export {
+            files.push(face_region_path(&crop, args));
+        }
+        files.extend(args.sizes.iter().map(|&size| sized_path(&crop, size)));
+        if args.sidecar {
+            files.push(sidecar::path_for(&crop));
+        }
+        files.push(crop);
+    }
+    files
+}
+
+/// `paths`, sorted, drawn as an indented tree below the directory they all
+/// share. A path listed more than once, which a later image would
+/// overwrite, is marked with its count.
+fn tree_lines(paths: &[PathBuf]) -> Vec<String> {
+    let Some(first) = paths.first() else {
+        return Vec::new();
+    };
+    let mut root = first.parent().unwrap_or(Path::new("")).to_path_buf();
+    while !paths.iter().all(|path| path.starts_with(&root)) {
+        if !root.pop() {
+            break;
+        }
+    }
+
+    let mut lines = Vec::new();
+    if !root.as_os_str().is_empty() {
+        lines.push(format!("{}/", root.display()));
+    }
+    let base = lines.len();
+    let indent = |depth: usize| "  ".repeat(base + depth);
+    let mut open: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < paths.len() {
+        let path = &paths[i];
+        let copies = paths[i..].iter().take_while(|other| *other == path).count();
+        i += copies;
+
+        let parts: Vec<String> = path
+            .strip_prefix(&root)
+            .unwrap_or(path)
+            .iter()
+            .map(|part| part.to_string_lossy().into_owned())
+            .collect();
+        let Some((name, dirs)) = parts.split_last() else {
+            continue;
+        };
+        let shared = open.iter().zip(dirs).take_while(|(a, b)| a == b).count();
+        open.truncate(shared);
+        for dir in &dirs[shared..] {
+            lines.push(format!("{}{}/", indent(open.len()), dir));
+            open.push(dir.clone());
+        }
+        let mark = if copies > 1 {
+            format!("  (written {} times)", copies)
+        } else {
+            String::new()
+        };
+        lines.push(format!("{}{}{}", indent(open.len()), name, mark));
+    }
+    lines
+}
+
+/// The images a directory run covers, after --sample, and the other files
+/// it leaves out (reported with --warn-skipped).
+fn select_images(args: &Args) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let (mut images, others): (Vec<PathBuf>, Vec<PathBuf>) =
+        list_files(args.input(), args.recursive)?
+            .into_iter()
+            .partition(|p| is_image_extension(p));
+    if args.warn_skipped {
+        for path in &others {
+            let reason = match path.extension() {
+                Some(ext) => format!("unrecognized extension .{}", ext.to_string_lossy()),
+                None => "no extension".to_string(),
+            };
+            eprintln!(
+                "Skipping {:?}: not an image ({})",
+                display_name(args, path),
+                reason
+            );
+        }
+    }
+
+    if let Some(n) = args.sample {
+        let seed = args.seed.unwrap_or_else(time_seed);
+        let total = images.len();
+        images = sample(images, n as usize, seed);
+        let mut report = format!(
+            "Sampled {} of {} image(s) (--seed {}):",
+            images.len(),
+            total,
+            seed
+        );
+        for path in &images {
+            report.push_str(&format!("\n  {}", display_name(args, path).display()));
+        }
+        // Keep stdout for the crops with --coords-only and --data-uri.
+        say(args, report);
+    }
+
+    Ok((images, others))
+}
+
+/// `finished` with an early stop by --limit or Ctrl-C counted as success:
+/// whether the run stopped there.
+fn until_stopped(finished: Result<()>) -> Result<bool> {
+    match finished {
+        Ok(()) => Ok(false),
+        Err(e) if e.is::<Stopped>() => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+/// First pass of --resize-crops-to-median: the median width and height of
+/// the crops the run would produce, or `None` if no image yields one.
+/// Images that fail are left out here and reported by the second pass.
+fn median_crop_size(
+    args: &Args,
+    images: &[PathBuf],
+    finder: &mut dyn FaceDetector,
+    config: &DetectorConfig,
+) -> Result<Option<(u32, u32)>> {
+    let sizes: Vec<(u32, u32)> = if args.jobs <= 1 {
+        images
+            .iter()
+            .flat_map(|path| measure_crops(path, finder, args).unwrap_or_default())
+            .collect()
+    } else {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(args.jobs)
+            .build()
+            .context("Failed to start worker threads")?;
+        pool.install(|| {
+            images
+                .par_iter()
+                .map_init(
+                    || create_detector(args, config),
+                    |finder, path| match finder {
+                        Ok(finder) => {
+                            measure_crops(path, finder.as_mut(), args).unwrap_or_default()
+                        }
+                        Err(_) => Vec::new(),
+                    },
+                )
+                .flatten()
+                .collect()
+        })
+    };
+
+    if sizes.is_empty() {
+        return Ok(None);
+    }
+    let mut widths: Vec<u32> = sizes.iter().map(|&(w, _)| w).collect();
+    let mut heights: Vec<u32> = sizes.iter().map(|&(_, h)| h).collect();
+    widths.sort_unstable();
+    heights.sort_unstable();
+    Ok(Some((widths[widths.len() / 2], heights[heights.len() / 2])))
+}
+
+/// Process the image at `path`, decoded already if `prefetched` holds it,
+/// and record the result.
+fn process_entry(
+    args: &Args,
+    (path, prefetched): (&Path, Prefetched),
+    total: usize,
+    finder: &mut dyn FaceDetector,
+    ctx: &RunContext,
+    (tally, limit): (&Mutex<Tally>, &Limit),
+    on_progress: OnProgress,
+) -> Result<()> {
+    if !limit.take() {
+        return Err(Stopped.into());
+    }
+    let image_started = Instant::now();
+    let (output_path, result) = match output_path_for(args, ctx, path) {
+        Ok(output_path) if is_up_to_date(args, &output_path) => {
+            limit.settle(false);
+            tally.lock().unwrap().up_to_date += 1;
+            return Ok(());
+        }
+        Ok(output_path) => {
+            tally.lock().unwrap().attempts += 1;
+            on_progress(ProgressEvent::Started { path, total });
+            let result = process_image(path, output_path.clone(), finder, args, ctx, prefetched);
+            limit.settle(args.limit_attempts || result.is_ok());
+            (Some(output_path), result)
+        }
+        Err(e) => {
+            limit.settle(false);
+            (None, Err(e))
+        }
+    };
+    let image_duration = image_started.elapsed();
+
+    let output = result
+        .as_ref()
+        .ok()
+        .and_then(|outcome| outcome.indexed_output.clone())
+        .or(output_path)
+        .filter(|_| result.is_ok() && !args.prints_only());
+    // Built before taking the lock, which the other workers wait on.
+    let thumbnail = args.html_report.as_ref().and_then(|_| {
+        report_picture(args, path, output.as_deref(), &result)
+            .and_then(|img| report::thumbnail(&img, args.html_report_thumb_size))
+    });
+
+    tally.lock().unwrap().record(
+        args,
+        on_progress,
+        (path, output),
+        thumbnail,
+        result,
+        image_duration,
+    )
+}
+
+/// The picture --html-report shows for `path`: its crop at `output`, read
+/// back from disk, or the source itself for failures.
+fn report_picture(
+    args: &Args,
+    path: &Path,
+    output: Option<&Path>,
+    result: &Result<ImageOutcome>,
+) -> Option<DynamicImage> {
+    match output {
+        Some(output) => image::open(output).ok(),
+        None if result.is_err() && !video::is_video(path) => {
+            open_image(path, args.retry_decode_with_fallback_backend).ok()
+        }
+        None => None,
+    }
+}
+
+/// Where the crop of `path` goes. With an output directory the input's
+/// subdirectory structure is mirrored inside it (creating directories as
+/// needed); otherwise the crop is written next to its source.
+fn output_path_for(args: &Args, ctx: &RunContext, path: &Path) -> Result<PathBuf> {
+    let output_path = match (expand_output(args, ctx, path), &ctx.numbering) {
+        // If no output dir: input_dir / filename_cropped.ext
+        (None, _) => generate_default_output_path(path, args)?,
+        // Numbered: out_dir / 000001.ext, whatever the input layout, once
+        // process_image knows the image is cropped.
+        (Some(out_dir), Some(_)) => out_dir.join(Numbering::pending(path)),
+        // If output dir specified: out_dir / relative_dir / filename_cropped.ext
+        (Some(out_dir), None) => {
+            let file_name =
+                generate_cropped_filename(path, args.normalize_filename, crop_suffix(args))?;
+            let (dest_dir, file_name) = destination(args, &out_dir, path, file_name);
+            if !args.prints_only() && !args.plan && (dest_dir != out_dir || has_output_tokens(args))
+            {
+                fs::create_dir_all(&dest_dir).context("Failed to create output directory")?;
+            }
+            dest_dir.join(file_name)
+        }
+    };
+
+    let output_path = with_mask_format(output_path, args, ctx);
+    Ok(match (&ctx.names, &ctx.numbering) {
+        (Some(names), None) => names.claim(output_path, path),
+        _ => output_path,
+    })
+}
+
+/// `n` of `images` picked at random from `seed`, kept in their sorted order.
+/// The same seed and file list always give the same sample.
+fn sample(mut images: Vec<PathBuf>, n: usize, seed: u64) -> Vec<PathBuf> {
+    let n = n.min(images.len());
+    let mut state = seed;
+    // Partial Fisher-Yates shuffle: the first `n` slots end up a uniform
+    // random sample.
+    for i in 0..n {
+        let j = i + (splitmix64(&mut state) % (images.len() - i) as u64) as usize;
+        images.swap(i, j);
+    }
+    images.truncate(n);
+    images.sort();
+    images
+}
+
+/// SplitMix64, a small fast generator; plenty for picking a sample.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// A seed for --sample without --seed, from the clock.
+fn time_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+/// Directory and file name under `out_dir` for `file_name`, derived from the
+/// input `path`, following --output-naming.
+fn destination(args: &Args, out_dir: &Path, path: &Path, file_name: PathBuf) -> (PathBuf, PathBuf) {
+    let Some(rel) = relative_parent(args.input(), path) else {
+        return (out_dir.to_path_buf(), file_name);
+    };
+
+    match args.output_naming {
+        OutputNaming::Mirror => (out_dir.join(rel), file_name),
+        OutputNaming::ParentPrefixed => {
+            let mut prefixed = rel
+                .components()
+                .map(|c| {
+                    if args.normalize_filename {
+                        filename::normalize(c.as_os_str()).into()
+                    } else {
+                        c.as_os_str().to_string_lossy()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(PREFIX_SEPARATOR);
+            prefixed.push_str(PREFIX_SEPARATOR);
+            prefixed.push_str(&file_name.to_string_lossy());
+            (out_dir.to_path_buf(), PathBuf::from(prefixed))
+        }
+    }
+}
+
+fn copy_unprocessed(args: &Args, ctx: &RunContext, path: &Path, copy_dir: &Path) {
+    let date = template::date_for(args.output_date, path, &ctx.started);
+    let copy_dir = template::expand(copy_dir, &date);
+    let (dest_dir, file_name) =
+        destination(args, &copy_dir, path, path.file_name().unwrap().into());
+    let dest = dest_dir.join(file_name);
+
+    // The expanded template can land back on the input directory.
+    if same_path(path, &dest) {
+        eprintln!(
+            "Skipping copy of {:?}: it would overwrite itself",
+            display_name(args, path)
+        );
+        return;
+    }
+
+    match fs::create_dir_all(&dest_dir).and_then(|_| fs::copy(path, &dest)) {
+        Ok(_) => say(args, format!("Copied: {:?}", display_name(args, path))),
+        Err(e) => eprintln!("Failed to copy {:?}: {}", display_name(args, path), e),
+    }
+}
+
+/// The directory of `path` relative to `root`, or `None` if it sits directly
+/// in `root`.
+fn relative_parent<'a>(root: &Path, path: &'a Path) -> Option<&'a Path> {
+    path.strip_prefix(root)
+        .ok()
+        .and_then(Path::parent)
+        .filter(|rel| !rel.as_os_str().is_empty())
+}
+
+/// Name used in progress messages: the file name, or the path relative to the
+/// input directory when walking recursively.
+pub fn display_name<'a>(args: &Args, path: &'a Path) -> &'a Path {
+    if args.recursive {
+        path.strip_prefix(args.input()).unwrap_or(path)
+    } else {
+        Path::new(path.file_name().unwrap())
+    }
+}
+
+/// All regular files under `root`, sorted so runs are reproducible.
+fn list_files(root: &Path, recursive: bool) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let entries = fs::read_dir(&dir).context("Failed to read input directory")?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_file() {
+                files.push(path);
+            } else if recursive && path.is_dir() {
+                pending.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Split `images` into one group per top-level subdirectory of `root`. Files
+/// directly inside `root` form a group of their own.
+fn group_by_top_directory(root: &Path, images: &[PathBuf]) -> Vec<Vec<PathBuf>> {
+    let mut groups: BTreeMap<Option<PathBuf>, Vec<PathBuf>> = BTreeMap::new();
+
+    for path in images {
+        let top = relative_parent(root, path)
+            .and_then(|rel| rel.components().next())
+            .map(|c| PathBuf::from(c.as_os_str()));
+        groups.entry(top).or_default().push(path.clone());
+    }
+
+    groups.into_values().collect()
+}
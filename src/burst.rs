@@ -0,0 +1,71 @@
+use image::{DynamicImage, GenericImageView};
+use image_hasher::{HasherConfig, ImageHash};
+use rustface::Rectangle;
+use std::sync::Mutex;
+
+use crate::geometry::{box_around, center_of};
+
+/// Steadies crop placement over bursts of near-identical shots for
+/// `--burst-smoothing`.
+///
+/// An image whose perceptual hash is within `max_distance` of the previous
+/// image's (and which has the same dimensions) continues that image's
+/// burst; anything else starts a new one. Each crop is centered on the mean
+/// face center of its burst so far, so the subject stays put from shot to
+/// shot while the crop size still follows the face.
+pub struct BurstSmoother {
+    max_distance: u32,
+    current: Mutex<Option<Burst>>,
+}
+
+struct Burst {
+    /// Hash and size of the latest image, which the next one is compared to.
+    hash: ImageHash,
+    dimensions: (u32, u32),
+    center_sum: (f64, f64),
+    count: u32,
+}
+
+impl BurstSmoother {
+    pub fn new(max_distance: u32) -> Self {
+        BurstSmoother {
+            max_distance,
+            current: Mutex::new(None),
+        }
+    }
+
+    /// `bbox`, a face in `img`, moved onto the mean center of the burst
+    /// `img` belongs to, with that burst's length so far (1 for the first
+    /// shot of a burst).
+    pub fn place(&self, img: &DynamicImage, bbox: &Rectangle) -> (Rectangle, u32) {
+        let hash = HasherConfig::new().to_hasher().hash_image(img);
+        let dimensions = img.dimensions();
+        let center = center_of(bbox);
+
+        let mut current = self.current.lock().unwrap();
+        let burst = match current.as_mut() {
+            Some(burst)
+                if burst.dimensions == dimensions
+                    && burst.hash.dist(&hash) <= self.max_distance =>
+            {
+                burst.hash = hash;
+                burst.center_sum.0 += center.0;
+                burst.center_sum.1 += center.1;
+                burst.count += 1;
+                burst
+            }
+            _ => current.insert(Burst {
+                hash,
+                dimensions,
+                center_sum: center,
+                count: 1,
+            }),
+        };
+
+        let mean = (
+            burst.center_sum.0 / burst.count as f64,
+            burst.center_sum.1 / burst.count as f64,
+        );
+        (box_around(mean, bbox.width(), bbox.height()), burst.count)
+    }
+}
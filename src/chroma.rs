@@ -0,0 +1,145 @@
+use image::{DynamicImage, GenericImageView, Rgba};
+
+/// Largest `--chroma-tolerance`: the distance between black and white.
+pub const MAX_TOLERANCE: f32 = 441.7;
+
+/// Background keying for `--chroma-key`.
+///
+/// Pixels within `tolerance` (Euclidean RGB distance, 0-255 per channel) of
+/// `key` are background. Up to half as far again they are edge pixels,
+/// partly keyed and despilled; beyond that they are kept as they are.
+pub struct ChromaKey {
+    pub key: [u8; 3],
+    pub tolerance: f32,
+    /// Solid color to put behind the subject; `None` for transparency.
+    pub replacement: Option<[u8; 3]>,
+}
+
+/// Pixel rectangle `(x, y, width, height)` that is never keyed.
+pub type Keep = (u32, u32, u32, u32);
+
+impl ChromaKey {
+    /// `img` with its background keyed out, except inside `keep`. The
+    /// result is RGBA for transparency or RGB with a replacement color, at
+    /// the source's bit depth.
+    pub fn apply(&self, img: &DynamicImage, keep: Keep) -> DynamicImage {
+        let key = self.key.map(|c| c as f32 / 255.0);
+        let tolerance = self.tolerance / 255.0;
+        let softness = tolerance / 2.0;
+        let (kx, ky, kw, kh) = keep;
+        let dominant = dominant_channel(self.key);
+
+        let mut keyed = img.to_rgba32f();
+        for (x, y, pixel) in keyed.enumerate_pixels_mut() {
+            if (kx..kx + kw).contains(&x) && (ky..ky + kh).contains(&y) {
+                continue;
+            }
+            let Rgba([r, g, b, a]) = *pixel;
+            let distance =
+                ((r - key[0]).powi(2) + (g - key[1]).powi(2) + (b - key[2]).powi(2)).sqrt();
+            if distance >= tolerance + softness {
+                continue;
+            }
+            let opacity = if softness > 0.0 {
+                ((distance - tolerance) / softness).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            if let Some(c) = dominant.filter(|_| opacity > 0.0) {
+                // Despill: the key color's strongest channel may not exceed
+                // the larger of the other two on the subject's edge.
+                let others = (0..3)
+                    .filter(|&i| i != c)
+                    .map(|i| pixel.0[i])
+                    .fold(0.0, f32::max);
+                pixel.0[c] = pixel.0[c].min(others);
+            }
+            pixel.0[3] = a * opacity;
+        }
+
+        let sixteen = img.color().bytes_per_pixel() > img.color().channel_count();
+        match self.replacement {
+            None => {
+                let keyed = DynamicImage::ImageRgba32F(keyed);
+                if sixteen {
+                    DynamicImage::ImageRgba16(keyed.to_rgba16())
+                } else {
+                    DynamicImage::ImageRgba8(keyed.to_rgba8())
+                }
+            }
+            Some(color) => {
+                let background = color.map(|c| c as f32 / 255.0);
+                for pixel in keyed.pixels_mut() {
+                    let a = pixel.0[3];
+                    for (channel, back) in pixel.0.iter_mut().zip(background) {
+                        *channel = *channel * a + back * (1.0 - a);
+                    }
+                    pixel.0[3] = 1.0;
+                }
+                let keyed = DynamicImage::ImageRgba32F(keyed);
+                if sixteen {
+                    DynamicImage::ImageRgb16(keyed.to_rgb16())
+                } else {
+                    DynamicImage::ImageRgb8(keyed.to_rgb8())
+                }
+            }
+        }
+    }
+}
+
+/// `keep` clipped to `img`, for callers with a rectangle that may reach
+/// past its edges.
+pub fn clip(img: &DynamicImage, (x, y, width, height): (i64, i64, u32, u32)) -> Keep {
+    let (w, h) = img.dimensions();
+    let x0 = x.clamp(0, w as i64);
+    let y0 = y.clamp(0, h as i64);
+    let x1 = (x + width as i64).clamp(0, w as i64);
+    let y1 = (y + height as i64).clamp(0, h as i64);
+    (x0 as u32, y0 as u32, (x1 - x0) as u32, (y1 - y0) as u32)
+}
+
+/// The channel that stands out in `key` (green in `#00ff00`), which despill
+/// pulls down; `None` for grays, which have no cast to remove.
+fn dominant_channel(key: [u8; 3]) -> Option<usize> {
+    let (index, &max) = key.iter().enumerate().max_by_key(|&(_, c)| *c)?;
+    let runner_up = key
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != index)
+        .map(|(_, &c)| c)
+        .max()?;
+    (max > runner_up).then_some(index)
+}
+
+/// Parse `green`, `blue`, `white`, `black`, or a hex color such as
+/// `#00ff00` / `00ff00`.
+pub fn parse_color(s: &str) -> Result<[u8; 3], String> {
+    match s.to_ascii_lowercase().as_str() {
+        "green" => return Ok([0, 255, 0]),
+        "blue" => return Ok([0, 0, 255]),
+        "white" => return Ok([255, 255, 255]),
+        "black" => return Ok([0, 0, 0]),
+        _ => {}
+    }
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    let channel = |i: usize| {
+        hex.get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+    };
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok([r, g, b]),
+        _ => Err(format!(
+            "`{}` is not a color (use green, blue, white, black or #rrggbb)",
+            s
+        )),
+    }
+}
+
+pub fn parse_tolerance(s: &str) -> Result<f32, String> {
+    let value: f32 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+    if (0.0..=MAX_TOLERANCE).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("{} is not between 0 and {}", value, MAX_TOLERANCE))
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::{Context, Result};
+use image::DynamicImage;
+use image_hasher::{HasherConfig, ImageHash};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Directory for crops that look like no other crop of the run.
+const UNCLUSTERED: &str = "unclustered";
+
+/// Groups the crops of a run by perceptual hash for
+/// `--cluster-by-similarity`, then moves each group into its own directory.
+///
+/// This is a rough visual grouping, not face recognition: crops of
+/// different people with similar lighting and pose can share a cluster,
+/// and one person in different shots can be split up.
+pub struct Clusterer {
+    max_distance: u32,
+    crops: Mutex<Vec<(PathBuf, ImageHash)>>,
+}
+
+/// How `Clusterer::finish` sorted the crops.
+pub struct Clustering {
+    pub clusters: usize,
+    pub clustered: usize,
+    pub unclustered: usize,
+}
+
+impl Clusterer {
+    pub fn new(max_distance: u32) -> Self {
+        Clusterer {
+            max_distance,
+            crops: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Remember the crop `img`, saved as `output`.
+    pub fn add(&self, img: &DynamicImage, output: &Path) {
+        let hash = HasherConfig::new().to_hasher().hash_image(img);
+        self.crops
+            .lock()
+            .unwrap()
+            .push((output.to_path_buf(), hash));
+    }
+
+    /// Move every crop added into `cluster_N/` under `dir`, largest cluster
+    /// first, or into `unclustered/` if it matched no other crop.
+    ///
+    /// Crops are taken in path order, whatever order workers saved them in.
+    /// Each one joins the first cluster whose first crop is within the
+    /// Hamming distance threshold, so a cluster cannot drift away from where
+    /// it started the way chained matches would.
+    pub fn finish(self, dir: &Path) -> Result<Clustering> {
+        let mut crops = self.crops.into_inner().unwrap();
+        crops.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut clusters: Vec<(ImageHash, Vec<PathBuf>)> = Vec::new();
+        for (path, hash) in crops {
+            match clusters
+                .iter_mut()
+                .find(|(first, _)| first.dist(&hash) <= self.max_distance)
+            {
+                Some((_, members)) => members.push(path),
+                None => clusters.push((hash, vec![path])),
+            }
+        }
+        // Stable, so clusters of the same size stay in path order.
+        clusters.sort_by_key(|(_, members)| std::cmp::Reverse(members.len()));
+
+        let mut summary = Clustering {
+            clusters: 0,
+            clustered: 0,
+            unclustered: 0,
+        };
+        for (_, members) in &clusters {
+            let target = if members.len() > 1 {
+                summary.clusters += 1;
+                summary.clustered += members.len();
+                dir.join(format!("cluster_{}", summary.clusters))
+            } else {
+                summary.unclustered += 1;
+                dir.join(UNCLUSTERED)
+            };
+            fs::create_dir_all(&target)
+                .with_context(|| format!("Failed to create cluster directory {:?}", target))?;
+            for path in members {
+                move_crop(path, &target, dir)?;
+            }
+        }
+        Ok(summary)
+    }
+}
+
+/// Move the crop at `path` into `target`, then remove the directories
+/// below `root` it leaves empty.
+fn move_crop(path: &Path, target: &Path, root: &Path) -> Result<()> {
+    let destination = free_name(target, path);
+    fs::rename(path, &destination)
+        .with_context(|| format!("Failed to move {:?} to {:?}", path, destination))?;
+
+    // Fails, as intended, at the first directory with anything left in it.
+    for parent in path.ancestors().skip(1) {
+        if parent == root || !parent.starts_with(root) || fs::remove_dir(parent).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// `path`'s file name in `dir`, with `_2`, `_3`, ... added to the stem if
+/// crops from different subdirectories share it.
+fn free_name(dir: &Path, path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default();
+    let candidate = dir.join(name);
+    if !candidate.exists() {
+        return candidate;
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (2..)
+        .map(|n| dir.join(format!("{}_{}{}", stem, n, extension)))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
@@ -0,0 +1,94 @@
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// Settings read from `--config`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Detector defaults; `--min-face-size` and the other flags, or their
+    /// `FACE_CROPPER_*` environment variables, take precedence.
+    pub min_face_size: Option<u32>,
+    pub score_thresh: Option<f64>,
+    pub pyramid_scale: Option<f32>,
+    pub slide_window_step: Option<(u32, u32)>,
+    /// Detector overrides chosen by image size; see [`SizeBand`].
+    #[serde(default)]
+    pub size_bands: Vec<SizeBand>,
+}
+
+/// Detector settings for images up to a given size.
+///
+/// An image matches a band when its longer side is at most `max_dimension`.
+/// Bands are tried from the smallest `max_dimension` up and the first match
+/// wins; a band without `max_dimension` matches every image and is tried
+/// last. Settings a band leaves out keep the global values.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SizeBand {
+    pub max_dimension: Option<u32>,
+    pub min_face_size: Option<u32>,
+    pub score_thresh: Option<f64>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {:?}", path))?;
+        let mut config: Config = toml::from_str(&text)
+            .with_context(|| format!("Failed to parse config file {:?}", path))?;
+
+        config.validate()?;
+        // `None` (catch-all) sorts after every explicit limit.
+        config
+            .size_bands
+            .sort_by_key(|band| band.max_dimension.unwrap_or(u32::MAX));
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        // The same limits as the flags, which rustface would panic on.
+        if self.min_face_size.is_some_and(|size| size < 20) {
+            bail!("min_face_size must be at least 20");
+        }
+        if self.score_thresh.is_some_and(|thresh| thresh <= 0.0) {
+            bail!("score_thresh must be greater than 0");
+        }
+        if self
+            .pyramid_scale
+            .is_some_and(|scale| !(0.01..=0.99).contains(&scale))
+        {
+            bail!("pyramid_scale must be between 0.01 and 0.99");
+        }
+        if self
+            .slide_window_step
+            .is_some_and(|(x, y)| x == 0 || y == 0)
+        {
+            bail!("slide_window_step must be at least 1 in both directions");
+        }
+
+        for band in &self.size_bands {
+            // rustface panics on these, so reject them up front.
+            if band.min_face_size.is_some_and(|size| size < 20) {
+                bail!("size_bands: min_face_size must be at least 20");
+            }
+            if band.score_thresh.is_some_and(|thresh| thresh <= 0.0) {
+                bail!("size_bands: score_thresh must be greater than 0");
+            }
+        }
+
+        if self
+            .size_bands
+            .iter()
+            .filter(|band| band.max_dimension.is_none())
+            .count()
+            > 1
+        {
+            bail!("size_bands: only one band may omit max_dimension");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::{Context, Result};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GenericImage, GrayImage, Rgb, RgbImage, Rgba};
+use rustface::ImageData;
+use std::fs;
+use std::path::Path;
+
+use crate::detection::DetectorConfig;
+
+/// Side of the detector's sliding window at every pyramid level.
+const WINDOW_SIZE: f32 = 40.0;
+
+/// Final score threshold for `--heatmap`, far below the default 2.0 so weak
+/// candidates show up too (`rustface` rejects 0).
+const HEATMAP_SCORE_THRESH: f64 = 0.01;
+
+/// Side of a `--heatmap` grid cell, in pixels of the detection image.
+const HEATMAP_CELL: u32 = 8;
+
+/// How strongly the hottest `--heatmap` cells cover the image.
+const HEATMAP_OPACITY: f32 = 0.8;
+
+/// Write the image pyramid the detector scans, one PNG per level, as
+/// `<stem>_pyramid_<level>_<scale>.png` in `dir`.
+///
+/// `rustface` keeps its pyramid private, so this reproduces it: levels start
+/// at full size and shrink by `scale_factor` until the shorter side would fall
+/// below the 40px detection window. Faces only become detectable at the level
+/// where they are roughly window-sized.
+pub fn write_pyramid(gray: &GrayImage, scale_factor: f32, dir: &Path, stem: &str) -> Result<()> {
+    fs::create_dir_all(dir).context("Failed to create pyramid directory")?;
+
+    let min_scale = WINDOW_SIZE / gray.width().min(gray.height()) as f32;
+    let mut scale = 1.0_f32;
+    let mut level = 0;
+
+    while scale >= min_scale {
+        let width = ((gray.width() as f32 * scale) as u32).max(1);
+        let height = ((gray.height() as f32 * scale) as u32).max(1);
+
+        let resized = imageops::resize(gray, width, height, FilterType::Triangle);
+        let path = dir.join(format!("{}_pyramid_{:02}_{:.3}.png", stem, level, scale));
+        resized
+            .save(&path)
+            .with_context(|| format!("Failed to write pyramid level {:?}", path))?;
+
+        scale *= scale_factor;
+        level += 1;
+    }
+
+    Ok(())
+}
+
+/// Write `gray`, the image handed to the detector, as `<stem>_detection.png`
+/// in `dir`.
+pub fn write_detection_image(gray: &GrayImage, dir: &Path, stem: &str) -> Result<()> {
+    fs::create_dir_all(dir).context("Failed to create detection image directory")?;
+
+    let path = dir.join(format!("{}_detection.png", stem));
+    gray.save(&path)
+        .with_context(|| format!("Failed to write detection image {:?}", path))
+}
+
+/// Draw the `(x, y, width, height)` box onto `img` for `--debug-outline`: a
+/// `thickness`-pixel frame just inside its edges, clipped to the image.
+pub fn outline(
+    img: &mut DynamicImage,
+    (x, y, width, height): (i64, i64, u32, u32),
+    color: [u8; 3],
+    thickness: u32,
+) {
+    let (img_width, img_height) = (img.width() as i64, img.height() as i64);
+    let (right, bottom) = (x + width as i64, y + height as i64);
+    let band = thickness as i64;
+    let [r, g, b] = color;
+    for py in y.max(0)..bottom.min(img_height) {
+        for px in x.max(0)..right.min(img_width) {
+            let on_edge =
+                px < x + band || px >= right - band || py < y + band || py >= bottom - band;
+            if on_edge {
+                img.put_pixel(px as u32, py as u32, Rgba([r, g, b, 255]));
+            }
+        }
+    }
+}
+
+/// Write a coarse map of where the detector responds in `gray`, as
+/// `<stem>_heatmap.png` in `dir`, for `--heatmap`.
+///
+/// `rustface` exposes no per-window scores, only the candidates that make it
+/// through its cascade. So the detector is run once more with a near-zero
+/// score threshold, and every `HEATMAP_CELL`-pixel cell takes the highest
+/// score of the candidate boxes covering it. The map is drawn over the
+/// dimmed detection image from dark red (weak) to white (the best score in
+/// the image).
+pub fn write_heatmap(
+    config: &DetectorConfig,
+    gray: &GrayImage,
+    dir: &Path,
+    stem: &str,
+) -> Result<()> {
+    fs::create_dir_all(dir).context("Failed to create heatmap directory")?;
+
+    let mut detector = config.build()?;
+    detector.set_score_thresh(HEATMAP_SCORE_THRESH);
+    let candidates = detector.detect(&ImageData::new(gray, gray.width(), gray.height()));
+
+    let columns = gray.width().div_ceil(HEATMAP_CELL);
+    let rows = gray.height().div_ceil(HEATMAP_CELL);
+    let mut cells = vec![0.0_f64; (columns * rows) as usize];
+    for face in &candidates {
+        let b = face.bbox();
+        let x0 = (b.x().max(0) as u32 / HEATMAP_CELL).min(columns);
+        let y0 = (b.y().max(0) as u32 / HEATMAP_CELL).min(rows);
+        let x1 = ((b.x() as i64 + b.width() as i64).max(0) as u32)
+            .div_ceil(HEATMAP_CELL)
+            .min(columns);
+        let y1 = ((b.y() as i64 + b.height() as i64).max(0) as u32)
+            .div_ceil(HEATMAP_CELL)
+            .min(rows);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let cell = &mut cells[(y * columns + x) as usize];
+                *cell = cell.max(face.score());
+            }
+        }
+    }
+
+    let best = cells.iter().copied().fold(0.0, f64::max);
+    let heatmap = RgbImage::from_fn(gray.width(), gray.height(), |x, y| {
+        let base = gray.get_pixel(x, y).0[0] as f32 * 0.5;
+        let score = cells[(y / HEATMAP_CELL * columns + x / HEATMAP_CELL) as usize];
+        if score <= 0.0 {
+            return Rgb([base as u8; 3]);
+        }
+        let t = (score / best) as f32;
+        // Dark red through yellow to white as the score rises.
+        let heat = [
+            (3.0 * t).min(1.0),
+            (3.0 * t - 1.0).clamp(0.0, 1.0),
+            (3.0 * t - 2.0).clamp(0.0, 1.0),
+        ];
+        let alpha = HEATMAP_OPACITY * t.max(0.25);
+        Rgb(heat.map(|h| (base * (1.0 - alpha) + h * 255.0 * alpha).round() as u8))
+    });
+
+    let path = dir.join(format!("{}_heatmap.png", stem));
+    heatmap
+        .save(&path)
+        .with_context(|| format!("Failed to write heatmap {:?}", path))
+}
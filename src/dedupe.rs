@@ -0,0 +1,38 @@
+use image::DynamicImage;
+use image_hasher::{HasherConfig, ImageHash};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Remembers the perceptual hash of every image seen in a run, so visually
+/// duplicate inputs can be skipped.
+pub struct Deduper {
+    max_distance: u32,
+    seen: Mutex<Vec<(ImageHash, PathBuf)>>,
+}
+
+impl Deduper {
+    pub fn new(max_distance: u32) -> Self {
+        Deduper {
+            max_distance,
+            seen: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// If `img` is within the Hamming distance threshold of an image already
+    /// seen, return that image's path. Otherwise remember `img` and return
+    /// `None`.
+    pub fn check(&self, img: &DynamicImage, path: &Path) -> Option<PathBuf> {
+        let hash = HasherConfig::new().to_hasher().hash_image(img);
+
+        let mut seen = self.seen.lock().unwrap();
+        if let Some((_, original)) = seen
+            .iter()
+            .find(|(other, _)| other.dist(&hash) <= self.max_distance)
+        {
+            return Some(original.clone());
+        }
+
+        seen.push((hash, path.to_path_buf()));
+        None
+    }
+}
@@ -0,0 +1,224 @@
+use image::{DynamicImage, GrayImage, ImageBuffer, Pixel};
+
+/// Largest `--deskew-max-angle`.
+pub const MAX_ANGLE: f32 = 20.0;
+
+/// Longer side of the copy the skew is measured on.
+const MEASURE_SIZE: u32 = 800;
+
+/// Fraction of each side searched for the print's edges.
+const BORDER_BAND: f32 = 0.25;
+
+/// Resolution of the angle search, in degrees.
+const ANGLE_STEP: f32 = 0.1;
+
+/// How much better edges must line up at the found angle than unrotated
+/// before the scan is corrected; below this it is taken to be straight.
+const MIN_GAIN: f64 = 1.5;
+
+/// Share of the strongest gradients taken as edge pixels.
+const EDGE_SHARE: f64 = 0.05;
+
+/// Edge pixel position in the measuring copy.
+type Point = (f64, f64);
+
+/// Skew of a scanned print, in degrees clockwise, found from the straight
+/// edges near the scan's borders (the print's own edges, or a frame or
+/// horizon close to them). Only angles up to `max_degrees` either way are
+/// tried. `None` if no angle lines the edges up clearly better than the
+/// scan as it is.
+pub fn skew_angle(img: &DynamicImage, max_degrees: f32) -> Option<f32> {
+    let gray = if img.width().max(img.height()) > MEASURE_SIZE {
+        img.thumbnail(MEASURE_SIZE, MEASURE_SIZE).to_luma8()
+    } else {
+        img.to_luma8()
+    };
+    let (horizontal, vertical) = border_edges(&gray);
+    if horizontal.is_empty() && vertical.is_empty() {
+        return None;
+    }
+
+    let diagonal = (gray.width() as f64).hypot(gray.height() as f64).ceil() as usize;
+    let steps = (max_degrees / ANGLE_STEP).round() as i32;
+    let (best_step, best) = (-steps..=steps)
+        .map(|step| {
+            let angle = step as f32 * ANGLE_STEP;
+            (step, alignment(&horizontal, &vertical, angle, diagonal))
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1).then(b.0.abs().cmp(&a.0.abs())))?;
+    let straight = alignment(&horizontal, &vertical, 0.0, diagonal);
+
+    (best_step != 0 && best >= straight * MIN_GAIN).then_some(best_step as f32 * ANGLE_STEP)
+}
+
+/// Strong edge pixels in the border bands of `gray`: those on roughly
+/// horizontal edges in the top and bottom bands, and those on roughly
+/// vertical edges in the left and right bands.
+fn border_edges(gray: &GrayImage) -> (Vec<Point>, Vec<Point>) {
+    let (width, height) = gray.dimensions();
+    if width < 3 || height < 3 {
+        return (Vec::new(), Vec::new());
+    }
+    let at = |x: u32, y: u32| gray.get_pixel(x, y).0[0] as i32;
+    let mut gradients = Vec::with_capacity(((width - 2) * (height - 2)) as usize);
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let gx = at(x + 1, y - 1) + 2 * at(x + 1, y) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2 * at(x - 1, y)
+                - at(x - 1, y + 1);
+            let gy = at(x - 1, y + 1) + 2 * at(x, y + 1) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2 * at(x, y - 1)
+                - at(x + 1, y - 1);
+            gradients.push((x, y, gx, gy));
+        }
+    }
+
+    let mut magnitudes: Vec<i32> = gradients
+        .iter()
+        .map(|&(_, _, gx, gy)| gx.abs() + gy.abs())
+        .collect();
+    let nth = ((magnitudes.len() as f64 * (1.0 - EDGE_SHARE)) as usize).min(magnitudes.len() - 1);
+    // Flat scans have no edges worth lining up; keep noise out.
+    let threshold = (*magnitudes.select_nth_unstable(nth).1).max(64);
+
+    let band_x = (width as f32 * BORDER_BAND) as u32;
+    let band_y = (height as f32 * BORDER_BAND) as u32;
+    let mut horizontal = Vec::new();
+    let mut vertical = Vec::new();
+    for (x, y, gx, gy) in gradients {
+        if gx.abs() + gy.abs() < threshold {
+            continue;
+        }
+        let point = (x as f64, y as f64);
+        if gy.abs() > 2 * gx.abs() && (y < band_y || y >= height - band_y) {
+            horizontal.push(point);
+        } else if gx.abs() > 2 * gy.abs() && (x < band_x || x >= width - band_x) {
+            vertical.push(point);
+        }
+    }
+    (horizontal, vertical)
+}
+
+/// How well the edge pixels fall on lines skewed by `degrees`: the sum of
+/// squared counts of pixels per line position, which is largest when whole
+/// edges project onto single positions.
+fn alignment(horizontal: &[Point], vertical: &[Point], degrees: f32, diagonal: usize) -> f64 {
+    let (sin, cos) = (degrees as f64).to_radians().sin_cos();
+    let peakiness = |points: &[Point], position: &dyn Fn(f64, f64) -> f64| {
+        let mut bins = vec![0u64; 2 * diagonal + 2];
+        for &(x, y) in points {
+            bins[(position(x, y) + diagonal as f64).round() as usize] += 1;
+        }
+        bins.iter().map(|&n| (n * n) as f64).sum::<f64>()
+    };
+    // A line skewed clockwise by the angle rises by sin for every cos
+    // across: y cos - x sin is constant along it, and x cos + y sin along
+    // its perpendiculars.
+    peakiness(horizontal, &|x, y| y * cos - x * sin)
+        + peakiness(vertical, &|x, y| x * cos + y * sin)
+}
+
+/// `img` rotated counter-clockwise by `degrees` about its center, undoing a
+/// clockwise skew. The canvas keeps its size and bit depth; corners the
+/// rotation uncovers repeat the nearest edge pixel.
+pub fn straighten(img: &DynamicImage, degrees: f32) -> DynamicImage {
+    match img {
+        DynamicImage::ImageLuma8(buffer) => DynamicImage::ImageLuma8(rotate(buffer, degrees)),
+        DynamicImage::ImageLumaA8(buffer) => DynamicImage::ImageLumaA8(rotate(buffer, degrees)),
+        DynamicImage::ImageRgb8(buffer) => DynamicImage::ImageRgb8(rotate(buffer, degrees)),
+        DynamicImage::ImageRgba8(buffer) => DynamicImage::ImageRgba8(rotate(buffer, degrees)),
+        DynamicImage::ImageLuma16(buffer) => DynamicImage::ImageLuma16(rotate(buffer, degrees)),
+        DynamicImage::ImageLumaA16(buffer) => DynamicImage::ImageLumaA16(rotate(buffer, degrees)),
+        DynamicImage::ImageRgb16(buffer) => DynamicImage::ImageRgb16(rotate(buffer, degrees)),
+        DynamicImage::ImageRgba16(buffer) => DynamicImage::ImageRgba16(rotate(buffer, degrees)),
+        _ => DynamicImage::ImageRgba32F(rotate(&img.to_rgba32f(), degrees)),
+    }
+}
+
+/// Channel types `rotate` can interpolate.
+trait Channel: Copy {
+    fn to_f32(self) -> f32;
+    fn from_f32(value: f32) -> Self;
+}
+
+impl Channel for u8 {
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+    fn from_f32(value: f32) -> Self {
+        value.round().clamp(0.0, u8::MAX as f32) as u8
+    }
+}
+
+impl Channel for u16 {
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+    fn from_f32(value: f32) -> Self {
+        value.round().clamp(0.0, u16::MAX as f32) as u16
+    }
+}
+
+impl Channel for f32 {
+    fn to_f32(self) -> f32 {
+        self
+    }
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+}
+
+/// Bilinear rotation behind `straighten`.
+fn rotate<P>(
+    src: &ImageBuffer<P, Vec<P::Subpixel>>,
+    degrees: f32,
+) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P: Pixel,
+    P::Subpixel: Channel,
+{
+    let (width, height) = src.dimensions();
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (cx, cy) = ((width as f32 - 1.0) / 2.0, (height as f32 - 1.0) / 2.0);
+    let (max_x, max_y) = (width as f32 - 1.0, height as f32 - 1.0);
+
+    ImageBuffer::from_fn(width, height, |x, y| {
+        // Each output pixel samples where the skewed scan had it.
+        let (dx, dy) = (x as f32 - cx, y as f32 - cy);
+        let sx = (cx + dx * cos - dy * sin).clamp(0.0, max_x);
+        let sy = (cy + dx * sin + dy * cos).clamp(0.0, max_y);
+        let (x0, y0) = (sx.floor() as u32, sy.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+        let (fx, fy) = (sx - x0 as f32, sy - y0 as f32);
+
+        let corners = [
+            (src.get_pixel(x0, y0), (1.0 - fx) * (1.0 - fy)),
+            (src.get_pixel(x1, y0), fx * (1.0 - fy)),
+            (src.get_pixel(x0, y1), (1.0 - fx) * fy),
+            (src.get_pixel(x1, y1), fx * fy),
+        ];
+        let mut pixel = *src.get_pixel(x0, y0);
+        for (i, channel) in pixel.channels_mut().iter_mut().enumerate() {
+            let value: f32 = corners
+                .iter()
+                .map(|(p, weight)| p.channels()[i].to_f32() * weight)
+                .sum();
+            *channel = P::Subpixel::from_f32(value);
+        }
+        pixel
+    })
+}
+
+pub fn parse_max_angle(s: &str) -> Result<f32, String> {
+    let value: f32 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+    if value > 0.0 && value <= MAX_ANGLE {
+        Ok(value)
+    } else {
+        Err(format!(
+            "{} is not above 0 and at most {}",
+            value, MAX_ANGLE
+        ))
+    }
+}
@@ -0,0 +1,1001 @@
+use anyhow::{Context, Result, anyhow};
+use clap::ValueEnum;
+use image::{DynamicImage, GrayImage, Luma, Rgb, RgbImage};
+use rustface::{Detector, FaceInfo, ImageData, Rectangle};
+use std::borrow::Cow;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::SizeBand;
+
+/// Overlap above which two detections are considered the same face.
+const NMS_IOU_THRESHOLD: f64 = 0.3;
+
+/// Side of the blank image `warm_up` detects on.
+const WARMUP_SIZE: u32 = 64;
+
+/// Smallest minimum face size `rustface` accepts.
+const MIN_FACE_FLOOR: u32 = 20;
+
+/// Most `--auto-white-balance` scales a channel up (or down, inverted), so
+/// a nearly empty channel is not blown up into noise.
+const MAX_WHITE_BALANCE_GAIN: f64 = 4.0;
+
+/// Everything needed to construct a configured detector.
+///
+/// Kept separate from the detector itself so a fresh one can be built on
+/// another thread (`rustface` detectors are not `Send`).
+#[derive(Clone, Debug)]
+pub struct DetectorConfig {
+    /// `embedded` or the `--model-set` entry `model_path` came from.
+    pub model_name: String,
+    pub model_path: PathBuf,
+    pub profile_model: Option<PathBuf>,
+    pub min_face_size: u32,
+    /// `--min-face-relative`: the minimum face size as a fraction of each
+    /// image's shorter side, in place of `min_face_size` and the bands'.
+    pub min_face_relative: Option<f64>,
+    pub score_thresh: f64,
+    pub pyramid_scale_factor: f32,
+    pub slide_window_step: (u32, u32),
+    /// Per-size overrides of `min_face_size` / `score_thresh`, sorted by
+    /// `max_dimension` as done by `Config::load`.
+    pub size_bands: Vec<SizeBand>,
+}
+
+/// The detector settings that may change from one image to the next.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DetectParams {
+    pub min_face_size: u32,
+    pub score_thresh: f64,
+}
+
+impl DetectorConfig {
+    /// The default detector settings for the given model.
+    pub fn new(model_name: String, model_path: PathBuf) -> Self {
+        DetectorConfig {
+            model_name,
+            model_path,
+            profile_model: None,
+            min_face_size: MIN_FACE_FLOOR,
+            min_face_relative: None,
+            score_thresh: 2.0,
+            pyramid_scale_factor: 0.8,
+            slide_window_step: (4, 4),
+            size_bands: Vec::new(),
+        }
+    }
+
+    pub fn build(&self) -> Result<Box<dyn Detector>> {
+        let mut detector = rustface::create_detector(self.model_path.to_str().unwrap())
+            .context("Failed to create face detector")?;
+
+        if let Some(profile_path) = &self.profile_model {
+            let profile_detector = rustface::create_detector(&profile_path.to_string_lossy())
+                .with_context(|| format!("Failed to load profile model {:?}", profile_path))?;
+
+            detector = Box::new(MergedDetector::new(vec![detector, profile_detector]));
+        }
+
+        detector.set_min_face_size(self.min_face_size);
+        detector.set_score_thresh(self.score_thresh);
+        detector.set_pyramid_scale_factor(self.pyramid_scale_factor);
+        detector.set_slide_window_step(self.slide_window_step.0, self.slide_window_step.1);
+
+        Ok(detector)
+    }
+
+    /// Settings for an image of the given size: the first matching size band
+    /// applied on top of the global values, and the --min-face-relative
+    /// size on top of both.
+    pub fn params_for(&self, width: u32, height: u32) -> DetectParams {
+        let mut params = DetectParams {
+            min_face_size: self.min_face_size,
+            score_thresh: self.score_thresh,
+        };
+
+        let long_side = width.max(height);
+        if let Some(band) = self
+            .size_bands
+            .iter()
+            .find(|band| band.max_dimension.is_none_or(|max| long_side <= max))
+        {
+            params.min_face_size = band.min_face_size.unwrap_or(params.min_face_size);
+            params.score_thresh = band.score_thresh.unwrap_or(params.score_thresh);
+        }
+        if let Some(fraction) = self.min_face_relative {
+            let relative = (width.min(height) as f64 * fraction).round() as u32;
+            params.min_face_size = relative.max(MIN_FACE_FLOOR);
+        }
+
+        params
+    }
+
+    /// The settings that influence which faces are found, as `key=value`
+    /// pairs in a fixed order. The temp model path is deliberately left out
+    /// since it changes on every run.
+    pub fn describe(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("min_face_size", self.min_face_size.to_string()),
+            (
+                "min_face_relative",
+                self.min_face_relative
+                    .map(|fraction| fraction.to_string())
+                    .unwrap_or_default(),
+            ),
+            ("score_thresh", self.score_thresh.to_string()),
+            (
+                "pyramid_scale_factor",
+                self.pyramid_scale_factor.to_string(),
+            ),
+            (
+                "slide_window_step",
+                format!("{}x{}", self.slide_window_step.0, self.slide_window_step.1),
+            ),
+            (
+                "profile_model",
+                self.profile_model
+                    .as_ref()
+                    .and_then(|p| p.file_name())
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            ),
+            (
+                "size_bands",
+                self.size_bands
+                    .iter()
+                    .map(|band| {
+                        format!(
+                            "{:?}/{:?}/{:?}",
+                            band.max_dimension, band.min_face_size, band.score_thresh
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(";"),
+            ),
+            ("model", self.model_name.clone()),
+        ]
+    }
+}
+
+/// Returned (inside `anyhow::Error`) when detection exceeds `--timeout`.
+#[derive(Debug)]
+pub struct DetectionTimeout(pub Duration);
+
+impl fmt::Display for DetectionTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Detection timed out after {} ms.", self.0.as_millis())
+    }
+}
+
+impl std::error::Error for DetectionTimeout {}
+
+/// What the crop pipeline needs from a face detector. `process_image` only
+/// sees this, so other detection libraries can be plugged in behind it.
+pub trait FaceDetector {
+    /// The settings to detect with in a `width` x `height` image.
+    fn params_for(&self, width: u32, height: u32) -> DetectParams;
+
+    /// Faces found in `gray` with `params`, each with its bounding box and
+    /// score. Lets a part of an image be scanned with the settings of the
+    /// whole.
+    fn detect_with(&mut self, gray: GrayImage, params: DetectParams) -> Result<Vec<FaceInfo>>;
+
+    /// Faces found in `gray`, each with its bounding box and score.
+    fn detect(&mut self, gray: GrayImage) -> Result<Vec<FaceInfo>> {
+        let params = self.params_for(gray.width(), gray.height());
+        self.detect_with(gray, params)
+    }
+
+    /// Eye centers of `face`, found in `gray`, for backends that locate
+    /// facial landmarks. `rustface` only finds boxes, so the default is `None`.
+    fn eyes(&mut self, _gray: &GrayImage, _face: &FaceInfo) -> Option<Eyes> {
+        None
+    }
+
+    /// Facial feature points of `face` (eyes, nose, mouth corners, ...),
+    /// found in `gray`, for backends that locate them. `None` by default.
+    fn landmarks(&mut self, _gray: &GrayImage, _face: &FaceInfo) -> Option<Landmarks> {
+        None
+    }
+}
+
+/// Eye centers of a face in image pixels, in image left-to-right order.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Eyes {
+    pub left: (f64, f64),
+    pub right: (f64, f64),
+}
+
+impl Eyes {
+    /// Inter-pupillary distance in pixels.
+    pub fn distance(&self) -> f64 {
+        (self.right.0 - self.left.0).hypot(self.right.1 - self.left.1)
+    }
+}
+
+/// Facial feature points of a face in image pixels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Landmarks {
+    pub points: Vec<(f64, f64)>,
+}
+
+impl Landmarks {
+    /// The square face box `--frame-from-landmarks` crops around: centered
+    /// on the bounds of the points, as wide as their larger extent plus
+    /// `padding` times that extent on each side. `None` for fewer than two
+    /// distinct points.
+    pub fn framed(&self, padding: f64) -> Option<Rectangle> {
+        let (mut left, mut top) = (f64::INFINITY, f64::INFINITY);
+        let (mut right, mut bottom) = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for &(x, y) in &self.points {
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x);
+            bottom = bottom.max(y);
+        }
+        let spread = (right - left).max(bottom - top);
+        if !spread.is_finite() || spread <= 0.0 {
+            return None;
+        }
+        let side = spread * (1.0 + 2.0 * padding);
+        let (cx, cy) = ((left + right) / 2.0, (top + bottom) / 2.0);
+        Some(Rectangle::new(
+            (cx - side / 2.0).round() as i32,
+            (cy - side / 2.0).round() as i32,
+            side.round() as u32,
+            side.round() as u32,
+        ))
+    }
+}
+
+/// Run `detector` once on a small blank image, so that lazy setup inside
+/// the detection library is not charged to the first real image.
+pub fn warm_up(detector: &mut dyn FaceDetector) -> Result<()> {
+    detector.detect(GrayImage::new(WARMUP_SIZE, WARMUP_SIZE))?;
+    Ok(())
+}
+
+/// Faces in `gray` found tile by tile (`--tile-size`): overlapping
+/// `tile`-pixel squares, `overlap` pixels apart at the seams, each scanned
+/// at full resolution, with the settings for the whole of `gray`. Returns
+/// the faces in `gray` coordinates and the number of tiles scanned.
+///
+/// A face cut by a seam is found whole in the neighbouring tile as long as
+/// it is smaller than the overlap, so boxes touching a seam are dropped
+/// when they overlap one that does not; the rest are merged with NMS.
+pub fn detect_tiled(
+    detector: &mut dyn FaceDetector,
+    gray: &GrayImage,
+    tile: u32,
+    overlap: u32,
+) -> Result<(Vec<FaceInfo>, usize)> {
+    let params = detector.params_for(gray.width(), gray.height());
+    let step = tile - overlap;
+    let xs = tile_starts(gray.width(), tile, step);
+    let ys = tile_starts(gray.height(), tile, step);
+
+    let mut whole = Vec::new();
+    let mut cut = Vec::new();
+    for &y in &ys {
+        for &x in &xs {
+            let w = tile.min(gray.width() - x);
+            let h = tile.min(gray.height() - y);
+            let view = image::imageops::crop_imm(gray, x, y, w, h).to_image();
+            for mut face in detector.detect_with(view, params)? {
+                let b = *face.bbox();
+                let on_seam = (b.x() <= 0 && x > 0)
+                    || (b.y() <= 0 && y > 0)
+                    || (b.x() as i64 + b.width() as i64 >= w as i64 && x + w < gray.width())
+                    || (b.y() as i64 + b.height() as i64 >= h as i64 && y + h < gray.height());
+                *face.bbox_mut() =
+                    Rectangle::new(b.x() + x as i32, b.y() + y as i32, b.width(), b.height());
+                if on_seam { &mut cut } else { &mut whole }.push(face);
+            }
+        }
+    }
+
+    cut.retain(|face| whole.iter().all(|w| iou(w.bbox(), face.bbox()) == 0.0));
+    whole.extend(cut);
+    Ok((
+        non_max_suppression(whole, NMS_IOU_THRESHOLD),
+        xs.len() * ys.len(),
+    ))
+}
+
+/// Faces in `gray` found in two passes (`--two-stage-detect`): a coarse
+/// one over a copy scaled down to `coarse_side` pixels on its longer side,
+/// then a full-resolution one over the regions around the faces it found,
+/// each face box grown by `padding` times its size on every side and
+/// overlapping regions merged. Both passes use the settings for the whole
+/// of `gray`, with the minimum face size scaled down for the coarse one.
+/// Returns the faces in `gray` coordinates and the number of regions
+/// rescanned.
+///
+/// Coarse faces the fine pass does not find again are kept, scaled up;
+/// the rest are merged with NMS. Images not larger than `coarse_side` are
+/// scanned once as they are.
+pub fn detect_two_stage(
+    detector: &mut dyn FaceDetector,
+    gray: &GrayImage,
+    coarse_side: u32,
+    padding: f64,
+) -> Result<(Vec<FaceInfo>, usize)> {
+    let (width, height) = gray.dimensions();
+    let params = detector.params_for(width, height);
+    let long_side = width.max(height);
+    if long_side <= coarse_side {
+        return Ok((detector.detect_with(gray.clone(), params)?, 0));
+    }
+
+    let ratio = long_side as f64 / coarse_side as f64;
+    let coarse_params = DetectParams {
+        min_face_size: ((params.min_face_size as f64 / ratio).round() as u32).max(MIN_FACE_FLOOR),
+        ..params
+    };
+    let scale = |side: u32| ((side as f64 / ratio).round() as u32).max(1);
+    let coarse = image::imageops::resize(
+        gray,
+        scale(width),
+        scale(height),
+        image::imageops::FilterType::Triangle,
+    );
+    let mut candidates = detector.detect_with(coarse, coarse_params)?;
+    for face in &mut candidates {
+        let b = *face.bbox();
+        let map = |v: i32| (v as f64 * ratio).round() as i32;
+        let size = |v: u32| (v as f64 * ratio).round() as u32;
+        *face.bbox_mut() =
+            Rectangle::new(map(b.x()), map(b.y()), size(b.width()), size(b.height()));
+    }
+
+    let mut found = Vec::new();
+    let regions = candidate_regions(&candidates, padding, width, height);
+    for &(x, y, w, h) in &regions {
+        let view = image::imageops::crop_imm(gray, x, y, w, h).to_image();
+        for mut face in detector.detect_with(view, params)? {
+            let b = *face.bbox();
+            *face.bbox_mut() =
+                Rectangle::new(b.x() + x as i32, b.y() + y as i32, b.width(), b.height());
+            found.push(face);
+        }
+    }
+
+    candidates.retain(|face| found.iter().all(|f| iou(f.bbox(), face.bbox()) == 0.0));
+    found.extend(candidates);
+    Ok((non_max_suppression(found, NMS_IOU_THRESHOLD), regions.len()))
+}
+
+/// The `(x, y, width, height)` regions of a `width` x `height` image around
+/// `faces`, each box grown by `padding` times its larger side on every side
+/// and clipped to the image. Overlapping regions are merged into their
+/// bounding box, so no pixel is scanned twice.
+fn candidate_regions(
+    faces: &[FaceInfo],
+    padding: f64,
+    width: u32,
+    height: u32,
+) -> Vec<(u32, u32, u32, u32)> {
+    // As (left, top, right, bottom), which merging needs.
+    let mut regions: Vec<(u32, u32, u32, u32)> = faces
+        .iter()
+        .map(|face| {
+            let b = face.bbox();
+            let pad = (b.width().max(b.height()) as f64 * padding).round() as i64;
+            let clip = |v: i64, max: u32| v.clamp(0, max as i64) as u32;
+            (
+                clip(b.x() as i64 - pad, width),
+                clip(b.y() as i64 - pad, height),
+                clip(b.x() as i64 + b.width() as i64 + pad, width),
+                clip(b.y() as i64 + b.height() as i64 + pad, height),
+            )
+        })
+        .filter(|&(left, top, right, bottom)| right > left && bottom > top)
+        .collect();
+
+    let mut merged = true;
+    while merged {
+        merged = false;
+        'outer: for i in 0..regions.len() {
+            for j in i + 1..regions.len() {
+                let (a, b) = (regions[i], regions[j]);
+                if a.0 < b.2 && b.0 < a.2 && a.1 < b.3 && b.1 < a.3 {
+                    regions[i] = (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3));
+                    regions.swap_remove(j);
+                    merged = true;
+                    break 'outer;
+                }
+            }
+        }
+    }
+
+    regions
+        .into_iter()
+        .map(|(left, top, right, bottom)| (left, top, right - left, bottom - top))
+        .collect()
+}
+
+/// Offsets of the tiles covering `length` pixels: every `step` pixels, with
+/// the last tile moved back to end at the edge.
+fn tile_starts(length: u32, tile: u32, step: u32) -> Vec<u32> {
+    if length <= tile {
+        return vec![0];
+    }
+    let mut starts: Vec<u32> = (0..length - tile).step_by(step as usize).collect();
+    starts.push(length - tile);
+    starts
+}
+
+/// Detection library selected with `--backend`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// SeetaFace via the `rustface` crate
+    #[default]
+    Rustface,
+}
+
+impl Backend {
+    /// A detector for `config`, giving up on images after `timeout`.
+    pub fn create(
+        self,
+        config: &DetectorConfig,
+        timeout: Option<Duration>,
+    ) -> Result<Box<dyn FaceDetector>> {
+        match self {
+            Backend::Rustface => Ok(Box::new(FaceFinder::new(config, timeout)?)),
+        }
+    }
+}
+
+/// How color images are turned into the grayscale the detector scans,
+/// selected with `--grayscale-conversion`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum GrayConversion {
+    /// Rec. 709 luma weights, as used by the `image` crate's `to_luma8`
+    #[default]
+    Rec709,
+    /// Rec. 601 luma weights (0.299 R + 0.587 G + 0.114 B), as in most
+    /// JPEG and OpenCV code
+    Rec601,
+    /// Plain mean of R, G and B
+    Average,
+}
+
+impl GrayConversion {
+    /// Any color type is accepted: see `flatten` for how alpha and deep
+    /// images are brought to 8 bits first. Gray images keep their levels
+    /// whatever the weights.
+    pub fn apply(self, img: &DynamicImage) -> GrayImage {
+        let img = flatten(img);
+        let weights = match (self, img.as_ref()) {
+            (_, DynamicImage::ImageLuma8(gray)) => return gray.clone(),
+            (GrayConversion::Rec709, img) => return img.to_luma8(),
+            (GrayConversion::Rec601, _) => [0.299, 0.587, 0.114],
+            (GrayConversion::Average, _) => [1.0 / 3.0; 3],
+        };
+
+        let rgb = img.to_rgb8();
+        GrayImage::from_fn(rgb.width(), rgb.height(), |x, y| {
+            let [r, g, b] = rgb.get_pixel(x, y).0;
+            let luma = weights[0] * r as f32 + weights[1] * g as f32 + weights[2] * b as f32;
+            Luma([luma.round().min(255.0) as u8])
+        })
+    }
+}
+
+/// `img` as 8-bit gray or RGB without alpha, the form detection works on.
+///
+/// Transparent pixels are composited over white rather than keeping
+/// whatever color they happen to store, which is often black and would
+/// hide a cut-out face in the dark. An alpha-only image (a mask with
+/// constant color) so comes out as its shape in dark on white. 16-bit and
+/// float images are scaled to 8 bits; palette images are already expanded
+/// to RGB by the decoder.
+pub fn flatten(img: &DynamicImage) -> Cow<'_, DynamicImage> {
+    let color = img.color();
+    if !color.has_alpha() {
+        return match img {
+            DynamicImage::ImageLuma8(_) | DynamicImage::ImageRgb8(_) => Cow::Borrowed(img),
+            _ if color.has_color() => Cow::Owned(DynamicImage::ImageRgb8(img.to_rgb8())),
+            _ => Cow::Owned(DynamicImage::ImageLuma8(img.to_luma8())),
+        };
+    }
+
+    let rgba = img.to_rgba8();
+    let over_white = |value: u8, alpha: u8| {
+        let (value, alpha) = (value as u32, alpha as u32);
+        ((value * alpha + 255 * (255 - alpha) + 127) / 255) as u8
+    };
+    let (width, height) = rgba.dimensions();
+    if color.has_color() {
+        Cow::Owned(DynamicImage::ImageRgb8(RgbImage::from_fn(
+            width,
+            height,
+            |x, y| {
+                let [r, g, b, a] = rgba.get_pixel(x, y).0;
+                Rgb([over_white(r, a), over_white(g, a), over_white(b, a)])
+            },
+        )))
+    } else {
+        Cow::Owned(DynamicImage::ImageLuma8(GrayImage::from_fn(
+            width,
+            height,
+            |x, y| {
+                let [l, _, _, a] = rgba.get_pixel(x, y).0;
+                Luma([over_white(l, a)])
+            },
+        )))
+    }
+}
+
+/// `img` with its color cast removed for `--auto-white-balance`, by the
+/// gray-world assumption: each channel is scaled so that the red, green
+/// and blue means come out equal. Gray images are returned as they are.
+pub fn white_balance(img: &DynamicImage) -> DynamicImage {
+    if img.color().channel_count() <= 2 {
+        return img.clone();
+    }
+    let mut rgb = flatten(img).to_rgb8();
+    let mut sums = [0u64; 3];
+    for pixel in rgb.pixels() {
+        for (sum, &value) in sums.iter_mut().zip(&pixel.0) {
+            *sum += value as u64;
+        }
+    }
+    let target = sums.iter().sum::<u64>() as f64 / 3.0;
+    // A channel with nothing in it cannot be brought back; leave it.
+    let gains = sums.map(|sum| {
+        if sum == 0 {
+            1.0
+        } else {
+            (target / sum as f64).clamp(1.0 / MAX_WHITE_BALANCE_GAIN, MAX_WHITE_BALANCE_GAIN)
+        }
+    });
+
+    for pixel in rgb.pixels_mut() {
+        for (value, gain) in pixel.0.iter_mut().zip(gains) {
+            *value = (*value as f64 * gain).round().min(255.0) as u8;
+        }
+    }
+    DynamicImage::ImageRgb8(rgb)
+}
+
+/// Histogram-equalize `gray` for `--auto-contrast`, spreading its levels
+/// over the full 0-255 range so faces in flat or backlit images stand out.
+pub fn equalize(gray: &GrayImage) -> GrayImage {
+    let mut histogram = [0u64; 256];
+    for pixel in gray.pixels() {
+        histogram[pixel[0] as usize] += 1;
+    }
+
+    let total = gray.width() as u64 * gray.height() as u64;
+    let darkest = histogram
+        .iter()
+        .copied()
+        .find(|&count| count > 0)
+        .unwrap_or(0);
+    if total == darkest {
+        // A single level (or no pixels): nothing to spread.
+        return gray.clone();
+    }
+
+    let mut lookup = [0u8; 256];
+    let mut cumulative = 0;
+    for (level, count) in histogram.iter().enumerate() {
+        cumulative += count;
+        lookup[level] = (cumulative.saturating_sub(darkest) * 255 / (total - darkest)) as u8;
+    }
+
+    let mut equalized = gray.clone();
+    for pixel in equalized.pixels_mut() {
+        pixel[0] = lookup[pixel[0] as usize];
+    }
+    equalized
+}
+
+/// The `rustface` backend.
+///
+/// Either calls the detector directly, or hands each image to a worker thread
+/// and waits at most `timeout` for the answer.
+pub struct FaceFinder {
+    config: DetectorConfig,
+    mode: Mode,
+}
+
+enum Mode {
+    Inline(Box<dyn Detector>),
+    Watched {
+        timeout: Duration,
+        worker: Option<Worker>,
+    },
+}
+
+impl FaceFinder {
+    pub fn new(config: &DetectorConfig, timeout: Option<Duration>) -> Result<Self> {
+        match timeout {
+            Some(timeout) => FaceFinder::with_timeout(config.clone(), timeout),
+            None => FaceFinder::inline(config),
+        }
+    }
+
+    pub fn inline(config: &DetectorConfig) -> Result<Self> {
+        Ok(FaceFinder {
+            config: config.clone(),
+            mode: Mode::Inline(config.build()?),
+        })
+    }
+
+    pub fn with_timeout(config: DetectorConfig, timeout: Duration) -> Result<Self> {
+        // Build once up front so a bad model is reported immediately rather
+        // than as a per-image failure from the worker.
+        config.build()?;
+
+        Ok(FaceFinder {
+            config,
+            mode: Mode::Watched {
+                timeout,
+                worker: None,
+            },
+        })
+    }
+}
+
+impl FaceDetector for FaceFinder {
+    fn params_for(&self, width: u32, height: u32) -> DetectParams {
+        self.config.params_for(width, height)
+    }
+
+    fn detect_with(&mut self, gray: GrayImage, params: DetectParams) -> Result<Vec<FaceInfo>> {
+        match &mut self.mode {
+            Mode::Inline(detector) => Ok(run_detect(detector.as_mut(), &gray, params)),
+            Mode::Watched { timeout, worker } => {
+                let active = match worker {
+                    Some(w) => w,
+                    None => worker.insert(Worker::spawn(self.config.clone())),
+                };
+
+                active
+                    .jobs
+                    .send((gray, params))
+                    .map_err(|_| anyhow!("Detection worker exited unexpectedly"))?;
+
+                match active.results.recv_timeout(*timeout) {
+                    Ok(result) => result.map_err(|e| anyhow!(e)),
+                    Err(RecvTimeoutError::Timeout) => {
+                        // `rustface` cannot be interrupted mid-call, so the
+                        // stuck worker is simply abandoned: it finishes in the
+                        // background and exits once it sees the closed channel.
+                        *worker = None;
+                        Err(DetectionTimeout(*timeout).into())
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        *worker = None;
+                        Err(anyhow!("Detection worker exited unexpectedly"))
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn run_detect(
+    detector: &mut dyn Detector,
+    gray: &GrayImage,
+    params: DetectParams,
+) -> Vec<FaceInfo> {
+    detector.set_min_face_size(params.min_face_size);
+    detector.set_score_thresh(params.score_thresh);
+
+    let image_data = ImageData::new(gray, gray.width(), gray.height());
+
+    // rustface 0.1.7 stores the minimum face size but never applies it, so
+    // enforce it on the returned boxes.
+    detector
+        .detect(&image_data)
+        .into_iter()
+        .filter(|face| face.bbox().width().max(face.bbox().height()) >= params.min_face_size)
+        .collect()
+}
+
+/// A thread owning its own detector, fed one image at a time.
+struct Worker {
+    jobs: Sender<(GrayImage, DetectParams)>,
+    results: Receiver<Result<Vec<FaceInfo>, String>>,
+}
+
+impl Worker {
+    fn spawn(config: DetectorConfig) -> Self {
+        let (job_tx, job_rx) = mpsc::channel::<(GrayImage, DetectParams)>();
+        let (result_tx, result_rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut detector = match config.build() {
+                Ok(d) => d,
+                Err(e) => {
+                    let _ = result_tx.send(Err(format!("{:#}", e)));
+                    return;
+                }
+            };
+
+            for (gray, params) in job_rx {
+                let faces = run_detect(detector.as_mut(), &gray, params);
+                if result_tx.send(Ok(faces)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Worker {
+            jobs: job_tx,
+            results: result_rx,
+        }
+    }
+}
+
+/// Runs several detectors over the same image and merges their results.
+///
+/// Implements `rustface::Detector` itself, so the rest of the pipeline does not
+/// need to know how many models are loaded. Parameter setters are forwarded to
+/// every inner detector.
+pub struct MergedDetector {
+    detectors: Vec<Box<dyn Detector>>,
+}
+
+impl MergedDetector {
+    pub fn new(detectors: Vec<Box<dyn Detector>>) -> Self {
+        MergedDetector { detectors }
+    }
+}
+
+impl Detector for MergedDetector {
+    fn detect(&mut self, image: &ImageData) -> Vec<FaceInfo> {
+        let faces = self
+            .detectors
+            .iter_mut()
+            .flat_map(|d| d.detect(image))
+            .collect();
+
+        non_max_suppression(faces, NMS_IOU_THRESHOLD)
+    }
+
+    fn set_window_size(&mut self, wnd_size: u32) {
+        self.detectors
+            .iter_mut()
+            .for_each(|d| d.set_window_size(wnd_size));
+    }
+
+    fn set_slide_window_step(&mut self, step_x: u32, step_y: u32) {
+        self.detectors
+            .iter_mut()
+            .for_each(|d| d.set_slide_window_step(step_x, step_y));
+    }
+
+    fn set_min_face_size(&mut self, min_face_size: u32) {
+        self.detectors
+            .iter_mut()
+            .for_each(|d| d.set_min_face_size(min_face_size));
+    }
+
+    fn set_max_face_size(&mut self, max_face_size: u32) {
+        self.detectors
+            .iter_mut()
+            .for_each(|d| d.set_max_face_size(max_face_size));
+    }
+
+    fn set_pyramid_scale_factor(&mut self, scale_factor: f32) {
+        self.detectors
+            .iter_mut()
+            .for_each(|d| d.set_pyramid_scale_factor(scale_factor));
+    }
+
+    fn set_score_thresh(&mut self, thresh: f64) {
+        self.detectors
+            .iter_mut()
+            .for_each(|d| d.set_score_thresh(thresh));
+    }
+}
+
+/// Intersection-over-union of two boxes, in `[0, 1]`.
+pub fn iou(a: &Rectangle, b: &Rectangle) -> f64 {
+    let left = a.x().max(b.x()) as i64;
+    let top = a.y().max(b.y()) as i64;
+    let right = (a.x() as i64 + a.width() as i64).min(b.x() as i64 + b.width() as i64);
+    let bottom = (a.y() as i64 + a.height() as i64).min(b.y() as i64 + b.height() as i64);
+
+    if right <= left || bottom <= top {
+        return 0.0;
+    }
+
+    let intersection = ((right - left) * (bottom - top)) as f64;
+    let area_a = a.width() as f64 * a.height() as f64;
+    let area_b = b.width() as f64 * b.height() as f64;
+
+    intersection / (area_a + area_b - intersection)
+}
+
+/// Greedy NMS: keep the highest-scoring face, drop everything overlapping it
+/// by more than `iou_threshold`, repeat.
+pub fn non_max_suppression(mut faces: Vec<FaceInfo>, iou_threshold: f64) -> Vec<FaceInfo> {
+    faces.sort_by(|a, b| b.score().total_cmp(&a.score()));
+
+    let mut kept: Vec<FaceInfo> = Vec::with_capacity(faces.len());
+    for face in faces {
+        if kept
+            .iter()
+            .all(|k| iou(k.bbox(), face.bbox()) <= iou_threshold)
+        {
+            kept.push(face);
+        }
+    }
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, LumaA, Rgba, RgbaImage};
+
+    /// The gray levels the detector would scan in `img`, row by row.
+    fn gray_levels(img: &DynamicImage, conversion: GrayConversion) -> Vec<u8> {
+        conversion.apply(img).into_raw()
+    }
+
+    /// A zlib stream holding `data` in stored (uncompressed) blocks.
+    fn zlib_stored(data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0x78, 0x01];
+        let mut blocks = data.chunks(0xFFFF).peekable();
+        while let Some(block) = blocks.next() {
+            out.push(blocks.peek().is_none() as u8);
+            let len = block.len() as u16;
+            out.extend(len.to_le_bytes());
+            out.extend((!len).to_le_bytes());
+            out.extend(block);
+        }
+        let (mut a, mut b) = (1u32, 0u32);
+        for &byte in data {
+            a = (a + byte as u32) % 65521;
+            b = (b + a) % 65521;
+        }
+        out.extend(((b << 16) | a).to_be_bytes());
+        out
+    }
+
+    /// An 8-bit palette PNG of one row of `indices` into `palette`, with
+    /// `alpha` as its tRNS entries.
+    fn palette_png(palette: &[[u8; 3]], alpha: &[u8], indices: &[u8]) -> Vec<u8> {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut chunk = |kind: &[u8], data: &[u8]| {
+            png.extend((data.len() as u32).to_be_bytes());
+            let mut crc = crc32fast::Hasher::new();
+            crc.update(kind);
+            crc.update(data);
+            png.extend(kind);
+            png.extend(data);
+            png.extend(crc.finalize().to_be_bytes());
+        };
+        let mut header = (indices.len() as u32).to_be_bytes().to_vec();
+        header.extend(1u32.to_be_bytes());
+        header.extend([8, 3, 0, 0, 0]);
+        chunk(b"IHDR", &header);
+        chunk(b"PLTE", palette.concat().as_slice());
+        if !alpha.is_empty() {
+            chunk(b"tRNS", alpha);
+        }
+        let mut row = vec![0];
+        row.extend(indices);
+        chunk(b"IDAT", &zlib_stored(&row));
+        chunk(b"IEND", &[]);
+        png
+    }
+
+    #[test]
+    fn palette_images_are_expanded() {
+        let palette = [[0, 0, 0], [90, 90, 90], [255, 0, 0]];
+        let png = palette_png(&palette, &[], &[0, 1, 1, 0]);
+        let img = image::load_from_memory(&png).unwrap();
+        assert_eq!(gray_levels(&img, GrayConversion::Average), [0, 90, 90, 0]);
+
+        // Index 0 fully transparent, index 2 red at half opacity.
+        let png = palette_png(&palette, &[0, 255, 128], &[0, 1, 2, 0]);
+        let img = image::load_from_memory(&png).unwrap();
+        assert!(img.color().has_alpha());
+        // Half-opaque red over white is (255, 127, 127), averaging to 170.
+        assert_eq!(
+            gray_levels(&img, GrayConversion::Average),
+            [255, 90, 170, 255]
+        );
+    }
+
+    #[test]
+    fn gray_alpha_goes_over_white() {
+        let img = ImageBuffer::from_raw(4, 1, vec![0, 0, 100, 255, 0, 128, 200, 0]).unwrap();
+        let img = DynamicImage::ImageLumaA8(img);
+        let flat = flatten(&img);
+        assert!(matches!(flat.as_ref(), DynamicImage::ImageLuma8(_)));
+        for conversion in [GrayConversion::Rec709, GrayConversion::Rec601] {
+            assert_eq!(gray_levels(&img, conversion), [255, 100, 127, 255]);
+        }
+
+        // Deep gray with alpha takes the same path.
+        let deep: ImageBuffer<LumaA<u16>, _> =
+            ImageBuffer::from_raw(2, 1, vec![0, 0, 25700, 65535]).unwrap();
+        let levels = gray_levels(&DynamicImage::ImageLumaA16(deep), GrayConversion::Rec709);
+        assert_eq!(levels, [255, 100]);
+    }
+
+    #[test]
+    fn deep_images_are_scaled_to_8_bits() {
+        let gray: ImageBuffer<Luma<u16>, _> =
+            ImageBuffer::from_raw(3, 1, vec![0, 0x8080, 0xFFFF]).unwrap();
+        let img = DynamicImage::ImageLuma16(gray);
+        let flat = flatten(&img);
+        assert!(matches!(flat.as_ref(), DynamicImage::ImageLuma8(_)));
+        assert_eq!(gray_levels(&img, GrayConversion::Rec601), [0, 128, 255]);
+
+        let rgb: ImageBuffer<Rgb<u16>, _> =
+            ImageBuffer::from_raw(1, 1, vec![200 * 257, 100 * 257, 50 * 257]).unwrap();
+        let img = DynamicImage::ImageRgb16(rgb);
+        assert!(matches!(flatten(&img).as_ref(), DynamicImage::ImageRgb8(_)));
+        assert_eq!(gray_levels(&img, GrayConversion::Rec601), [124]);
+        assert_eq!(gray_levels(&img, GrayConversion::Average), [117]);
+
+        let float = image::Rgb32FImage::from_raw(1, 1, vec![1.0, 1.0, 1.0]).unwrap();
+        let img = DynamicImage::ImageRgb32F(float);
+        assert_eq!(gray_levels(&img, GrayConversion::Rec709), [255]);
+    }
+
+    #[test]
+    fn mostly_transparent_rgba_goes_over_white() {
+        // A cut-out: an opaque dark square in a transparent field whose
+        // hidden color is black.
+        let img = RgbaImage::from_fn(8, 8, |x, y| {
+            if (2..6).contains(&x) && (2..6).contains(&y) {
+                Rgba([40, 40, 40, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        });
+        let img = DynamicImage::ImageRgba8(img);
+        assert!(matches!(flatten(&img).as_ref(), DynamicImage::ImageRgb8(_)));
+        for conversion in [
+            GrayConversion::Rec709,
+            GrayConversion::Rec601,
+            GrayConversion::Average,
+        ] {
+            let gray = conversion.apply(&img);
+            for (x, y, level) in gray.enumerate_pixels() {
+                let inside = (2..6).contains(&x) && (2..6).contains(&y);
+                let expected = if inside { 40 } else { 255 };
+                assert_eq!(level.0[0], expected, "{:?} at ({}, {})", conversion, x, y);
+            }
+        }
+
+        // An alpha-only mask comes out as its shape in dark on white.
+        let mask = RgbaImage::from_fn(2, 1, |x, _| Rgba([0, 0, 0, [255, 0][x as usize]]));
+        let levels = gray_levels(&DynamicImage::ImageRgba8(mask), GrayConversion::Rec709);
+        assert_eq!(levels, [0, 255]);
+    }
+
+    #[test]
+    fn opaque_images_keep_their_levels() {
+        let gray = GrayImage::from_raw(3, 1, vec![0, 77, 255]).unwrap();
+        let img = DynamicImage::ImageLuma8(gray.clone());
+        assert!(matches!(flatten(&img), Cow::Borrowed(_)));
+        for conversion in [GrayConversion::Rec601, GrayConversion::Average] {
+            assert_eq!(conversion.apply(&img), gray);
+        }
+
+        let rgb = RgbImage::from_raw(1, 1, vec![200, 100, 50]).unwrap();
+        let img = DynamicImage::ImageRgb8(rgb);
+        assert!(matches!(flatten(&img), Cow::Borrowed(_)));
+        assert_eq!(gray_levels(&img, GrayConversion::Rec601), [124]);
+        assert_eq!(gray_levels(&img, GrayConversion::Average), [117]);
+        assert_eq!(
+            gray_levels(&img, GrayConversion::Rec709),
+            img.to_luma8().into_raw()
+        );
+    }
+}
@@ -0,0 +1,39 @@
+//! `--explain`: a plain-language account of how one image is cropped.
+//!
+//! The steps themselves are printed where they happen in the pipeline; this
+//! only words the parts that are shared between steps.
+
+use rustface::{FaceInfo, Rectangle};
+
+use crate::Args;
+use crate::selection::SelectStrategy;
+
+/// `(x, y) WxH` of a box.
+pub fn region(b: &Rectangle) -> String {
+    format!("({}, {}) {}x{}", b.x(), b.y(), b.width(), b.height())
+}
+
+/// A face as its box and detector score.
+pub fn face(face: &FaceInfo) -> String {
+    format!("{}, score {:.2}", region(face.bbox()), face.score())
+}
+
+/// Why the face(s) to crop were picked from `count` detections, mirroring
+/// the order of checks in `choose_face`.
+pub fn selection(args: &Args, count: usize) -> String {
+    if let Some(n) = args.face_index {
+        return format!("face {} counting from the left (--face-index)", n);
+    }
+    if args.group_crop {
+        return format!("one crop around all {} face(s) (--group-crop)", count);
+    }
+    if let Some(n) = args.largest_n {
+        return format!("the {} largest face(s) (--largest-n)", n);
+    }
+    match args.select {
+        Some(SelectStrategy::Largest) => "the largest face (--select largest)".to_string(),
+        Some(SelectStrategy::Score) => "the highest score (--select score)".to_string(),
+        None if count > 1 => "the largest face (--lenient)".to_string(),
+        None => "the only face".to_string(),
+    }
+}
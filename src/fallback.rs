@@ -0,0 +1,79 @@
+//! `--retry-decode-with-fallback-backend`: a second, more forgiving attempt
+//! at image files the usual decoder rejects.
+
+use image::{DynamicImage, ImageError, ImageFormat, ImageReader, ImageResult, Limits};
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+/// How far into a file `retry` looks for the start of an image, past junk
+/// such as a leftover download or recovery header.
+const SIGNATURE_SEARCH: usize = 4096;
+
+/// Start bytes of formats that are commonly found behind such junk.
+const SIGNATURES: [(&[u8], ImageFormat); 3] = [
+    (&[0xFF, 0xD8, 0xFF], ImageFormat::Jpeg),
+    (b"\x89PNG\r\n\x1a\n", ImageFormat::Png),
+    (b"RIFF", ImageFormat::WebP),
+];
+
+/// `result` of opening `path`, or if that failed, the image decoded another
+/// way, with a note saying how. Tried in order:
+///
+/// 1. the format found from the file's contents, for files whose extension
+///    names another format;
+/// 2. without the decoder's memory limits, for very large images;
+/// 3. from the first JPEG, PNG or WebP signature in the first 4 KiB, for
+///    files with junk in front of the image.
+///
+/// Otherwise the original error is returned.
+pub fn retry(path: &Path, result: ImageResult<DynamicImage>) -> ImageResult<DynamicImage> {
+    let error = match result {
+        Ok(img) => return Ok(img),
+        Err(e) => e,
+    };
+    let Ok(data) = fs::read(path) else {
+        return Err(error);
+    };
+    match decode(path, &data) {
+        Some((img, how)) => {
+            eprintln!(
+                "Recovered {:?} with the fallback decoder: read {} (the usual decoder failed: {}).",
+                path, how, error
+            );
+            Ok(img)
+        }
+        None => Err(error),
+    }
+}
+
+fn decode(path: &Path, data: &[u8]) -> Option<(DynamicImage, String)> {
+    let named = ImageFormat::from_path(path).ok();
+    if let Ok(found) = image::guess_format(data)
+        && Some(found) != named
+        && let Ok(img) = image::load_from_memory_with_format(data, found)
+    {
+        return Some((img, format!("as {:?}, the format of its contents", found)));
+    }
+
+    if let Ok(img) = unlimited(data) {
+        return Some((img, "without the decoder's memory limits".to_string()));
+    }
+
+    let window = &data[..data.len().min(SIGNATURE_SEARCH)];
+    SIGNATURES.iter().find_map(|&(signature, format)| {
+        let offset = (1..window.len()).find(|&i| window[i..].starts_with(signature))?;
+        let img = image::load_from_memory_with_format(&data[offset..], format).ok()?;
+        Some((
+            img,
+            format!("as {:?} after skipping {} leading byte(s)", format, offset),
+        ))
+    })
+}
+
+/// `data` decoded in the format its contents show, with no limit on size.
+fn unlimited(data: &[u8]) -> Result<DynamicImage, ImageError> {
+    let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+    reader.limits(Limits::no_limits());
+    reader.decode()
+}
@@ -0,0 +1,147 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Hex digits of the input path hash appended by `NameRegistry::claim`.
+const HASH_LENGTH: usize = 8;
+
+/// Digits of a `--number-outputs` file name, zero-padded.
+const NUMBER_WIDTH: usize = 6;
+
+/// Stem of the name a crop has until it gets its `--number-outputs` number.
+const PENDING: &str = ".pending";
+
+/// `name` for `--normalize-filename`: every character other than an ASCII
+/// letter, digit or `_` becomes `_`, one per character.
+pub fn normalize(name: &OsStr) -> String {
+    name.to_string_lossy()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// The crop paths handed out so far in a `--normalize-filename` run, so two
+/// inputs that normalize to the same name do not overwrite each other.
+pub struct NameRegistry {
+    /// Crop path -> the input it belongs to.
+    claimed: Mutex<HashMap<PathBuf, PathBuf>>,
+    /// Suffix the crops are named with (`_cropped` by default).
+    suffix: String,
+}
+
+impl NameRegistry {
+    pub fn new(suffix: &str) -> Self {
+        NameRegistry {
+            claimed: Mutex::default(),
+            suffix: suffix.to_string(),
+        }
+    }
+
+    /// `output` if it is free or already belongs to `input`; otherwise
+    /// `output` with a short hash of the input path added to its stem
+    /// (before the crop suffix).
+    pub fn claim(&self, output: PathBuf, input: &Path) -> PathBuf {
+        let mut claimed = self.claimed.lock().unwrap();
+        let output = match claimed.get(&output) {
+            Some(owner) if owner != input => with_hash(&output, input, &self.suffix),
+            _ => output,
+        };
+        claimed.insert(output.clone(), input.to_path_buf());
+        output
+    }
+}
+
+fn with_hash(output: &Path, input: &Path, suffix: &str) -> PathBuf {
+    let hash: String = Sha256::digest(input.to_string_lossy().as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = match stem.strip_suffix(suffix) {
+        Some(base) => format!("{}_{}{}", base, &hash[..HASH_LENGTH], suffix),
+        None => format!("{}_{}", stem, &hash[..HASH_LENGTH]),
+    };
+    if let Some(ext) = output.extension() {
+        name.push('.');
+        name.push_str(&ext.to_string_lossy());
+    }
+    output.with_file_name(name)
+}
+
+/// Sequential crop names for `--number-outputs`: `000001.jpg`, `000002.jpg`,
+/// ... in the order crops are claimed. An image is named with `pending`
+/// until it is about to be written, so images that are rejected never take
+/// a number.
+pub struct Numbering {
+    next: AtomicU64,
+}
+
+impl Numbering {
+    pub fn new(first: u64) -> Self {
+        Numbering {
+            next: AtomicU64::new(first),
+        }
+    }
+
+    /// Numbering that continues after the highest numbered file already in
+    /// `dir` (`--continue-numbering`), or starts at 1 if there is none.
+    pub fn continuing(dir: &Path) -> std::io::Result<Self> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Numbering::new(1)),
+            Err(e) => return Err(e),
+        };
+        let mut highest = 0;
+        for entry in entries {
+            if let Some(n) = number_of(&entry?.path()) {
+                highest = highest.max(n);
+            }
+        }
+        Ok(Numbering::new(highest + 1))
+    }
+
+    /// The name `input`'s crop has until `claim` numbers it, with `input`'s
+    /// extension.
+    pub fn pending(input: &Path) -> PathBuf {
+        let name = PathBuf::from(PENDING);
+        match input.extension() {
+            Some(ext) => name.with_extension(ext),
+            None => name,
+        }
+    }
+
+    /// `path` with the `pending` part of its file name replaced by the next
+    /// number, keeping what was added to it since (`_frame2`, a new
+    /// extension). Paths that are not pending are returned as they are.
+    pub fn claim(&self, path: &Path) -> PathBuf {
+        let name = path.file_name().and_then(OsStr::to_str);
+        let Some(rest) = name.and_then(|name| name.strip_prefix(PENDING)) else {
+            return path.to_path_buf();
+        };
+        let n = self.next.fetch_add(1, Ordering::Relaxed);
+        path.with_file_name(format!("{:0width$}{}", n, rest, width = NUMBER_WIDTH))
+    }
+}
+
+/// The index of a numbered output such as `000042.jpg`, `000042_2.jpg`
+/// (--largest-n) or `000042.json` (sidecar).
+fn number_of(path: &Path) -> Option<u64> {
+    let stem = path.file_stem()?.to_str()?;
+    let digits = stem.split('_').next()?;
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
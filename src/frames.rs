@@ -0,0 +1,62 @@
+//! `--all-frames`: every frame of an animated WebP rather than only the
+//! first.
+
+use anyhow::{Context, Result};
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, DynamicImage};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+fn decoder(path: &Path) -> Option<WebPDecoder<BufReader<File>>> {
+    let is_webp = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("webp"));
+    if !is_webp {
+        return None;
+    }
+    let file = File::open(path).ok()?;
+    WebPDecoder::new(BufReader::new(file)).ok()
+}
+
+/// Whether `path` is an animated WebP. Anything else, including a WebP that
+/// cannot be read, is left to the usual decoder.
+pub fn is_animated(path: &Path) -> bool {
+    decoder(path).is_some_and(|decoder| decoder.has_animation())
+}
+
+/// Call `each` with every frame of the animated WebP at `path`, numbered
+/// from 1, in order. Each frame is the full canvas as it is shown at that
+/// point of the animation, not the possibly smaller patch the file stores
+/// for it. Stops at the first error returned by `each`.
+pub fn for_each_frame(
+    path: &Path,
+    mut each: impl FnMut(usize, DynamicImage) -> Result<()>,
+) -> Result<()> {
+    let decoder = decoder(path).context("Failed to open image")?;
+    for (i, frame) in decoder.into_frames().enumerate() {
+        let frame = frame.with_context(|| format!("Failed to decode frame {}", i + 1))?;
+        let canvas = frame.into_buffer();
+        // Frames always come as RGBA; most animations are opaque, and opaque
+        // crops can be saved as JPEG.
+        let img = if canvas.pixels().all(|p| p[3] == u8::MAX) {
+            DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(canvas).to_rgb8())
+        } else {
+            DynamicImage::ImageRgba8(canvas)
+        };
+        each(i + 1, img)?;
+    }
+    Ok(())
+}
+
+/// Where the crop of frame `n` goes, given the crop path `path` of the
+/// image: `_frame<n>` added to its stem.
+pub fn frame_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!("_frame{}", n));
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    path.with_file_name(name)
+}
@@ -0,0 +1,457 @@
+use clap::ValueEnum;
+use rustface::{FaceInfo, Rectangle};
+
+use crate::quality;
+
+/// How large the crop should be.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum CropStrategy {
+    /// As large as the image allows (the shorter image side)
+    #[default]
+    Fill,
+    /// The smallest crop holding the face plus `--crop-margin`
+    TightestSquare,
+}
+
+/// Which side of the image bounds the size of a square crop.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SquareBasis {
+    /// The shorter side, so the crop always lies within the image
+    #[default]
+    Short,
+    /// The longer side, padding the crop where it reaches past the shorter
+    /// one (needs `--pad-mode`)
+    Long,
+}
+
+/// How the crop is sized around the face; see [`compute_crop_rect`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CropOptions {
+    pub strategy: CropStrategy,
+    /// Fraction of the face size added around it by `TightestSquare`.
+    pub margin: f64,
+    /// Exact border in pixels around the face; overrides `strategy` and
+    /// `margin`.
+    pub pad_pixels: Option<u32>,
+    pub square: bool,
+    /// Round the width and height down to even numbers.
+    pub even: bool,
+    /// Fraction of the face height added above the face box, up to the top
+    /// edge of the image.
+    pub top_extend: f64,
+    /// Shrink the crop near image edges instead of shifting it, so it stays
+    /// centered on the face.
+    pub keep_centered: bool,
+    /// Image side that caps the crop size of `strategy`.
+    pub basis: SquareBasis,
+}
+
+impl CropOptions {
+    /// The options as `key=value` pairs, like `DetectorConfig::describe`.
+    pub fn describe(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("crop_strategy", format!("{:?}", self.strategy)),
+            ("crop_margin", self.margin.to_string()),
+            (
+                "pad_pixels",
+                self.pad_pixels.map(|p| p.to_string()).unwrap_or_default(),
+            ),
+            ("square", self.square.to_string()),
+            ("even", self.even.to_string()),
+            ("top_extend", self.top_extend.to_string()),
+            ("keep_centered", self.keep_centered.to_string()),
+            ("square_basis", format!("{:?}", self.basis)),
+        ]
+    }
+}
+
+/// Where the crop goes, in image pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CropRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Center of the face box the crop was centered on.
+    pub center: (u32, u32),
+    /// Origin that centers the crop on the face, before it was shifted back
+    /// inside the image.
+    pub unclamped_origin: (u32, u32),
+    /// Origin to cut from when the crop may reach past the image edges
+    /// (`--pad-mode`): centered on the face, except that a
+    /// `SquareBasis::Long` crop always holds the whole image.
+    pub padded_origin: (i64, i64),
+}
+
+/// The crop for a face `bbox` in a `width` x `height` image: sized per
+/// `opts`, centered on the face, and shifted as needed to lie within the
+/// image (or, with `keep_centered`, scaled down until it fits around the
+/// face center). Never larger than the image, except for a
+/// `SquareBasis::Long` crop on the shorter side, which then starts at 0 on
+/// that side; it is cut from `padded_origin`.
+pub fn compute_crop_rect(
+    width: u32,
+    height: u32,
+    bbox: &Rectangle,
+    opts: &CropOptions,
+) -> CropRect {
+    let extended;
+    let bbox = if opts.top_extend > 0.0 {
+        extended = extend_top(bbox, opts.top_extend);
+        &extended
+    } else {
+        bbox
+    };
+
+    // Boxes may reach past the image edges; keep the center inside it.
+    let center_x = (bbox.x() as i64 + (bbox.width() / 2) as i64).clamp(0, width as i64) as u32;
+    let center_y = (bbox.y() as i64 + (bbox.height() / 2) as i64).clamp(0, height as i64) as u32;
+
+    let (crop_w, crop_h) = match opts.pad_pixels {
+        Some(pad) => padded_dimensions(bbox, width, height, pad, opts.square),
+        None => {
+            let bounds = match opts.basis {
+                SquareBasis::Short => (width, height),
+                SquareBasis::Long => (width.max(height), width.max(height)),
+            };
+            crop_dimensions(opts.strategy, bbox, bounds, opts.margin, opts.square)
+        }
+    };
+    let (crop_w, crop_h) = if opts.keep_centered {
+        centered_dimensions(crop_w, crop_h, (center_x, center_y), width, height)
+    } else {
+        (crop_w, crop_h)
+    };
+    let (crop_w, crop_h) = if opts.even {
+        (even_down(crop_w), even_down(crop_h))
+    } else {
+        (crop_w, crop_h)
+    };
+
+    let origin_x = center_x.saturating_sub(crop_w / 2);
+    let origin_y = center_y.saturating_sub(crop_h / 2);
+
+    let padded = |center: u32, size: u32, side: u32| {
+        let origin = center as i64 - (size / 2) as i64;
+        match opts.basis {
+            SquareBasis::Long if size >= side => origin.clamp(side as i64 - size as i64, 0),
+            _ => origin,
+        }
+    };
+
+    CropRect {
+        x: origin_x.min(width.saturating_sub(crop_w)),
+        y: origin_y.min(height.saturating_sub(crop_h)),
+        width: crop_w,
+        height: crop_h,
+        center: (center_x, center_y),
+        unclamped_origin: (origin_x, origin_y),
+        padded_origin: (
+            padded(center_x, crop_w, width),
+            padded(center_y, crop_h, height),
+        ),
+    }
+}
+
+/// `bbox` grown upward by `fraction` of its height, stopping at the top edge
+/// of the image (or at its own top, if that is already above the image).
+fn extend_top(bbox: &Rectangle, fraction: f64) -> Rectangle {
+    let extra = (bbox.height() as f64 * fraction).round() as i32;
+    let top = (bbox.y() - extra).max(bbox.y().min(0));
+    let bottom = bbox.y() + bbox.height() as i32;
+    Rectangle::new(bbox.x(), top, bbox.width(), (bottom - top) as u32)
+}
+
+/// Width and height of the crop for a face `bbox`, at most `width` x
+/// `height` (the image, or the square on its longer side).
+///
+/// With `square` the crop is a square; otherwise it keeps the aspect ratio of
+/// `bbox`. Either way it is scaled down, if needed, to fit inside those
+/// bounds.
+fn crop_dimensions(
+    strategy: CropStrategy,
+    bbox: &Rectangle,
+    (width, height): (u32, u32),
+    margin: f64,
+    square: bool,
+) -> (u32, u32) {
+    let (face_w, face_h) = face_dimensions(bbox, square);
+
+    // Largest uniform scale of the face box that still fits in the image.
+    let max_scale = (width as f64 / face_w).min(height as f64 / face_h);
+    let scale = match strategy {
+        CropStrategy::Fill => max_scale,
+        CropStrategy::TightestSquare => (1.0 + margin).min(max_scale),
+    };
+    let (crop_w, crop_h) = (face_w * scale, face_h * scale);
+
+    (
+        (crop_w.round() as u32).clamp(1, width),
+        (crop_h.round() as u32).clamp(1, height),
+    )
+}
+
+/// Width and height of a crop extending `pad` pixels beyond the face `bbox`
+/// on every side, scaled down uniformly if that does not fit the image.
+fn padded_dimensions(
+    bbox: &Rectangle,
+    width: u32,
+    height: u32,
+    pad: u32,
+    square: bool,
+) -> (u32, u32) {
+    let (face_w, face_h) = face_dimensions(bbox, square);
+    let (crop_w, crop_h) = (face_w + 2.0 * pad as f64, face_h + 2.0 * pad as f64);
+    let scale = (width as f64 / crop_w).min(height as f64 / crop_h).min(1.0);
+
+    (
+        ((crop_w * scale).round() as u32).clamp(1, width),
+        ((crop_h * scale).round() as u32).clamp(1, height),
+    )
+}
+
+/// `crop_w` x `crop_h` scaled down uniformly, if needed, so that a crop
+/// centered on `center` stays inside a `width` x `height` image.
+fn centered_dimensions(
+    crop_w: u32,
+    crop_h: u32,
+    center: (u32, u32),
+    width: u32,
+    height: u32,
+) -> (u32, u32) {
+    let room_w = 2 * center.0.min(width - center.0);
+    let room_h = 2 * center.1.min(height - center.1);
+    let scale = (room_w as f64 / crop_w as f64)
+        .min(room_h as f64 / crop_h as f64)
+        .min(1.0);
+
+    (
+        ((crop_w as f64 * scale) as u32).max(1),
+        ((crop_h as f64 * scale) as u32).max(1),
+    )
+}
+
+/// Size of the region to frame: `bbox` itself, or a square on its longer
+/// side when `square` is set.
+fn face_dimensions(bbox: &Rectangle, square: bool) -> (f64, f64) {
+    if square {
+        let side = bbox.width().max(bbox.height()).max(1) as f64;
+        (side, side)
+    } else {
+        (bbox.width().max(1) as f64, bbox.height().max(1) as f64)
+    }
+}
+
+/// The smallest box holding every face in `faces`, clipped to a `width` x
+/// `height` image. `None` if `faces` is empty or lies wholly outside.
+pub fn union_box(faces: &[FaceInfo], width: u32, height: u32) -> Option<Rectangle> {
+    let boxes = faces.iter().map(FaceInfo::bbox);
+    let left = boxes.clone().map(|b| b.x() as i64).min()?;
+    let top = boxes.clone().map(|b| b.y() as i64).min()?;
+    let right = boxes
+        .clone()
+        .map(|b| b.x() as i64 + b.width() as i64)
+        .max()?;
+    let bottom = boxes.map(|b| b.y() as i64 + b.height() as i64).max()?;
+
+    let union = Rectangle::new(
+        left as i32,
+        top as i32,
+        (right - left) as u32,
+        (bottom - top) as u32,
+    );
+    let (x, y, w, h) = quality::clip_to_image(&union, width, height)?;
+    Some(Rectangle::new(x as i32, y as i32, w, h))
+}
+
+/// How central `bbox` is in a `width` x `height` image: 1 at the image
+/// center, falling linearly with distance to 0 at the corners.
+pub fn centrality(bbox: &Rectangle, (width, height): (u32, u32)) -> f64 {
+    let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+    let half_diagonal = cx.hypot(cy).max(1.0);
+    let (x, y) = center_of(bbox);
+    1.0 - ((x - cx).hypot(y - cy) / half_diagonal).min(1.0)
+}
+
+/// Center point of `bbox`.
+pub fn center_of(bbox: &Rectangle) -> (f64, f64) {
+    (
+        bbox.x() as f64 + bbox.width() as f64 / 2.0,
+        bbox.y() as f64 + bbox.height() as f64 / 2.0,
+    )
+}
+
+/// A `width` x `height` box centered on `center`.
+pub fn box_around(center: (f64, f64), width: u32, height: u32) -> Rectangle {
+    Rectangle::new(
+        (center.0 - width as f64 / 2.0).round() as i32,
+        (center.1 - height as f64 / 2.0).round() as i32,
+        width,
+        height,
+    )
+}
+
+/// Round `dim` down to an even number, for encoders that reject odd sizes.
+/// A 1px dimension is left alone since it cannot be made even.
+pub fn even_down(dim: u32) -> u32 {
+    if dim > 1 { dim & !1 } else { dim }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The options `crop_options` gives for a plain run.
+    fn defaults() -> CropOptions {
+        CropOptions {
+            strategy: CropStrategy::Fill,
+            margin: 0.2,
+            pad_pixels: None,
+            square: true,
+            even: false,
+            top_extend: 0.0,
+            keep_centered: false,
+            basis: SquareBasis::Short,
+        }
+    }
+
+    fn tightest(margin: f64) -> CropOptions {
+        CropOptions {
+            strategy: CropStrategy::TightestSquare,
+            margin,
+            ..defaults()
+        }
+    }
+
+    /// The crop of the original single-face version: a square on the
+    /// shorter side, centered on the face and shifted back inside.
+    fn baseline(width: u32, height: u32, bbox: &Rectangle) -> (u32, u32, u32) {
+        let size = width.min(height);
+        let center_x = bbox.x() as u32 + bbox.width() / 2;
+        let center_y = bbox.y() as u32 + bbox.height() / 2;
+        let x = center_x.saturating_sub(size / 2).min(width - size);
+        let y = center_y.saturating_sub(size / 2).min(height - size);
+        (x, y, size)
+    }
+
+    fn assert_inside(rect: &CropRect, width: u32, height: u32) {
+        assert!(rect.width >= 1 && rect.height >= 1, "{:?}", rect);
+        assert!(rect.x + rect.width <= width, "{:?} in {}px", rect, width);
+        assert!(rect.y + rect.height <= height, "{:?} in {}px", rect, height);
+    }
+
+    #[test]
+    fn fill_matches_baseline() {
+        for (width, height) in [(640, 480), (480, 640), (300, 300), (1001, 37), (2, 3)] {
+            let face = (width.min(height) / 4).max(1);
+            for fx in [0, width / 3, width / 2, width - face] {
+                for fy in [0, height / 3, height / 2, height - face] {
+                    let bbox = Rectangle::new(fx as i32, fy as i32, face, face);
+                    let rect = compute_crop_rect(width, height, &bbox, &defaults());
+                    let (x, y, size) = baseline(width, height, &bbox);
+                    assert_eq!(
+                        (rect.x, rect.y, rect.width, rect.height),
+                        (x, y, size, size),
+                        "{}x{} image, {:?}",
+                        width,
+                        height,
+                        bbox
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn faces_in_corners_are_shifted_inside() {
+        let (width, height) = (1000, 800);
+        let corners = [(0, 0), (900, 0), (0, 700), (900, 700)];
+        for (fx, fy) in corners {
+            let bbox = Rectangle::new(fx, fy, 100, 100);
+            let rect = compute_crop_rect(width, height, &bbox, &tightest(0.5));
+            assert_eq!((rect.width, rect.height), (150, 150));
+            assert_inside(&rect, width, height);
+            // Pushed against the corner the face is in.
+            assert_eq!(rect.x, if fx == 0 { 0 } else { width - 150 });
+            assert_eq!(rect.y, if fy == 0 { 0 } else { height - 150 });
+            assert_eq!(rect.center, (fx as u32 + 50, fy as u32 + 50));
+        }
+    }
+
+    #[test]
+    fn faces_past_the_corners_are_clamped() {
+        let (width, height) = (1000, 800);
+        let corners = [(-60, -60), (960, -60), (-60, 760), (960, 760)];
+        for (fx, fy) in corners {
+            let bbox = Rectangle::new(fx, fy, 100, 100);
+            for opts in [defaults(), tightest(0.5)] {
+                let rect = compute_crop_rect(width, height, &bbox, &opts);
+                assert_inside(&rect, width, height);
+                assert!(rect.center.0 <= width && rect.center.1 <= height);
+            }
+        }
+    }
+
+    #[test]
+    fn face_larger_than_the_crop_fits_the_image() {
+        let bbox = Rectangle::new(-100, -200, 800, 900);
+        let rect = compute_crop_rect(400, 300, &bbox, &tightest(0.2));
+        assert_eq!((rect.width, rect.height), (300, 300));
+        assert_inside(&rect, 400, 300);
+
+        let mut opts = tightest(0.2);
+        opts.square = false;
+        let rect = compute_crop_rect(400, 300, &bbox, &opts);
+        // Keeps the face's 8:9 shape, scaled to the image height.
+        assert_eq!((rect.width, rect.height), (267, 300));
+        assert_inside(&rect, 400, 300);
+    }
+
+    #[test]
+    fn tiny_images() {
+        for (width, height) in [(1, 1), (1, 5), (5, 1), (2, 2)] {
+            let bbox = Rectangle::new(0, 0, width, height);
+            for mut opts in [defaults(), tightest(3.0)] {
+                let rect = compute_crop_rect(width, height, &bbox, &opts);
+                assert_inside(&rect, width, height);
+                opts.even = true;
+                opts.keep_centered = true;
+                let rect = compute_crop_rect(width, height, &bbox, &opts);
+                assert_inside(&rect, width, height);
+            }
+        }
+        // A zero-sized face still gives a pixel.
+        let rect = compute_crop_rect(3, 3, &Rectangle::new(1, 1, 0, 0), &tightest(0.0));
+        assert_eq!((rect.width, rect.height), (1, 1));
+    }
+
+    #[test]
+    fn extreme_padding() {
+        let (width, height) = (640, 480);
+        let bbox = Rectangle::new(270, 190, 100, 100);
+        let padded = |pad| CropOptions {
+            pad_pixels: Some(pad),
+            ..defaults()
+        };
+
+        let rect = compute_crop_rect(width, height, &bbox, &padded(0));
+        assert_eq!(
+            (rect.x, rect.y, rect.width, rect.height),
+            (270, 190, 100, 100)
+        );
+
+        for pad in [1_000, u32::MAX / 4, u32::MAX] {
+            let rect = compute_crop_rect(width, height, &bbox, &padded(pad));
+            assert_eq!((rect.width, rect.height), (480, 480), "pad {}", pad);
+            assert_inside(&rect, width, height);
+        }
+
+        let rect = compute_crop_rect(width, height, &bbox, &tightest(0.0));
+        assert_eq!((rect.width, rect.height), (100, 100));
+        for margin in [100.0, 1e12, f64::MAX] {
+            let rect = compute_crop_rect(width, height, &bbox, &tightest(margin));
+            assert_eq!((rect.width, rect.height), (480, 480), "margin {}", margin);
+            assert_inside(&rect, width, height);
+        }
+    }
+}
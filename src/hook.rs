@@ -0,0 +1,139 @@
+use anyhow::{Result, bail};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The `--exec` command, started once for every saved crop.
+///
+/// The command line is split into words up front and each word has its
+/// placeholders filled in separately, so it is run directly rather than
+/// through a shell and file names can never be read as shell syntax.
+pub struct Hook {
+    words: Vec<String>,
+    max_running: usize,
+    running: Mutex<Vec<Running>>,
+    failures: AtomicUsize,
+}
+
+struct Running {
+    child: Child,
+    output: PathBuf,
+}
+
+impl Hook {
+    /// A hook for `command`, with at most `max_running` copies running at
+    /// once.
+    pub fn new(command: &str, max_running: usize) -> Result<Self> {
+        let words = split_words(command)?;
+        if words.is_empty() {
+            bail!("--exec command is empty");
+        }
+        Ok(Hook {
+            words,
+            max_running: max_running.max(1),
+            running: Mutex::new(Vec::new()),
+            failures: AtomicUsize::new(0),
+        })
+    }
+
+    /// Start the command for the crop `output` of `input`, first waiting for
+    /// the oldest one still running if the limit is reached. Failures are
+    /// logged rather than returned: the crop itself was saved.
+    pub fn run(&self, input: &Path, output: &Path, score: f64) {
+        let fill = |word: &str| {
+            word.replace("{input}", &input.to_string_lossy())
+                .replace("{output}", &output.to_string_lossy())
+                .replace("{score}", &score.to_string())
+        };
+        let words: Vec<String> = self.words.iter().map(|word| fill(word)).collect();
+
+        let mut running = self.running.lock().unwrap();
+        running.retain_mut(|r| match r.child.try_wait() {
+            Ok(Some(status)) => {
+                self.report(&r.output, Ok(status));
+                false
+            }
+            Ok(None) => true,
+            Err(e) => {
+                self.report(&r.output, Err(e));
+                false
+            }
+        });
+        while running.len() >= self.max_running {
+            let mut oldest = running.remove(0);
+            self.report(&oldest.output, oldest.child.wait());
+        }
+
+        match Command::new(&words[0]).args(&words[1..]).spawn() {
+            Ok(child) => running.push(Running {
+                child,
+                output: output.to_path_buf(),
+            }),
+            Err(e) => self.report(output, Err(e)),
+        }
+    }
+
+    /// Wait for the commands still running and say how many failed.
+    pub fn finish(mut self) {
+        for mut r in std::mem::take(self.running.get_mut().unwrap()) {
+            let status = r.child.wait();
+            self.report(&r.output, status);
+        }
+
+        let failures = self.failures.into_inner();
+        if failures > 0 {
+            eprintln!("{} --exec command(s) failed.", failures);
+        }
+    }
+
+    fn report(&self, output: &Path, status: std::io::Result<ExitStatus>) {
+        let problem = match status {
+            Ok(status) if status.success() => return,
+            Ok(status) => status.to_string(),
+            Err(e) => e.to_string(),
+        };
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        eprintln!("--exec command failed for {:?}: {}", output, problem);
+    }
+}
+
+/// Split `command` into words at whitespace, honouring single and double
+/// quotes and backslash escapes outside single quotes.
+fn split_words(command: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('\''), c) => word.push(c),
+            (_, '\\') => match chars.next() {
+                Some(escaped) => word.push(escaped),
+                None => bail!("--exec command ends with a lone backslash"),
+            },
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => quote = Some(c),
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+                continue;
+            }
+            (None, c) => word.push(c),
+        }
+        in_word = true;
+    }
+
+    if quote.is_some() {
+        bail!("--exec command has an unterminated quote");
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
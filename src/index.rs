@@ -0,0 +1,65 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::sidecar::Region;
+
+/// Closes the object after the last entry; overwritten by the next one.
+const CLOSING: &[u8] = b"}\n";
+
+/// Where one crop came from, as listed in `--index-json`.
+#[derive(Debug, Serialize)]
+pub struct IndexEntry {
+    pub source: PathBuf,
+    /// Index of the face in the image's detections, in detector order.
+    pub face: usize,
+    pub score: f64,
+    /// Composite quality score of the face (see `quality::face_quality`).
+    pub quality: f64,
+    /// The cropped rectangle, in source image pixels.
+    pub crop: Region,
+}
+
+/// The `--index-json` file: one JSON object mapping every crop written in
+/// the run to its source.
+///
+/// The object is closed again after each entry, so the file is valid JSON
+/// for everything cropped so far even if the run is interrupted.
+pub struct OutputIndex {
+    file: Mutex<(File, usize)>,
+}
+
+impl OutputIndex {
+    pub fn create(path: &Path) -> Result<Self> {
+        let mut file = File::create(path).context("Failed to create index file")?;
+        file.write_all(b"{\n")
+            .and_then(|()| file.write_all(CLOSING))
+            .context("Failed to write index file")?;
+        Ok(OutputIndex {
+            file: Mutex::new((file, 0)),
+        })
+    }
+
+    /// List the crop saved as `output`.
+    pub fn add(&self, output: &str, entry: &IndexEntry) -> Result<()> {
+        let key = serde_json::to_string(output).context("Failed to encode index entry")?;
+        let value = serde_json::to_string(entry).context("Failed to encode index entry")?;
+
+        let mut guard = self.file.lock().unwrap();
+        let (file, entries) = &mut *guard;
+        // Write over the line break before the closing brace, which the
+        // record ends with again.
+        let separator = if *entries == 0 { "\n" } else { ",\n" };
+        let mut record = format!("{}  {}: {}\n", separator, key, value).into_bytes();
+        record.extend(CLOSING);
+        file.seek(SeekFrom::End(-(CLOSING.len() as i64 + 1)))
+            .and_then(|_| file.write_all(&record))
+            .and_then(|()| file.flush())
+            .context("Failed to write index file")?;
+        *entries += 1;
+        Ok(())
+    }
+}
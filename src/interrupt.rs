@@ -0,0 +1,57 @@
+//! `--interrupt-safe`: Ctrl-C finishes the images being cropped instead of
+//! aborting mid-save.
+
+use anyhow::{Context, Result};
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by the first Ctrl-C; checked before each image is started.
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+static INSTALL: Once = Once::new();
+
+/// Printed by the handler itself, which may only make async-signal-safe
+/// calls.
+#[cfg(unix)]
+const NOTICE: &[u8] =
+    b"\nInterrupted: finishing the current image(s). Press Ctrl-C again to stop immediately.\n";
+
+/// Catch SIGINT from now on. The first one asks the run to stop after the
+/// images in flight; on unix a second one exits at once with status 130, as
+/// an uncaught Ctrl-C would. Installing more than once (job files) is a
+/// no-op.
+pub fn install() -> Result<()> {
+    let mut result = Ok(());
+    INSTALL.call_once(|| {
+        // SAFETY: `on_interrupt` only makes async-signal-safe calls.
+        let registered = unsafe { signal_hook_registry::register(libc::SIGINT, on_interrupt) };
+        result = registered
+            .map(drop)
+            .context("Failed to install the Ctrl-C handler");
+    });
+    result
+}
+
+/// Touches only an atomic and calls write(2) and _exit(2), which are
+/// async-signal-safe.
+#[cfg(unix)]
+fn on_interrupt() {
+    if REQUESTED.swap(true, Ordering::SeqCst) {
+        // SAFETY: _exit(2) takes no locks and runs no destructors.
+        unsafe { libc::_exit(130) };
+    }
+    // SAFETY: NOTICE is a valid buffer of the length passed.
+    unsafe { libc::write(2, NOTICE.as_ptr().cast(), NOTICE.len()) };
+}
+
+/// Without write(2) and _exit(2) the handler can only set the flag, so
+/// further Ctrl-Cs are ignored until the run stops.
+#[cfg(not(unix))]
+fn on_interrupt() {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Whether Ctrl-C was pressed since `install`.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
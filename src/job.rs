@@ -0,0 +1,165 @@
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::net;
+
+/// Settings that have their own place in a job file rather than under
+/// `settings`.
+const RESERVED: &[&str] = &["input", "output", "job"];
+
+/// A `--job` file: the inputs to process and the flags to process them with.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    /// Flags for every input, by long name without the leading `--`.
+    #[serde(default)]
+    settings: Map<String, Value>,
+    /// Output directory for inputs that do not name their own `output`.
+    output: Option<PathBuf>,
+    inputs: Vec<JobInput>,
+}
+
+/// One input of a job, processed like a separate run with `--input`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobInput {
+    pub input: PathBuf,
+    /// Used as `--output`.
+    pub output: Option<PathBuf>,
+    /// Flags for this input only, replacing the job-wide ones of the same
+    /// name.
+    #[serde(default)]
+    settings: Map<String, Value>,
+}
+
+impl Job {
+    /// Read a job from YAML (`.yaml`, `.yml`) or JSON (`.json`).
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read job file {:?}", path))?;
+        let job: Job = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => serde_yaml::from_str(&text).map_err(anyhow::Error::from),
+            Some("json") => serde_json::from_str(&text).map_err(anyhow::Error::from),
+            _ => bail!("Job file {:?} must end in .yaml, .yml or .json", path),
+        }
+        .with_context(|| format!("Failed to parse job file {:?}", path))?;
+
+        job.validate()
+            .with_context(|| format!("Invalid job file {:?}", path))?;
+        Ok(job)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.inputs.is_empty() {
+            bail!("inputs: at least one input is required");
+        }
+        let all_settings =
+            std::iter::once(&self.settings).chain(self.inputs.iter().map(|input| &input.settings));
+        for settings in all_settings {
+            for (key, value) in settings {
+                if RESERVED.contains(&flag_name(key).as_str()) {
+                    bail!("settings: `{}` cannot be set here", key);
+                }
+                if let Value::Object(_) = value {
+                    bail!("settings: `{}` must be a value or a list of values", key);
+                }
+                if let Value::Array(values) = value
+                    && values.iter().any(|v| v.is_array() || v.is_object())
+                {
+                    bail!("settings: `{}` must be a flat list of values", key);
+                }
+            }
+        }
+        for (i, input) in self.inputs.iter().enumerate() {
+            if input.input.as_os_str().is_empty() {
+                bail!("inputs[{}]: input must not be empty", i);
+            }
+            // A URL's crop is named after the downloaded format, which is
+            // not known yet.
+            if self.output.is_some() && input.output.is_none() && net::is_url(&input.input) {
+                bail!("inputs[{}]: URL inputs need their own output", i);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn inputs(&self) -> &[JobInput] {
+        &self.inputs
+    }
+
+    /// The directory a file input that names no `output` of its own saves
+    /// its crop in, under its default crop name.
+    pub fn output_dir_for(&self, input: &JobInput) -> Option<&Path> {
+        match (&input.output, &self.output) {
+            (None, Some(dir)) if !input.input.is_dir() => Some(dir),
+            _ => None,
+        }
+    }
+
+    /// The command line for `input`: the job-wide settings, then the input's
+    /// own, then `cli` (the flags given next to `--job`, which win over
+    /// both). Inputs with an `output_dir_for` get no `--output`; their crop
+    /// name depends on the parsed settings.
+    pub fn command_line(&self, input: &JobInput, cli: &[OsString]) -> Vec<OsString> {
+        let settings: Map<String, Value> = self
+            .settings
+            .iter()
+            .chain(&input.settings)
+            .map(|(key, value)| (flag_name(key), value.clone()))
+            .collect();
+
+        let mut line: Vec<OsString> = vec!["--input".into(), input.input.clone().into()];
+        let output = match (&input.output, &self.output) {
+            (Some(output), _) => Some(output),
+            (None, Some(dir)) if input.input.is_dir() => Some(dir),
+            _ => None,
+        };
+        if let Some(output) = output {
+            line.extend(["--output".into(), output.into()]);
+        }
+
+        for (key, value) in &settings {
+            let flag = format!("--{}", key);
+            let values = match value {
+                Value::Array(values) => values.as_slice(),
+                value => std::slice::from_ref(value),
+            };
+            for value in values {
+                match value {
+                    Value::Bool(true) => line.push(flag.clone().into()),
+                    Value::Bool(false) | Value::Null => {}
+                    Value::String(s) => line.extend([flag.clone().into(), s.into()]),
+                    value => line.extend([flag.clone().into(), value.to_string().into()]),
+                }
+            }
+        }
+
+        line.extend(cli.iter().cloned());
+        line
+    }
+}
+
+/// The command-line arguments after the program name, without `--job` and
+/// its value.
+pub fn cli_without_job(args: impl IntoIterator<Item = OsString>) -> Vec<OsString> {
+    let mut rest = Vec::new();
+    let mut args = args.into_iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--job" {
+            args.next();
+        } else if !arg.to_string_lossy().starts_with("--job=") {
+            rest.push(arg);
+        }
+    }
+    rest
+}
+
+/// `key` as a long flag name: `largest_n` and `largest-n` both become
+/// `largest-n`.
+fn flag_name(key: &str) -> String {
+    key.trim_start_matches("--").replace('_', "-")
+}
@@ -0,0 +1,158 @@
+//! `--label`: the source file name, or a template, drawn onto each crop.
+
+use clap::ValueEnum;
+use image::{DynamicImage, GenericImage, Rgba};
+use std::ffi::OsStr;
+use std::path::Path;
+
+/// Columns of the glyphs for `' '` to `'~'`, 5 per glyph with the lowest
+/// bit as the top row: the classic 5x7 LCD font, so no font file has to be
+/// shipped or found at runtime.
+#[rustfmt::skip]
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5f, 0x00, 0x00],
+    [0x00, 0x07, 0x00, 0x07, 0x00], [0x14, 0x7f, 0x14, 0x7f, 0x14],
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62],
+    [0x36, 0x49, 0x55, 0x22, 0x50], [0x00, 0x05, 0x03, 0x00, 0x00],
+    [0x00, 0x1c, 0x22, 0x41, 0x00], [0x00, 0x41, 0x22, 0x1c, 0x00],
+    [0x08, 0x2a, 0x1c, 0x2a, 0x08], [0x08, 0x08, 0x3e, 0x08, 0x08],
+    [0x00, 0x50, 0x30, 0x00, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08],
+    [0x00, 0x60, 0x60, 0x00, 0x00], [0x20, 0x10, 0x08, 0x04, 0x02],
+    [0x3e, 0x51, 0x49, 0x45, 0x3e], [0x00, 0x42, 0x7f, 0x40, 0x00],
+    [0x42, 0x61, 0x51, 0x49, 0x46], [0x21, 0x41, 0x45, 0x4b, 0x31],
+    [0x18, 0x14, 0x12, 0x7f, 0x10], [0x27, 0x45, 0x45, 0x45, 0x39],
+    [0x3c, 0x4a, 0x49, 0x49, 0x30], [0x01, 0x71, 0x09, 0x05, 0x03],
+    [0x36, 0x49, 0x49, 0x49, 0x36], [0x06, 0x49, 0x49, 0x29, 0x1e],
+    [0x00, 0x36, 0x36, 0x00, 0x00], [0x00, 0x56, 0x36, 0x00, 0x00],
+    [0x00, 0x08, 0x14, 0x22, 0x41], [0x14, 0x14, 0x14, 0x14, 0x14],
+    [0x41, 0x22, 0x14, 0x08, 0x00], [0x02, 0x01, 0x51, 0x09, 0x06],
+    [0x32, 0x49, 0x79, 0x41, 0x3e], [0x7e, 0x11, 0x11, 0x11, 0x7e],
+    [0x7f, 0x49, 0x49, 0x49, 0x36], [0x3e, 0x41, 0x41, 0x41, 0x22],
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], [0x7f, 0x49, 0x49, 0x49, 0x41],
+    [0x7f, 0x09, 0x09, 0x01, 0x01], [0x3e, 0x41, 0x41, 0x51, 0x32],
+    [0x7f, 0x08, 0x08, 0x08, 0x7f], [0x00, 0x41, 0x7f, 0x41, 0x00],
+    [0x20, 0x40, 0x41, 0x3f, 0x01], [0x7f, 0x08, 0x14, 0x22, 0x41],
+    [0x7f, 0x40, 0x40, 0x40, 0x40], [0x7f, 0x02, 0x04, 0x02, 0x7f],
+    [0x7f, 0x04, 0x08, 0x10, 0x7f], [0x3e, 0x41, 0x41, 0x41, 0x3e],
+    [0x7f, 0x09, 0x09, 0x09, 0x06], [0x3e, 0x41, 0x51, 0x21, 0x5e],
+    [0x7f, 0x09, 0x19, 0x29, 0x46], [0x46, 0x49, 0x49, 0x49, 0x31],
+    [0x01, 0x01, 0x7f, 0x01, 0x01], [0x3f, 0x40, 0x40, 0x40, 0x3f],
+    [0x1f, 0x20, 0x40, 0x20, 0x1f], [0x7f, 0x20, 0x18, 0x20, 0x7f],
+    [0x63, 0x14, 0x08, 0x14, 0x63], [0x03, 0x04, 0x78, 0x04, 0x03],
+    [0x61, 0x51, 0x49, 0x45, 0x43], [0x00, 0x00, 0x7f, 0x41, 0x41],
+    [0x02, 0x04, 0x08, 0x10, 0x20], [0x41, 0x41, 0x7f, 0x00, 0x00],
+    [0x04, 0x02, 0x01, 0x02, 0x04], [0x40, 0x40, 0x40, 0x40, 0x40],
+    [0x00, 0x01, 0x02, 0x04, 0x00], [0x20, 0x54, 0x54, 0x54, 0x78],
+    [0x7f, 0x48, 0x44, 0x44, 0x38], [0x38, 0x44, 0x44, 0x44, 0x20],
+    [0x38, 0x44, 0x44, 0x48, 0x7f], [0x38, 0x54, 0x54, 0x54, 0x18],
+    [0x08, 0x7e, 0x09, 0x01, 0x02], [0x08, 0x14, 0x54, 0x54, 0x3c],
+    [0x7f, 0x08, 0x04, 0x04, 0x78], [0x00, 0x44, 0x7d, 0x40, 0x00],
+    [0x20, 0x40, 0x44, 0x3d, 0x00], [0x00, 0x7f, 0x10, 0x28, 0x44],
+    [0x00, 0x41, 0x7f, 0x40, 0x00], [0x7c, 0x04, 0x18, 0x04, 0x78],
+    [0x7c, 0x08, 0x04, 0x04, 0x78], [0x38, 0x44, 0x44, 0x44, 0x38],
+    [0x7c, 0x14, 0x14, 0x14, 0x08], [0x08, 0x14, 0x14, 0x18, 0x7c],
+    [0x7c, 0x08, 0x04, 0x04, 0x08], [0x48, 0x54, 0x54, 0x54, 0x20],
+    [0x04, 0x3f, 0x44, 0x40, 0x20], [0x3c, 0x40, 0x40, 0x20, 0x7c],
+    [0x1c, 0x20, 0x40, 0x20, 0x1c], [0x3c, 0x40, 0x30, 0x40, 0x3c],
+    [0x44, 0x28, 0x10, 0x28, 0x44], [0x0c, 0x50, 0x50, 0x50, 0x3c],
+    [0x44, 0x64, 0x54, 0x4c, 0x44], [0x00, 0x08, 0x36, 0x41, 0x00],
+    [0x00, 0x00, 0x7f, 0x00, 0x00], [0x00, 0x41, 0x36, 0x08, 0x00],
+    [0x08, 0x04, 0x08, 0x10, 0x08],
+];
+
+/// Font cell in glyph pixels: 5x7 glyphs plus a column and a row of space.
+const CELL: (u32, u32) = (6, 8);
+
+/// Which corner of the crop `--label` is drawn in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LabelPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// How `--label` text is drawn.
+pub struct LabelStyle {
+    pub position: LabelPosition,
+    pub color: [u8; 3],
+    /// Line height in pixels, at least one font cell.
+    pub size: u32,
+    /// Strip behind the text, across the full width of the crop.
+    pub background: Option<[u8; 3]>,
+}
+
+/// `template` with `{name}`, `{stem}`, `{face}` and `{score}` filled in for
+/// face `face` of `input`.
+pub fn fill(template: &str, input: &Path, face: usize, score: f64) -> String {
+    let part = |part: Option<&OsStr>| part.unwrap_or_default().to_string_lossy().into_owned();
+    template
+        .replace("{name}", &part(input.file_name()))
+        .replace("{stem}", &part(input.file_stem()))
+        .replace("{face}", &face.to_string())
+        .replace("{score}", &format!("{:.2}", score))
+}
+
+/// Draw `text` on one line in a corner of `img`. Characters outside
+/// printable ASCII show as `?`, and text too long for the crop is cut off
+/// with `...`.
+pub fn draw(img: &mut DynamicImage, text: &str, style: &LabelStyle) {
+    let scale = style.size as f64 / CELL.1 as f64;
+    let margin = (style.size / 4).max(1);
+    let (width, height) = (img.width(), img.height());
+    let advance = CELL.0 as f64 * scale;
+
+    let mut chars: Vec<char> = text
+        .chars()
+        .map(|c| if (' '..='~').contains(&c) { c } else { '?' })
+        .collect();
+    let fits = (width.saturating_sub(2 * margin) as f64 / advance) as usize;
+    if chars.len() > fits {
+        chars.truncate(fits.saturating_sub(3));
+        chars.extend("...".chars().take(fits));
+    }
+    // The last column of space is left out of the text width.
+    let text_width = (chars.len() as f64 * advance - scale).max(0.0).round() as u32;
+
+    let strip = style.size + 2 * margin;
+    let top = match style.position {
+        LabelPosition::TopLeft | LabelPosition::TopRight => 0,
+        LabelPosition::BottomLeft | LabelPosition::BottomRight => height.saturating_sub(strip),
+    };
+    let left = match style.position {
+        LabelPosition::TopLeft | LabelPosition::BottomLeft => margin,
+        LabelPosition::TopRight | LabelPosition::BottomRight => {
+            width.saturating_sub(margin + text_width)
+        }
+    };
+
+    if let Some([r, g, b]) = style.background {
+        for y in top..(top + strip).min(height) {
+            for x in 0..width {
+                img.put_pixel(x, y, Rgba([r, g, b, u8::MAX]));
+            }
+        }
+    }
+
+    let [r, g, b] = style.color;
+    // Glyph pixel `at` along one axis covers output pixels `span(at)`.
+    let span = |at: u32| (at as f64 * scale) as u32..((at + 1) as f64 * scale) as u32;
+    for (i, c) in chars.iter().enumerate() {
+        let glyph = FONT[*c as usize - ' ' as usize];
+        for (column, bits) in glyph.iter().enumerate() {
+            for row in 0..7 {
+                if bits & (1 << row) == 0 {
+                    continue;
+                }
+                for y in span(row) {
+                    for x in span(i as u32 * CELL.0 + column as u32) {
+                        let (x, y) = (left + x, top + margin + y);
+                        if x < width && y < height {
+                            img.put_pixel(x, y, Rgba([r, g, b, u8::MAX]));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
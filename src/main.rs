@@ -1,207 +1,3617 @@
 use anyhow::{Context, Result, bail};
-use clap::Parser;
-use image::GenericImageView;
-use rustface::{FaceInfo, ImageData};
-use std::ffi::OsStr;
+use chrono::{DateTime, Local};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, GrayImage, ImageFormat};
+use rustface::{FaceInfo, Rectangle};
+use std::ffi::{OsStr, OsString};
+use std::fmt::Display;
 use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Once, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod annotations;
+mod archive;
+mod batch;
+mod burst;
+mod chroma;
+mod cluster;
+mod config;
+mod debug;
+mod dedupe;
+mod deskew;
+mod detection;
+mod explain;
+mod fallback;
+mod filename;
+mod frames;
+mod geometry;
+mod hook;
+mod index;
+mod interrupt;
+mod job;
+mod label;
+mod manifest;
+mod metrics;
+mod models;
+mod net;
+mod output;
+mod padding;
+mod placeholder;
+mod prefetch;
+mod preview;
+mod profile;
+mod provenance;
+mod quality;
+mod raw;
+mod report;
+mod s3;
+mod scores;
+mod selection;
+mod selftest;
+mod sidecar;
+mod stats;
+mod summary;
+mod sweep;
+mod template;
+mod video;
+
+use annotations::{AnnotationFormat, Annotations};
+use archive::{TarOutput, ZipOutput};
+use batch::{OutputNaming, ParallelGranularity, ProgressEvent};
+use burst::BurstSmoother;
+use chroma::ChromaKey;
+use cluster::Clusterer;
+use config::Config;
+use dedupe::Deduper;
+use detection::{Backend, DetectorConfig, FaceDetector, GrayConversion};
+use filename::{NameRegistry, Numbering};
+use geometry::{CropOptions, CropRect, CropStrategy, SquareBasis};
+use hook::Hook;
+use index::{IndexEntry, OutputIndex};
+use job::Job;
+use label::{LabelPosition, LabelStyle};
+use manifest::ManifestFormat;
+use models::ModelSet;
+use output::{BitDepth, ColorMode, DataUriFormat, MaskFormat, SaveOptions};
+use padding::PadMode;
+use prefetch::Prefetched;
+use preview::TermPreview;
+use profile::Phase;
+use quality::{FaceQuality, QualityWeights};
+use s3::S3Output;
+use scores::ScoresArgs;
+use selection::SelectStrategy;
+use sidecar::{Region, Sidecar};
+use stats::ValidationError;
+use sweep::SweepArgs;
+use template::DateSource;
+
+/// Wait before the first save retry; doubled for each further one.
+const SAVE_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Largest --detect-upscale-factor, which keeps the detection copy of a
+/// small image small.
+const MAX_UPSCALE_FACTOR: f64 = 8.0;
+
+/// Overlap (IoU) of two crop rectangles above which --reject-duplicate-output
+/// takes the later one for a copy of the earlier.
+const DUPLICATE_CROP_IOU: f64 = 0.8;
+
+/// Printed at most once per run, by whichever image first needs landmarks.
+static NO_LANDMARKS_WARNING: Once = Once::new();
+
+/// Like `NO_LANDMARKS_WARNING`, for --frame-from-landmarks.
+static NO_FRAMING_LANDMARKS_WARNING: Once = Once::new();
 
 // 1. Embed the model bytes into the binary at compile time.
 const MODEL_BYTES: &[u8] = include_bytes!("../models/seeta_fd_frontal_v1.0.bin");
 
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    /// Input path (can be a single image file or a directory)
-    #[arg(short, long)]
-    input: PathBuf,
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Input path (can be a single image file or a directory, or an
+    /// http(s):// URL with the `net` feature)
+    #[arg(short, long, required_unless_present = "job")]
+    input: Option<PathBuf>,
+
+    /// Output path (optional).
+    /// If input is a file: this is the destination file path, or `-` to
+    /// write the crop to stdout.
+    /// If input is a directory: this is the destination directory.
+    /// With the `aws` feature, `s3://bucket/prefix` uploads the crops there.
+    /// May contain date tokens {Y}, {m}, {d}, {H} and {M}, e.g.
+    /// `out/{Y}/{m}/`; see --output-date.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Allow a single-file --output that is the input itself, replacing the
+    /// original with its crop
+    #[arg(long)]
+    in_place: bool,
+
+    /// Date used for the tokens in --output: each input's modification time,
+    /// or the time the run started
+    #[arg(long, value_enum, default_value_t = DateSource::Mtime)]
+    output_date: DateSource,
+
+    /// Write Prometheus-style counters to this file after a directory run
+    #[arg(long, value_name = "PATH")]
+    metrics_file: Option<PathBuf>,
+
+    /// At the end of a directory run, print one JSON object with the counts,
+    /// failure categories, duration and parameters used
+    #[arg(long)]
+    json_summary: bool,
+
+    /// Write the --json-summary object to this file instead of stdout
+    #[arg(long, value_name = "PATH")]
+    summary_file: Option<PathBuf>,
+
+    /// TOML config file (see README for the supported settings)
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// YAML or JSON job file listing the inputs to process, with their
+    /// outputs and settings (see README); flags given next to it override
+    /// the file's settings
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["input", "output"])]
+    job: Option<PathBuf>,
+
+    /// In directory mode, also process images in subdirectories (mirrored
+    /// inside the output directory)
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// In directory mode, report each file left out for not having an image
+    /// (or raw or video) extension, instead of skipping them silently
+    #[arg(long)]
+    warn_skipped: bool,
+
+    /// In directory mode, process only N images picked at random, for a
+    /// quick spot check of a large archive
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    sample: Option<u64>,
+
+    /// Seed for --sample, to pick the same images again; printed with the
+    /// sample when not given
+    #[arg(long, value_name = "SEED", requires = "sample")]
+    seed: Option<u64>,
+
+    /// In directory mode, print the tree of files the run would write and
+    /// exit, without opening any image
+    #[arg(long, conflicts_with_all = ["coords_only", "data_uri", "explain"])]
+    plan: bool,
+
+    /// In directory mode, stop once N images have been cropped successfully
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    limit: Option<u64>,
+
+    /// Count every image attempted towards --limit, not only successful ones
+    #[arg(long, requires = "limit")]
+    limit_attempts: bool,
+
+    /// On Ctrl-C, finish the images being cropped, record them and print
+    /// what was done before exiting, instead of stopping mid-save
+    #[arg(long)]
+    interrupt_safe: bool,
+
+    /// Number of images to process in parallel in directory mode
+    #[arg(short, long, default_value_t = 1, value_name = "N")]
+    jobs: usize,
+
+    /// With --jobs > 1, parallelize over individual files or over top-level
+    /// subdirectories
+    #[arg(long, value_enum, default_value_t = ParallelGranularity::File, value_name = "UNIT")]
+    parallel_granularity: ParallelGranularity,
+
+    /// Without --jobs, decode up to N images ahead on a background thread
+    /// while the current one is detected
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    prefetch: Option<u32>,
+
+    /// How crops from subdirectories are named inside --output
+    #[arg(long, value_enum, default_value_t = OutputNaming::Mirror, value_name = "MODE")]
+    output_naming: OutputNaming,
+
+    /// Reduce crop file names to ASCII letters, digits and underscores,
+    /// adding a short hash where two inputs would get the same name
+    #[arg(long)]
+    normalize_filename: bool,
+
+    /// Name crops after the active mask (`_keyed` with --chroma-key,
+    /// `_feathered` with --feather, otherwise `_square`, or `_rect` with
+    /// --no-square) instead of `_cropped`, so variants of the same source can
+    /// share a directory
+    #[arg(long)]
+    output_suffix_per_mode: bool,
+
+    /// Append this to the input's file stem to name its crop, instead of
+    /// `_cropped` or the --output-suffix-per-mode suffix
+    #[arg(long, value_name = "SUFFIX", value_parser = parse_suffix)]
+    suffix: Option<String>,
+
+    /// Name the crops of a directory run 000001.jpg, 000002.jpg, ... in the
+    /// --output directory, in processing order, instead of after their
+    /// sources
+    #[arg(long, requires = "output", conflicts_with = "skip_on_existing_sidecar")]
+    number_outputs: bool,
+
+    /// With --number-outputs, start after the highest number already in the
+    /// output directory rather than at 1, to append to an existing dataset
+    #[arg(long, requires = "number_outputs", conflicts_with = "output_zip")]
+    continue_numbering: bool,
+
+    /// Write a row per image (input, output, status, faces, duration_ms,
+    /// detail) during a directory run
+    #[arg(long, value_name = "PATH")]
+    manifest: Option<PathBuf>,
+
+    /// File format of --manifest
+    #[arg(long, value_enum, default_value_t = ManifestFormat::Csv, value_name = "FORMAT", requires = "manifest")]
+    manifest_format: ManifestFormat,
+
+    /// Write a JSON object mapping every crop written to its source image,
+    /// face index and crop rectangle, kept up to date as crops are saved
+    #[arg(long, value_name = "PATH")]
+    index_json: Option<PathBuf>,
+
+    /// Write a self-contained HTML gallery of a directory run, with a
+    /// thumbnail, score and status for every image
+    #[arg(long, value_name = "PATH")]
+    html_report: Option<PathBuf>,
+
+    /// Longest side of the --html-report thumbnails
+    #[arg(
+        long,
+        value_name = "PX",
+        default_value_t = 160,
+        value_parser = clap::value_parser!(u32).range(16..),
+        requires = "html_report"
+    )]
+    html_report_thumb_size: u32,
+
+    /// Thumbnails per row in the --html-report gallery
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 5,
+        value_parser = clap::value_parser!(u32).range(1..),
+        requires = "html_report"
+    )]
+    html_report_columns: u32,
+
+    /// Also run a profile (side-face) detector and merge its detections with
+    /// the frontal ones. Roughly doubles detection time per image.
+    #[arg(long, requires = "profile_model")]
+    include_profile: bool,
+
+    /// SeetaFace-format model file used by `--include-profile`
+    #[arg(long, value_name = "PATH")]
+    profile_model: Option<PathBuf>,
+
+    /// Retry a failed save up to this many times, waiting 200ms, 400ms, ...
+    /// in between; for flaky network mounts
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    retry_on_save_error: u32,
+
+    /// Face detection library to use
+    #[arg(long, value_enum, default_value_t = Backend::Rustface)]
+    backend: Backend,
+
+    /// Grayscale conversion applied before detection; worth trying another
+    /// when faces are missed in strongly tinted images
+    #[arg(long, value_enum, default_value_t = GrayConversion::Rec709)]
+    grayscale_conversion: GrayConversion,
+
+    /// Histogram-equalize the grayscale image before detection, to find
+    /// faces in low-contrast or backlit photos; crops are unaffected
+    #[arg(long)]
+    auto_contrast: bool,
+
+    /// Remove color casts (tungsten light, underwater shots) from the copy
+    /// the detector scans, by gray-world white balance; crops are unaffected
+    #[arg(long)]
+    auto_white_balance: bool,
+
+    /// Upscale images whose longer side is under this many pixels before
+    /// detection, so small faces clear the minimum face size; crops are
+    /// still taken from the original pixels
+    #[arg(
+        long,
+        value_name = "PX",
+        num_args = 0..=1,
+        default_missing_value = "400",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    detect_upscale_small: Option<u32>,
+
+    /// How much --detect-upscale-small enlarges images
+    #[arg(
+        long,
+        value_name = "FACTOR",
+        default_value_t = 2.0,
+        value_parser = parse_upscale_factor,
+        requires = "detect_upscale_small"
+    )]
+    detect_upscale_factor: f64,
+
+    /// Scan images larger than this many pixels in overlapping square tiles
+    /// at full resolution, to find small faces in huge group photos
+    #[arg(
+        long,
+        value_name = "PX",
+        value_parser = clap::value_parser!(u32).range(64..)
+    )]
+    tile_size: Option<u32>,
+
+    /// Pixels neighbouring --tile-size tiles share; faces up to this size are
+    /// found whole even where they cross a seam
+    #[arg(long, value_name = "PX", default_value_t = 128, requires = "tile_size")]
+    tile_overlap: u32,
+
+    /// Detect in two passes: a fast one on a copy scaled down to
+    /// --coarse-size, then a full-resolution one only around the faces it
+    /// found
+    #[arg(long, conflicts_with = "tile_size")]
+    two_stage_detect: bool,
+
+    /// Longer side, in pixels, of the copy --two-stage-detect scans first
+    #[arg(
+        long,
+        value_name = "PX",
+        default_value_t = 800,
+        value_parser = clap::value_parser!(u32).range(64..),
+        requires = "two_stage_detect"
+    )]
+    coarse_size: u32,
+
+    /// Space around each face found by the coarse pass that the fine pass
+    /// rescans, as a fraction of the face size on each side
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        default_value_t = 0.5,
+        value_parser = parse_fraction,
+        requires = "two_stage_detect"
+    )]
+    candidate_padding: f64,
+
+    /// Give up on an image if a detector call takes longer than this many
+    /// milliseconds (tiles, regions and rotations are separate calls); also
+    /// limits downloads of URL inputs
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: Option<u64>,
+
+    /// Run the detector once on a blank image before the first input, so
+    /// one-time setup does not inflate the first image's timing
+    #[arg(long)]
+    detector_warmup: bool,
+
+    /// Time each phase (decode, grayscale, detect, crop, save) across the
+    /// run and print a breakdown at the end
+    #[arg(long)]
+    profile: bool,
+
+    /// Also write the --profile breakdown to this file as folded stacks,
+    /// for flamegraph tools
+    #[arg(long, value_name = "PATH", requires = "profile")]
+    profile_folded: Option<PathBuf>,
+
+    /// In directory mode, copy files that are not images into the output
+    /// directory unchanged, so it mirrors the input
+    #[arg(long)]
+    copy_unprocessed: bool,
+
+    /// Copy each successfully cropped source image into the output directory
+    /// next to its `_cropped` result, for side-by-side review
+    #[arg(long, conflicts_with = "coords_only")]
+    keep_original: bool,
+
+    /// TOML file naming alternative detector models; see --model-name
+    #[arg(long, value_name = "PATH")]
+    model_set: Option<PathBuf>,
+
+    /// Which model to detect with: `embedded` (the built-in frontal model)
+    /// or a name from --model-set
+    #[arg(long, value_name = "NAME", default_value = models::EMBEDDED)]
+    model_name: String,
+
+    /// Embed a provenance stamp (tool version + detector parameter hash) in
+    /// the EXIF ImageDescription of each saved crop
+    #[arg(long)]
+    stamp: bool,
+
+    /// Skip images with fewer than N faces (after the face filters), e.g. to
+    /// keep only group shots; above 1 it needs a multi-face option such as
+    /// --group-crop or --largest-n
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    min_faces: Option<u32>,
+
+    /// Crop the Nth face (0-based), counting left to right, instead of
+    /// skipping multi-face images
+    #[arg(long, value_name = "N", conflicts_with_all = ["select", "group_crop"])]
+    face_index: Option<usize>,
+
+    /// Crop each of the N largest faces (ties broken by score) into
+    /// `_1`, `_2`, ... numbered files, instead of skipping multi-face images
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["select", "group_crop", "face_index", "skip_on_existing_sidecar"]
+    )]
+    largest_n: Option<u32>,
+
+    /// With --largest-n, save each image's crops as 1.jpg, 2.jpg, ... in a
+    /// subdirectory named after the source
+    #[arg(long, requires = "largest_n")]
+    group_by_source: bool,
+
+    /// With --largest-n, skip crops that cover nearly the same area as one
+    /// already saved from the image
+    #[arg(long, requires = "largest_n")]
+    reject_duplicate_output: bool,
+
+    /// Size every crop as if its face were the Pth percentile face of the
+    /// image by size (50 = median), so one outlier does not change the
+    /// scale; each crop stays centered on its own face
+    #[arg(
+        long,
+        value_name = "P",
+        value_parser = clap::value_parser!(u32).range(0..=100),
+        conflicts_with = "group_crop"
+    )]
+    crop_from_percentile_face: Option<u32>,
+
+    /// Frame all detected faces in one crop, centered on the box enclosing
+    /// them, instead of skipping multi-face images
+    #[arg(long, conflicts_with = "select")]
+    group_crop: bool,
+
+    /// When several faces are found, crop one of them instead of skipping
+    /// the image
+    #[arg(long, value_enum, value_name = "STRATEGY")]
+    select: Option<SelectStrategy>,
+
+    /// Crop the largest face, with a warning, when several are found and no
+    /// other option says which to use (the default is to skip the image)
+    #[arg(long)]
+    lenient: bool,
+
+    /// How much --select and --lenient favor the face nearest the image
+    /// center over a larger or higher-scoring one; 0 ignores position
+    #[arg(long, value_name = "WEIGHT", default_value_t = 0.0, value_parser = parse_non_negative)]
+    center_weight: f64,
+
+    /// Ignore faces smaller than this fraction of the largest face's area
+    /// (e.g. 0.3 drops background faces in group photos)
+    #[arg(long, value_name = "FRACTION", value_parser = parse_fraction)]
+    relative_face_threshold: Option<f64>,
+
+    /// Skip images whose face box reaches PX or more pixels past the image
+    /// edge; without a value, touching the edge is enough
+    #[arg(long, value_name = "PX", num_args = 0..=1, default_missing_value = "0")]
+    reject_edge_faces: Option<u32>,
+
+    /// Warn when a crop, before any resizing, is narrower or shorter than
+    /// PX pixels (default 100)
+    #[arg(long, value_name = "PX", num_args = 0..=1, default_missing_value = "100", value_parser = clap::value_parser!(u32).range(1..))]
+    crop_quality_check: Option<u32>,
+
+    /// Skip images that fail --crop-quality-check instead of warning
+    #[arg(long, requires = "crop_quality_check")]
+    reject_low_detail: bool,
+
+    /// Skip images narrower than this width/height ratio (e.g. 0.5) before
+    /// detection
+    #[arg(long, value_name = "RATIO", value_parser = parse_aspect)]
+    min_aspect: Option<f64>,
+
+    /// Skip images wider than this width/height ratio (e.g. 2.5 to leave out
+    /// panoramas) before detection
+    #[arg(long, value_name = "RATIO", value_parser = parse_aspect)]
+    max_aspect: Option<f64>,
+
+    /// Ignore faces whose eyes are closer together than this many pixels.
+    /// Needs a backend that finds landmarks; ignored (with a warning) otherwise
+    #[arg(long, value_name = "PX")]
+    min_eye_distance: Option<f64>,
+
+    /// Frame each crop around the face's landmarks (eyes, nose, mouth)
+    /// instead of the looser detector box. Needs a backend that finds
+    /// landmarks; faces without them keep the detector box
+    #[arg(long)]
+    frame_from_landmarks: bool,
+
+    /// Space around the landmark bounds for --frame-from-landmarks, as a
+    /// fraction of their extent on each side
+    #[arg(
+        long,
+        value_name = "FACTOR",
+        default_value_t = 0.5,
+        value_parser = parse_landmark_padding,
+        requires = "frame_from_landmarks"
+    )]
+    landmark_padding: f64,
+
+    /// How large the crop is: fill the frame, or fit tightly around the face
+    #[arg(long, value_enum, default_value_t = CropStrategy::Fill, value_name = "STRATEGY")]
+    face_crop_strategy: CropStrategy,
+
+    /// Crop a rectangle with the face box's aspect ratio instead of a square
+    #[arg(long)]
+    no_square: bool,
+
+    /// Image side that caps the crop size: the shorter one, so the crop
+    /// stays inside the image, or the longer one for more context, padding
+    /// the other side per --pad-mode
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = SquareBasis::Short,
+        value_name = "SIDE",
+        requires_if("long", "pad_mode"),
+        conflicts_with_all = ["no_square", "pad_pixels"]
+    )]
+    square_basis: SquareBasis,
+
+    /// Extra space around the face for `--face-crop-strategy tightest-square`,
+    /// as a fraction of the face size
+    #[arg(long, default_value_t = 0.2, value_name = "FRACTION", value_parser = parse_non_negative)]
+    crop_margin: f64,
+
+    /// Grow the face box upward by this fraction of its height before
+    /// framing, to take in hair and headroom
+    #[arg(long, default_value_t = 0.0, value_name = "FRACTION", value_parser = parse_fraction)]
+    top_extend: f64,
+
+    /// In directory mode, first measure every crop, then resize all of them
+    /// to the median width and height (detects each image twice)
+    #[arg(long, conflicts_with = "coords_only")]
+    resize_crops_to_median: bool,
+
+    /// Keep the crop exactly centered on the face, making it smaller near
+    /// image edges, instead of shifting it back inside the image
+    #[arg(long)]
+    exclude_center_bias: bool,
+
+    /// Keep the crop centered on the face near image edges too, and fill the
+    /// part that falls outside the image: a solid color, the repeated edge
+    /// pixels, or the mirrored image
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        conflicts_with = "exclude_center_bias"
+    )]
+    pad_mode: Option<PadMode>,
+
+    /// Fill color for `--pad-mode color` (`green`, `blue`, `white`, `black`
+    /// or `#rrggbb`)
+    #[arg(
+        long,
+        value_name = "COLOR",
+        default_value = "#000000",
+        value_parser = chroma::parse_color,
+        requires = "pad_mode"
+    )]
+    pad_color: [u8; 3],
+
+    /// Crop the face box plus exactly this many pixels on each side, instead
+    /// of using --face-crop-strategy
+    #[arg(long, value_name = "N", conflicts_with_all = ["crop_margin", "face_crop_strategy"])]
+    pad_pixels: Option<u32>,
+
+    /// Weights of sharpness, size and centering in the quality score written
+    /// to sidecars, --index-json and the manifest
+    #[arg(
+        long,
+        value_name = "SHARPNESS,SIZE,CENTER",
+        default_value = "1,1,1",
+        value_parser = parse_quality_weights
+    )]
+    quality_weights: QualityWeights,
+
+    /// Skip images whose face region is blurrier than this (variance of the
+    /// Laplacian; see the value printed by --verbose to calibrate)
+    #[arg(long, value_name = "VALUE")]
+    min_sharpness: Option<f64>,
+
+    /// Skip images whose chosen face has a detector score below this
+    #[arg(long, value_name = "SCORE")]
+    score_min: Option<f64>,
+
+    /// Skip images whose chosen face has a detector score above this (with
+    /// --score-min, keeps only a band of "hard" detections)
+    #[arg(long, value_name = "SCORE")]
+    score_max: Option<f64>,
+
+    /// Straighten slightly rotated scans before detection, judging the skew
+    /// from straight edges near the borders; crops come from the
+    /// straightened image
+    #[arg(long)]
+    deskew: bool,
+
+    /// Largest skew --deskew corrects, in degrees either way
+    #[arg(
+        long,
+        value_name = "DEGREES",
+        default_value_t = 5.0,
+        value_parser = deskew::parse_max_angle,
+        requires = "deskew"
+    )]
+    deskew_max_angle: f32,
+
+    /// If no valid face is found, retry detection with the image rotated by
+    /// 90, 180 and 270 degrees and crop from the first orientation that works
+    #[arg(long)]
+    try_rotations: bool,
+
+    /// Skip inputs that look like an image already processed in this run
+    /// (perceptual hash comparison); the first one seen is kept
+    #[arg(long)]
+    dedupe: bool,
+
+    /// Maximum Hamming distance between perceptual hashes for `--dedupe` to
+    /// treat two images as duplicates
+    #[arg(long, default_value_t = 4, value_name = "BITS")]
+    dedupe_threshold: u32,
+
+    /// After a directory run, sort the crops into `cluster_N/` directories
+    /// of --output by perceptual hash: a rough visual grouping, not face
+    /// recognition. Conflicts with the options that record crop paths
+    /// before they are moved
+    #[arg(
+        long,
+        requires = "output",
+        conflicts_with_all = [
+            "output_zip",
+            "output_tar",
+            "sizes",
+            "face_region_export",
+            "plan",
+            "manifest",
+            "index_json",
+            "sidecar",
+        ]
+    )]
+    cluster_by_similarity: bool,
+
+    /// Maximum Hamming distance between the perceptual hashes of a crop and
+    /// the first crop of a cluster for --cluster-by-similarity to put it
+    /// there
+    #[arg(
+        long,
+        default_value_t = 12,
+        value_name = "BITS",
+        value_parser = clap::value_parser!(u32).range(0..=64),
+        requires = "cluster_by_similarity"
+    )]
+    cluster_threshold: u32,
+
+    /// Center the crops of consecutive near-identical shots (a burst) on
+    /// their mean face position, so the series looks consistent
+    #[arg(long, conflicts_with = "largest_n")]
+    burst_smoothing: bool,
+
+    /// Maximum Hamming distance between the perceptual hashes of
+    /// consecutive images for `--burst-smoothing` to treat them as one burst
+    #[arg(
+        long,
+        default_value_t = 10,
+        value_name = "BITS",
+        requires = "burst_smoothing"
+    )]
+    burst_threshold: u32,
+
+    /// Convert crops to this color type before saving (detection always
+    /// runs on luma regardless)
+    #[arg(long, value_enum, value_name = "TYPE")]
+    color: Option<ColorMode>,
+
+    /// Save crops with 8 or 16 bits per channel (16 needs PNG, TIFF or
+    /// PNM output); by default the source depth is kept
+    #[arg(long, value_name = "BITS", value_parser = BitDepth::parse)]
+    output_bit_depth: Option<BitDepth>,
+
+    /// Tag crops with this print resolution, in dots per inch (JPEG and PNG
+    /// only); by default no resolution is written
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    dpi: Option<u16>,
+
+    /// Lower the JPEG quality of crops as far as needed to keep each file
+    /// under this many kilobytes (1024 bytes); other formats are lossless
+    /// and saved as they are
+    #[arg(long, value_name = "KB", value_parser = clap::value_parser!(u64).range(1..))]
+    max_filesize: Option<u64>,
+
+    /// When an image fails to decode, try again from its contents: in the
+    /// format they show, without memory limits, or past leading junk
+    #[arg(long)]
+    retry_decode_with_fallback_backend: bool,
+
+    /// Crop every frame of an animated WebP, each saved with `_frame<N>`
+    /// added to its name, instead of only the first frame
+    #[arg(long, conflicts_with_all = ["annotations_format", "plan"])]
+    all_frames: bool,
+
+    /// Fade crops to transparent over this many pixels at their border, for
+    /// compositing (JPEG crops are saved as --mask-format instead)
+    #[arg(long, value_name = "PX", value_parser = clap::value_parser!(u32).range(1..))]
+    feather: Option<u32>,
+
+    /// Format for transparent crops (--feather, --chroma-key) whose name
+    /// picks a format without alpha, such as JPEG sources
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = MaskFormat::Png)]
+    mask_format: MaskFormat,
+
+    /// Save crops whose longer side is at most --lossless-threshold as PNG
+    /// when their name asks for JPEG, where compression artifacts would
+    /// show most
+    #[arg(long, conflicts_with = "data_uri")]
+    prefer_format_lossless_for_small_crops: bool,
+
+    /// Longest side, in pixels of the saved crop, up to which
+    /// --prefer-format-lossless-for-small-crops saves crops as PNG
+    #[arg(
+        long,
+        default_value_t = 128,
+        value_name = "PX",
+        value_parser = clap::value_parser!(u32).range(1..),
+        requires = "prefer_format_lossless_for_small_crops"
+    )]
+    lossless_threshold: u32,
+
+    /// Key out a green- or blue-screen background of this color (`green`,
+    /// `blue` or `#rrggbb`) around the face, leaving it transparent
+    #[arg(long, value_name = "COLOR", value_parser = chroma::parse_color)]
+    chroma_key: Option<[u8; 3]>,
+
+    /// How far (RGB distance, 0-441) a pixel may be from the --chroma-key
+    /// color and still count as background
+    #[arg(
+        long,
+        value_name = "DISTANCE",
+        default_value_t = 100.0,
+        value_parser = chroma::parse_tolerance,
+        requires = "chroma_key"
+    )]
+    chroma_tolerance: f32,
+
+    /// Fill the keyed-out background with this color instead of leaving it
+    /// transparent
+    #[arg(
+        long,
+        value_name = "COLOR",
+        value_parser = chroma::parse_color,
+        requires = "chroma_key"
+    )]
+    chroma_replace: Option<[u8; 3]>,
+
+    /// Round the crop width and height down to even numbers, as required by
+    /// some video encoders (e.g. yuv420p in FFmpeg)
+    #[arg(long)]
+    even_dimensions: bool,
+
+    /// Show each saved crop in the terminal (half-block art, or inline images
+    /// where the terminal supports them)
+    #[arg(long, conflicts_with = "coords_only")]
+    term_preview: bool,
+
+    /// In directory mode, stop previewing after this many crops
+    #[arg(long, default_value_t = 10, value_name = "N")]
+    term_preview_limit: usize,
+
+    /// Compute a BlurHash placeholder string for each saved crop; printed
+    /// and recorded in the manifest
+    #[arg(long, conflicts_with = "coords_only")]
+    blurhash: bool,
+
+    /// BlurHash component counts as XxY (1-9 each); more components keep
+    /// more detail in a longer string
+    #[arg(long, default_value = "4x3", value_name = "XxY", value_parser = placeholder::parse_components)]
+    blurhash_components: (u32, u32),
+
+    /// Write `<crop>.json` next to each crop with the face box, score and
+    /// crop rectangle
+    #[arg(long, conflicts_with = "coords_only")]
+    sidecar: bool,
+
+    /// Also save the face box itself, without margin or squaring, as
+    /// `<stem>_bbox.<ext>` next to each crop
+    #[arg(long, conflicts_with_all = ["coords_only", "data_uri"])]
+    face_region_export: bool,
+
+    /// Also save each crop scaled to these sizes (longer side, in pixels),
+    /// as `<crop stem>_<N>px.<ext>` next to it, e.g. `--sizes 64,128,256`.
+    /// With --even-dimensions, odd sides are rounded down to even
+    #[arg(
+        long,
+        value_name = "N,...",
+        value_delimiter = ',',
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["coords_only", "data_uri"]
+    )]
+    sizes: Vec<u32>,
+
+    /// Write TEMPLATE onto each crop, the source file name by default. Fills
+    /// in `{name}`, `{stem}`, `{face}` and `{score}`
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        num_args = 0..=1,
+        default_missing_value = "{name}"
+    )]
+    label: Option<String>,
+
+    /// Corner of the crop the --label text goes in
+    #[arg(long, value_enum, default_value_t = LabelPosition::BottomLeft, value_name = "CORNER", requires = "label")]
+    label_position: LabelPosition,
+
+    /// Color of the --label text (`green`, `blue`, `white`, `black` or
+    /// `#rrggbb`)
+    #[arg(
+        long,
+        value_name = "COLOR",
+        default_value = "white",
+        value_parser = chroma::parse_color,
+        requires = "label"
+    )]
+    label_color: [u8; 3],
+
+    /// Height of the --label text line, in pixels of the saved crop
+    #[arg(long, default_value_t = 16, value_name = "PX", value_parser = clap::value_parser!(u32).range(8..=512), requires = "label")]
+    label_size: u32,
+
+    /// Draw the --label text on a strip of this color across the crop, so it
+    /// stays legible on busy backgrounds
+    #[arg(long, value_name = "COLOR", value_parser = chroma::parse_color, requires = "label")]
+    label_background: Option<[u8; 3]>,
+
+    /// Skip inputs whose crop already has a sidecar from an earlier run, for
+    /// incremental rebuilds
+    #[arg(long, requires = "sidecar")]
+    skip_on_existing_sidecar: bool,
+
+    /// With --skip-on-existing-sidecar, reprocess everything anyway
+    #[arg(long, requires = "skip_on_existing_sidecar")]
+    refresh: bool,
+
+    /// Write all crops into this zip archive, mirroring the input's
+    /// directory structure, instead of as loose files
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["output", "coords_only", "keep_original", "copy_unprocessed", "sidecar"]
+    )]
+    output_zip: Option<PathBuf>,
+
+    /// Write all crops into this uncompressed tar archive, or stream it to
+    /// stdout with `-`, mirroring the input's directory structure
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["output", "output_zip", "coords_only", "keep_original", "copy_unprocessed", "sidecar"]
+    )]
+    output_tar: Option<PathBuf>,
+
+    /// Run this command after every saved crop, with {input}, {output} and
+    /// {score} replaced (run directly, not through a shell)
+    #[arg(
+        long,
+        value_name = "COMMAND",
+        conflicts_with_all = ["output_zip", "output_tar", "coords_only", "data_uri"]
+    )]
+    exec: Option<String>,
+
+    /// Maximum number of --exec commands running at once
+    #[arg(long, default_value_t = 1, value_name = "N", requires = "exec")]
+    exec_parallel: usize,
+
+    /// Print `input<TAB>x<TAB>y<TAB>w<TAB>h` for each crop rectangle instead
+    /// of writing images
+    #[arg(long, conflicts_with = "copy_unprocessed")]
+    coords_only: bool,
+
+    /// Print `input<TAB>data:image/...;base64,...` for each crop instead of
+    /// writing images, encoded as FORMAT
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        default_missing_value = "png",
+        conflicts_with_all = [
+            "coords_only",
+            "output",
+            "output_zip",
+            "output_tar",
+            "keep_original",
+            "copy_unprocessed",
+            "sidecar",
+            "blurhash",
+            "term_preview",
+            "write_empty_on_no_face"
+        ]
+    )]
+    data_uri: Option<DataUriFormat>,
+
+    /// Write bounding boxes of every detected face to --annotations in this
+    /// training-set format
+    #[arg(long, value_enum, requires = "annotations")]
+    annotations_format: Option<AnnotationFormat>,
+
+    /// Annotation output: a JSON file for `coco`, a directory of XML files
+    /// for `voc`
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "annotations_format",
+        conflicts_with = "try_rotations"
+    )]
+    annotations: Option<PathBuf>,
+
+    /// When no face is found, save the centered square of the image as a
+    /// placeholder instead of skipping it, so every input has an output
+    #[arg(long, conflicts_with = "coords_only")]
+    write_empty_on_no_face: bool,
+
+    /// Directory for the temporary model file (defaults to the system temp
+    /// dir, i.e. $TMPDIR or /tmp)
+    #[arg(long, value_name = "PATH")]
+    temp_dir: Option<PathBuf>,
+
+    /// How quickly a video crop follows the face: the weight of each frame's
+    /// face position in the moving average of the crop center (1 follows
+    /// every detection exactly, smaller values are steadier)
+    #[cfg(feature = "video")]
+    #[arg(long, default_value_t = 0.3, value_name = "FACTOR", value_parser = parse_fraction)]
+    smoothing: f64,
+
+    /// Minimum face size as a fraction of each image's shorter side (e.g.
+    /// 0.05), instead of a fixed 20 px; never below 20 px
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        value_parser = parse_fraction
+    )]
+    min_face_relative: Option<f64>,
+
+    /// Smallest face the detector reports, in pixels (at least 20; default
+    /// 20)
+    #[arg(
+        long,
+        value_name = "PX",
+        env = "FACE_CROPPER_MIN_FACE_SIZE",
+        value_parser = clap::value_parser!(u32).range(20..)
+    )]
+    min_face_size: Option<u32>,
+
+    /// Ignore faces whose box is larger than this many pixels on its longer
+    /// side, such as a close-up in front of the intended subjects
+    #[arg(long, value_name = "PX", value_parser = clap::value_parser!(u32).range(1..))]
+    max_face_size: Option<u32>,
+
+    /// Score a detection needs to count as a face; higher values mean fewer
+    /// false positives (default 2.0)
+    #[arg(
+        long,
+        value_name = "SCORE",
+        env = "FACE_CROPPER_SCORE_THRESH",
+        value_parser = parse_score_thresh
+    )]
+    score_thresh: Option<f64>,
+
+    /// Factor between adjacent levels of the detector's image pyramid; larger
+    /// values scan more scales (slower, better recall on small faces; default
+    /// 0.8)
+    #[arg(
+        long,
+        value_name = "FACTOR",
+        env = "FACE_CROPPER_PYRAMID_SCALE",
+        value_parser = parse_pyramid_scale
+    )]
+    pyramid_scale: Option<f32>,
+
+    /// Pixels the detector's window moves between positions, as `N` or
+    /// `XxY`; smaller steps are slower but miss fewer faces (default 4)
+    #[arg(
+        long,
+        value_name = "STEP",
+        env = "FACE_CROPPER_SLIDE_WINDOW_STEP",
+        value_parser = parse_window_step
+    )]
+    slide_window_step: Option<(u32, u32)>,
+
+    /// Write the downscaled image pyramid levels the detector scans into
+    /// this directory, for tuning --pyramid-scale and min face size
+    #[arg(long, value_name = "DIR", help_heading = "Debugging")]
+    debug_pyramid: Option<PathBuf>,
+
+    /// Write the grayscale image the detector scans (after
+    /// --grayscale-conversion and --auto-contrast) into this directory
+    #[arg(long, value_name = "DIR", help_heading = "Debugging")]
+    save_grayscale_detection_image: Option<PathBuf>,
+
+    /// Experimental: write a coarse map of the detector's response across
+    /// each image into this directory, to see where faces were nearly found
+    #[arg(long, value_name = "DIR", help_heading = "Debugging")]
+    heatmap: Option<PathBuf>,
+
+    /// Draw the detected face box onto each crop, to check at a glance that
+    /// the detector found the right region
+    #[arg(long, help_heading = "Debugging")]
+    debug_outline: bool,
+
+    /// Color of the --debug-outline box (`green`, `blue`, `white`, `black`
+    /// or `#rrggbb`)
+    #[arg(
+        long,
+        value_name = "COLOR",
+        default_value = "#ff0000",
+        value_parser = chroma::parse_color,
+        requires = "debug_outline",
+        help_heading = "Debugging"
+    )]
+    debug_outline_color: [u8; 3],
+
+    /// Line width of the --debug-outline box, in pixels of the saved crop
+    #[arg(
+        long,
+        value_name = "PX",
+        default_value_t = 2,
+        value_parser = clap::value_parser!(u32).range(1..),
+        requires = "debug_outline",
+        help_heading = "Debugging"
+    )]
+    debug_outline_width: u32,
+
+    /// Print extra detail about each image
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Do not print suggestions for settings that seem not to suit the
+    /// images, such as a minimum face size far too small for their size
+    #[arg(long)]
+    no_hints: bool,
+
+    /// Narrate how a single image is cropped: its size, the faces found,
+    /// which were filtered out and why, the face picked, the crop rectangle
+    /// and where it is written
+    #[arg(long)]
+    explain: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check that the embedded model loads and finds the face in a bundled
+    /// test image; exits nonzero on failure
+    Selftest,
+    /// Print the size, SHA-256 and structure of the model picked by
+    /// --model-name as one line of JSON
+    ModelInfo,
+    /// Detect on a sample image or directory with every combination of the
+    /// given detector settings and print how many faces each finds
+    Sweep(SweepArgs),
+    /// Detect on a sample image or directory and print a histogram and
+    /// percentiles of the face scores, for choosing --score-thresh
+    Scores(ScoresArgs),
+}
+
+
+impl Args {
+    fn input(&self) -> &Path {
+        self.input
+            .as_deref()
+            .expect("clap requires --input unless a subcommand or --job is given")
+    }
+
+    /// Whether crops are printed (--coords-only, --data-uri) rather than
+    /// written to files.
+    fn prints_only(&self) -> bool {
+        self.coords_only || self.data_uri.is_some()
+    }
+
+    /// Whether stdout carries the results (crops printed, `--output -` or
+    /// `--output-tar -`), so messages have to go to stderr.
+    fn stdout_is_data(&self) -> bool {
+        self.prints_only()
+            || self.output.as_deref() == Some(Path::new(STDOUT))
+            || self.output_tar.as_deref() == Some(Path::new(STDOUT))
+    }
+}
+
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let result = match &args.job {
+        Some(_) if args.command.is_some() => bail!("--job cannot be used with a subcommand"),
+        Some(path) => run_job(path),
+        None => run(args),
+    };
+    if interrupt::requested() {
+        // Report a failure the way an uncaught Ctrl-C would.
+        result?;
+        std::process::exit(130);
+    }
+    result
+}
+
+
+/// Process every input of the job file at `path`, each like a separate run
+/// with its own `--input`, `--output` and flags.
+fn run_job(path: &Path) -> Result<()> {
+    let job = Job::load(path)?;
+    let program = std::env::args_os().next().unwrap_or_default();
+    let cli = job::cli_without_job(std::env::args_os());
+
+    // Parse every command line before processing anything, so a bad setting
+    // anywhere in the file stops the job up front.
+    let runs = job
+        .inputs()
+        .iter()
+        .map(|input| {
+            let parse = |line: Vec<OsString>| -> Result<Args> {
+                let matches = Args::command()
+                    .args_override_self(true)
+                    .try_get_matches_from(std::iter::once(program.clone()).chain(line))?;
+                Ok(Args::from_arg_matches(&matches)?)
+            };
+            let mut line = job.command_line(input, &cli);
+            if let Some(dir) = job.output_dir_for(input) {
+                // Named from the input's own --suffix and --normalize-filename.
+                let args = parse(line.clone())?;
+                let name = generate_cropped_filename(
+                    args.input(),
+                    args.normalize_filename,
+                    crop_suffix(&args),
+                )?;
+                line.extend(["--output".into(), dir.join(name).into()]);
+            }
+            parse(line)
+        })
+        .map(|run| run.with_context(|| format!("Invalid settings in job file {:?}", path)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let total = runs.len();
+    let mut failed = 0;
+    for (done, args) in runs.into_iter().enumerate() {
+        if interrupt::requested() {
+            eprintln!(
+                "Interrupted: {} of {} job input(s) not started.",
+                total - done,
+                total
+            );
+            break;
+        }
+        let input = args.input().to_path_buf();
+        if let Err(e) = run(args) {
+            eprintln!("Job input {:?} failed: {:#}", input, e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        bail!("{} of {} job inputs failed", failed, total);
+    }
+    Ok(())
+}
+
+
+fn run(mut args: Args) -> Result<()> {
+    if args.interrupt_safe {
+        interrupt::install()?;
+    }
+    if args.burst_smoothing && args.jobs > 1 {
+        bail!("--burst-smoothing compares images in order and cannot be used with --jobs above 1");
+    }
+    if args.min_faces.is_some_and(|min| min > 1)
+        && !(args.group_crop
+            || args.largest_n.is_some()
+            || args.select.is_some()
+            || args.face_index.is_some()
+            || args.lenient)
+    {
+        bail!(
+            "--min-faces above 1 needs a way to crop multi-face images: --group-crop, \
+             --largest-n, --select, --face-index or --lenient"
+        );
+    }
+    if args.tile_size.is_some_and(|tile| args.tile_overlap >= tile) {
+        bail!("--tile-overlap must be smaller than --tile-size");
+    }
+    if let (Some(min), Some(max)) = (args.score_min, args.score_max)
+        && min > max
+    {
+        bail!("--score-min must not be greater than --score-max");
+    }
+
+    if let Some(Command::Selftest) = args.command {
+        let model_temp_file = write_embedded_model(&args)?;
+        return selftest::run(model_temp_file.path());
+    }
+
+    let model_set = match &args.model_set {
+        Some(path) => ModelSet::load(path)?,
+        None => ModelSet::default(),
+    };
+
+    if let Some(Command::ModelInfo) = args.command {
+        let path = model_set.resolve(&args.model_name)?;
+        println!("{}", models::info(&args.model_name, path, MODEL_BYTES)?);
+        return Ok(());
+    }
+
+    // 2. Write the embedded model to a temporary file, unless another model
+    //    was picked. Kept alive until the end of main.
+    let model_temp_file;
+    let model_path = match model_set.resolve(&args.model_name)? {
+        Some(path) => path.to_path_buf(),
+        None => {
+            model_temp_file = write_embedded_model(&args)?;
+            model_temp_file.path().to_path_buf()
+        }
+    };
+
+    let file_config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+
+    // 3. Initialize Detector ONCE. Flags (or their environment variables)
+    //    win over the config file, which wins over the defaults.
+    let defaults = DetectorConfig::new(args.model_name.clone(), model_path);
+    let config = DetectorConfig {
+        profile_model: args.profile_model.clone().filter(|_| args.include_profile),
+        min_face_size: args
+            .min_face_size
+            .or(file_config.min_face_size)
+            .unwrap_or(defaults.min_face_size),
+        min_face_relative: args.min_face_relative,
+        score_thresh: args
+            .score_thresh
+            .or(file_config.score_thresh)
+            .unwrap_or(defaults.score_thresh),
+        pyramid_scale_factor: args
+            .pyramid_scale
+            .or(file_config.pyramid_scale)
+            .unwrap_or(defaults.pyramid_scale_factor),
+        slide_window_step: args
+            .slide_window_step
+            .or(file_config.slide_window_step)
+            .unwrap_or(defaults.slide_window_step),
+        size_bands: file_config.size_bands,
+        ..defaults
+    };
+
+    if let Some(max) = args.max_face_size.filter(|&max| max < config.min_face_size) {
+        bail!(
+            "--max-face-size {} is below the minimum face size {}, so no face could be kept",
+            max,
+            config.min_face_size
+        );
+    }
+
+    if let Some(Command::Sweep(sweep)) = &args.command {
+        return sweep::run(
+            sweep,
+            &config,
+            args.grayscale_conversion,
+            args.retry_decode_with_fallback_backend,
+        );
+    }
+    if let Some(Command::Scores(scores)) = &args.command {
+        return scores::run(
+            scores,
+            &config,
+            args.grayscale_conversion,
+            args.retry_decode_with_fallback_backend,
+        );
+    }
+
+    // Crops are named as without --output and uploaded under the prefix.
+    let s3 = match args.output.take_if(|output| s3::is_s3(output)) {
+        Some(url) => {
+            if args.prints_only()
+                || args.keep_original
+                || args.copy_unprocessed
+                || args.sidecar
+                || args.exec.is_some()
+                || args.number_outputs
+            {
+                bail!(
+                    "s3:// output cannot be combined with --coords-only, --keep-original, \
+                     --copy-unprocessed, --sidecar, --exec or --number-outputs"
+                );
+            }
+            Some(S3Output::connect(&url.to_string_lossy())?)
+        }
+        None => None,
+    };
+
+    if args.explain && args.input().is_dir() {
+        bail!("--explain narrates a single image; pass a file or URL as --input");
+    }
+
+    if args.plan && !args.input().is_dir() {
+        bail!("--plan lists the output of a directory run; pass a directory as --input");
+    }
+
+    if args.cluster_by_similarity
+        && (!args.input().is_dir()
+            || args.output.is_none()
+            || has_output_tokens(&args)
+            || args.prints_only()
+            || args.output.as_deref() == Some(Path::new(STDOUT)))
+    {
+        bail!(
+            "--cluster-by-similarity sorts the crops of a directory run into an --output \
+             directory without date tokens, and cannot be combined with s3:// output, \
+             --output -, --coords-only or --data-uri"
+        );
+    }
+
+    if args.number_outputs && (!args.input().is_dir() || has_output_tokens(&args)) {
+        bail!(
+            "--number-outputs needs a directory input and an --output directory without date tokens"
+        );
+    }
+
+    let to_stdout = args.output.as_deref() == Some(Path::new(STDOUT));
+    if to_stdout {
+        if args.input().is_dir() {
+            bail!("--output - writes a single crop and needs a single input file or URL");
+        }
+        if args.sidecar
+            || args.face_region_export
+            || !args.sizes.is_empty()
+            || args.keep_original
+            || args.largest_n.is_some()
+            || args.exec.is_some()
+            || args.term_preview
+            || args.skip_on_existing_sidecar
+        {
+            bail!(
+                "--output - cannot be combined with --sidecar, --face-region-export, \
+                 --sizes, --keep-original, --largest-n, --exec, --term-preview or \
+                 --skip-on-existing-sidecar"
+            );
+        }
+    }
+
+    if args.output_tar.as_deref() == Some(Path::new(STDOUT))
+        && (args.term_preview || (args.json_summary && args.summary_file.is_none()))
+    {
+        bail!(
+            "--output-tar - cannot be combined with --term-preview or with --json-summary \
+             unless it goes to --summary-file"
+        );
+    }
+
+    let ctx = RunContext {
+        to_stdout,
+        save: SaveOptions {
+            exif: args
+                .stamp
+                .then(|| provenance::exif_description(&provenance::stamp(&config.describe()))),
+            color: args.color,
+            bit_depth: args.output_bit_depth,
+            feather: args.feather,
+            keyed_transparent: args.chroma_key.is_some() && args.chroma_replace.is_none(),
+            dpi: args.dpi,
+            max_bytes: args.max_filesize.map(|kb| kb * 1024),
+            verbose: args.verbose,
+        },
+        chroma: args.chroma_key.map(|key| ChromaKey {
+            key,
+            tolerance: args.chroma_tolerance,
+            replacement: args.chroma_replace,
+        }),
+        dedupe: args.dedupe.then(|| Deduper::new(args.dedupe_threshold)),
+        clusters: args
+            .cluster_by_similarity
+            .then(|| Clusterer::new(args.cluster_threshold)),
+        names: args
+            .normalize_filename
+            .then(|| NameRegistry::new(crop_suffix(&args))),
+        numbering: match &args.output {
+            Some(dir) if args.continue_numbering => Some(
+                Numbering::continuing(dir)
+                    .context("Failed to read the output directory for --continue-numbering")?,
+            ),
+            _ => args.number_outputs.then(|| Numbering::new(1)),
+        },
+        burst: args
+            .burst_smoothing
+            .then(|| BurstSmoother::new(args.burst_threshold)),
+        preview: args
+            .term_preview
+            .then(|| TermPreview::new(args.term_preview_limit)),
+        // --plan only lists names, so it opens no archive, index or hook.
+        zip: args
+            .output_zip
+            .as_deref()
+            .filter(|_| !args.plan)
+            .map(ZipOutput::create)
+            .transpose()?,
+        tar: args
+            .output_tar
+            .as_deref()
+            .filter(|_| !args.plan)
+            .map(TarOutput::create)
+            .transpose()?,
+        s3,
+        annotations: match (args.annotations_format, &args.annotations) {
+            (Some(format), Some(path)) if !args.plan => {
+                Some(Annotations::create(format, path, input_root(&args))?)
+            }
+            _ => None,
+        },
+        index: args
+            .index_json
+            .as_deref()
+            .filter(|_| !args.plan)
+            .map(OutputIndex::create)
+            .transpose()?,
+        hook: args
+            .exec
+            .as_deref()
+            .filter(|_| !args.plan)
+            .map(|command| Hook::new(command, args.exec_parallel))
+            .transpose()?,
+        started: Local::now(),
+        crop_size: OnceLock::new(),
+        mask_format_warning: Once::new(),
+        lossless_warning: Once::new(),
+        face_size_hint: Once::new(),
+        detector: config.clone(),
+    };
+
+    if args.plan {
+        return batch::print_plan(&args, &ctx);
+    }
+
+    let profiling = args.profile.then(profile::enable);
+    let started = Instant::now();
+    let mut finder = create_detector(&args, &config)?;
+
+    if args.input().is_dir() {
+        let started = AtomicUsize::new(0);
+        let report = |event: ProgressEvent| match event {
+            ProgressEvent::Started { path, total } if args.verbose => eprintln!(
+                "[{}/{}] {:?}",
+                started.fetch_add(1, Ordering::Relaxed) + 1,
+                total,
+                batch::display_name(&args, path)
+            ),
+            ProgressEvent::Started { .. } => {}
+            ProgressEvent::Completed { .. } if args.prints_only() => {}
+            ProgressEvent::Completed { path, outcome } if outcome.placeholder => say(
+                &args,
+                format!(
+                    "Placeholder (no face): {:?}",
+                    batch::display_name(&args, path)
+                ),
+            ),
+            ProgressEvent::Completed { path, outcome } => {
+                say(
+                    &args,
+                    format!("Processed: {:?}", batch::display_name(&args, path)),
+                );
+                if let Some(hash) = &outcome.blurhash {
+                    say(&args, format!("Blurhash: {}", hash));
+                }
+            }
+            ProgressEvent::Failed { path, error } => {
+                eprintln!("Skipping {:?}: {}", batch::display_name(&args, path), error)
+            }
+        };
+        batch::process_directory(&args, finder.as_mut(), &config, &ctx, &report)?;
+    } else {
+        // Process single file
+
+        let input = args.input();
+        let (default_output, prefetched) = if net::is_url(input) {
+            let url = input.to_string_lossy();
+            let timeout = args
+                .timeout
+                .map_or(net::DEFAULT_TIMEOUT, Duration::from_millis);
+            let (img, format) = net::fetch(&url, timeout)?;
+            let name = net::file_name(&url, format);
+            (
+                generate_cropped_filename(&name, args.normalize_filename, crop_suffix(&args))?,
+                Some(Ok(img)),
+            )
+        } else {
+            (generate_default_output_path(input, &args)?, None)
+        };
+        let output_path = match expand_output(&args, &ctx, input) {
+            Some(p) if !ctx.to_stdout => p,
+            _ => default_output,
+        };
+        let output_path = with_mask_format(output_path, &args, &ctx);
+        if let Some(parent) = output_path.parent().filter(|_| has_output_tokens(&args)) {
+            fs::create_dir_all(parent).context("Failed to create output directory")?;
+        }
+
+        if is_up_to_date(&args, &output_path) {
+            say(&args, format!("Up to date: {:?}", args.input()));
+            return Ok(());
+        }
+
+        match process_image(input, output_path, finder.as_mut(), &args, &ctx, prefetched) {
+            Ok(_) if args.prints_only() => {}
+            Ok(outcome) if outcome.placeholder => {
+                say(
+                    &args,
+                    format!("No face found, wrote placeholder: {:?}", input),
+                );
+            }
+            Ok(outcome) => {
+                say(&args, format!("Successfully processed: {:?}", input));
+                if outcome.duplicate_crops > 0 {
+                    say(
+                        &args,
+                        format!("Skipped {} duplicate crop(s).", outcome.duplicate_crops),
+                    );
+                }
+                if let Some(hash) = outcome.blurhash {
+                    say(&args, format!("Blurhash: {}", hash));
+                }
+            }
+            Err(e) => eprintln!("Error processing {:?}: {}", args.input(), e),
+        }
+    }
+
+    if let Some(clusters) = ctx.clusters
+        && let Some(dir) = &args.output
+    {
+        let sorted = clusters.finish(dir)?;
+        say(
+            &args,
+            format!(
+                "Sorted {} crop(s) into {} cluster(s); {} matched no other crop and went to unclustered/.",
+                sorted.clustered + sorted.unclustered,
+                sorted.clusters,
+                sorted.unclustered
+            ),
+        );
+    }
+
+    if let Some(zip) = ctx.zip {
+        if let Some(manifest) = &args.manifest {
+            let data = fs::read(manifest).context("Failed to read manifest")?;
+            let name = manifest.file_name().unwrap_or_default().to_string_lossy();
+            zip.add(&name, &data, true)?;
+        }
+        zip.finish()?;
+    }
+
+    if let Some(tar) = ctx.tar {
+        if let Some(manifest) = &args.manifest {
+            let data = fs::read(manifest).context("Failed to read manifest")?;
+            let name = manifest.file_name().unwrap_or_default().to_string_lossy();
+            tar.add(&name, &data)?;
+        }
+        tar.finish()?;
+    }
+
+    if let Some(annotations) = ctx.annotations {
+        annotations.finish()?;
+    }
+
+    if let Some(hook) = ctx.hook {
+        hook.finish();
+    }
+
+    if let Some(session) = profiling {
+        let (table, folded) = profile::report(session, started.elapsed());
+        eprint!("{}", table);
+        if let Some(path) = &args.profile_folded {
+            fs::write(path, folded).context("Failed to write --profile-folded file")?;
+        }
+    }
+
+    // The temp file is automatically deleted when 'model_temp_file' goes out of scope here.
+    Ok(())
+}
+
+
+/// Print a progress message: on stdout, unless stdout carries the results.
+fn say(args: &Args, message: impl Display) {
+    if args.stdout_is_data() {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+
+/// The `--backend` detector for `config`, warmed up with --detector-warmup.
+fn create_detector(args: &Args, config: &DetectorConfig) -> Result<Box<dyn FaceDetector>> {
+    let mut detector = args
+        .backend
+        .create(config, args.timeout.map(Duration::from_millis))?;
+    if args.detector_warmup {
+        detection::warm_up(detector.as_mut()).context("Detector warm-up failed")?;
+    }
+    Ok(detector)
+}
+
+
+/// Write `MODEL_BYTES` to a temp file (in --temp-dir if given, otherwise the
+/// system temp dir, which honors TMPDIR).
+fn write_embedded_model(args: &Args) -> Result<tempfile::NamedTempFile> {
+    let mut temp_builder = tempfile::Builder::new();
+    temp_builder.suffix(".bin");
+
+    let mut model_temp_file = match &args.temp_dir {
+        Some(dir) => temp_builder.tempfile_in(dir),
+        None => temp_builder.tempfile(),
+    }
+    .context("Failed to create temp file for model")?;
+
+    model_temp_file
+        .write_all(MODEL_BYTES)
+        .context("Failed to write model bytes")?;
+
+    Ok(model_temp_file)
+}
+
+
+fn parse_quality_weights(s: &str) -> Result<QualityWeights, String> {
+    let weights = s
+        .split(',')
+        .map(|part| {
+            let value: f64 = part
+                .trim()
+                .parse()
+                .map_err(|_| format!("`{}` is not a number", part))?;
+            if !value.is_finite() {
+                Err(format!("`{}` is not a finite number", part.trim()))
+            } else if value >= 0.0 {
+                Ok(value)
+            } else {
+                Err(format!("weight {} is negative", value))
+            }
+        })
+        .collect::<Result<Vec<f64>, String>>()?;
+    let [sharpness, size, center] = weights[..] else {
+        return Err("expected three weights: SHARPNESS,SIZE,CENTER".to_string());
+    };
+    if sharpness + size + center <= 0.0 {
+        return Err("at least one weight must be above 0".to_string());
+    }
+    Ok(QualityWeights {
+        sharpness,
+        size,
+        center,
+    })
+}
+
+
+fn parse_fraction(s: &str) -> Result<f64, String> {
+    let value: f64 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+    if (0.0..=1.0).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("{} is not between 0 and 1", value))
+    }
+}
+
+
+fn parse_suffix(s: &str) -> Result<String, String> {
+    if s.contains(['/', '\\']) {
+        Err(format!(
+            "`{}` would name a directory; leave out `/` and `\\`",
+            s
+        ))
+    } else {
+        Ok(s.to_string())
+    }
+}
+
+
+fn parse_landmark_padding(s: &str) -> Result<f64, String> {
+    let value: f64 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+    if (0.0..=2.0).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("{} is not between 0 and 2", value))
+    }
+}
+
+
+fn parse_score_thresh(s: &str) -> Result<f64, String> {
+    let value: f64 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+    // rustface panics on thresholds of 0 and below.
+    if value > 0.0 {
+        Ok(value)
+    } else {
+        Err(format!("{} is not greater than 0", value))
+    }
+}
+
+
+/// `N` or `XxY`, each at least 1.
+fn parse_window_step(s: &str) -> Result<(u32, u32), String> {
+    let step = |part: &str| match part.trim().parse::<u32>() {
+        Ok(n) if n >= 1 => Ok(n),
+        _ => Err(format!(
+            "`{}` is not a step of N or XxY pixels (at least 1)",
+            s
+        )),
+    };
+    match s.split_once(['x', 'X']) {
+        Some((x, y)) => Ok((step(x)?, step(y)?)),
+        None => step(s).map(|n| (n, n)),
+    }
+}
+
+
+fn parse_pyramid_scale(s: &str) -> Result<f32, String> {
+    let value: f32 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+    // rustface panics outside this range.
+    if (0.01..=0.99).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("{} is not between 0.01 and 0.99", value))
+    }
+}
+
+
+fn parse_upscale_factor(s: &str) -> Result<f64, String> {
+    let value: f64 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+    if value > 1.0 && value <= MAX_UPSCALE_FACTOR {
+        Ok(value)
+    } else {
+        Err(format!(
+            "{} is not above 1 and at most {}",
+            value, MAX_UPSCALE_FACTOR
+        ))
+    }
+}
+
+
+fn parse_non_negative(s: &str) -> Result<f64, String> {
+    let value: f64 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+    if !value.is_finite() {
+        Err(format!("`{}` is not a finite number", s))
+    } else if value >= 0.0 {
+        Ok(value)
+    } else {
+        Err(format!("{} is not 0 or more", value))
+    }
+}
+
+
+fn parse_aspect(s: &str) -> Result<f64, String> {
+    let value: f64 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+    if value > 0.0 && value.is_finite() {
+        Ok(value)
+    } else {
+        Err(format!("{} is not a positive ratio", value))
+    }
+}
+
+
+/// Whether `a` and `b` name the same file or directory.
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+
+/// `output::save_image`, retried with exponential backoff per
+/// --retry-on-save-error. A failed attempt's partial file is removed so a
+/// final failure does not leave a truncated image behind.
+fn save_with_retry(img: &DynamicImage, path: &Path, args: &Args, ctx: &RunContext) -> Result<()> {
+    let mut delay = SAVE_RETRY_DELAY;
+    let mut attempt = 0;
+
+    loop {
+        match output::save_image(img, path, &ctx.save) {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(path);
+                if attempt == args.retry_on_save_error {
+                    return Err(e);
+                }
+                attempt += 1;
+                if args.verbose {
+                    eprintln!(
+                        "  save failed ({}), retry {}/{} in {} ms",
+                        e,
+                        attempt,
+                        args.retry_on_save_error,
+                        delay.as_millis()
+                    );
+                }
+                thread::sleep(delay);
+                delay *= 2;
+            }
+        }
+    }
+}
+
+
+/// `--output` with its date tokens expanded for `input`.
+fn expand_output(args: &Args, ctx: &RunContext, input: &Path) -> Option<PathBuf> {
+    args.output.as_ref().map(|output| {
+        let date = template::date_for(args.output_date, input, &ctx.started);
+        template::expand(output, &date)
+    })
+}
+
+
+fn has_output_tokens(args: &Args) -> bool {
+    args.output.as_deref().is_some_and(template::has_tokens)
+}
+
+
+/// The directory crop paths are relative to: the input directory, or the
+/// input file's directory.
+fn input_root(args: &Args) -> &Path {
+    let input = args.input();
+    if input.is_dir() {
+        input
+    } else {
+        input.parent().unwrap_or(Path::new(""))
+    }
+}
+
+
+fn crop_options(args: &Args) -> CropOptions {
+    CropOptions {
+        strategy: args.face_crop_strategy,
+        margin: args.crop_margin,
+        pad_pixels: args.pad_pixels,
+        square: !args.no_square,
+        even: args.even_dimensions,
+        top_extend: args.top_extend,
+        keep_centered: args.exclude_center_bias,
+        basis: args.square_basis,
+    }
+}
+
+
+/// Whether --skip-on-existing-sidecar applies to the crop at `output_path`.
+fn is_up_to_date(args: &Args, output_path: &Path) -> bool {
+    args.skip_on_existing_sidecar && !args.refresh && sidecar::path_for(output_path).exists()
+}
+
+
+/// Copy `input_path` next to `output_path`, unless that is where it already
+/// lives.
+fn keep_original(input_path: &Path, output_path: &Path) -> Result<()> {
+    let (Some(src_dir), Some(dest_dir)) = (input_path.parent(), output_path.parent()) else {
+        return Ok(());
+    };
+    if same_path(src_dir, dest_dir) {
+        return Ok(());
+    }
+
+    let dest = dest_dir.join(input_path.file_name().context("Input has no file name")?);
+    fs::copy(input_path, &dest)
+        .with_context(|| format!("Failed to copy original to {:?}", dest))?;
+    Ok(())
+}
+
+
+fn is_image_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .map(|ext| {
+            let e = ext.to_lowercase();
+            matches!(
+                e.as_str(),
+                "jpg" | "jpeg" | "png" | "bmp" | "tif" | "tiff" | "webp"
+            )
+        })
+        .unwrap_or(false)
+        || raw::is_raw(path)
+        || video::is_video(path)
+}
+
+
+/// The input's extension, except for raw files, whose crops are saved as
+/// JPEG.
+fn output_extension(input_path: &Path) -> Option<&OsStr> {
+    if raw::is_raw(input_path) {
+        Some(OsStr::new(raw::OUTPUT_EXTENSION))
+    } else {
+        input_path.extension()
+    }
+}
+
+
+fn generate_default_output_path(input_path: &Path, args: &Args) -> Result<PathBuf> {
+    Ok(input_path.with_file_name(generate_cropped_filename(
+        input_path,
+        args.normalize_filename,
+        crop_suffix(args),
+    )?))
+}
+
+
+/// `--output` value that sends the crop to stdout.
+const STDOUT: &str = "-";
+
+
+/// Appended to the input's file stem to name its crop.
+const CROPPED_SUFFIX: &str = "_cropped";
+
+/// The suffix crops of this run are named with: --suffix, the
+/// --output-suffix-per-mode suffix of the active mask, or `_cropped`.
+fn crop_suffix(args: &Args) -> &str {
+    match &args.suffix {
+        Some(suffix) => suffix,
+        None if !args.output_suffix_per_mode => CROPPED_SUFFIX,
+        None if args.chroma_key.is_some() => "_keyed",
+        None if args.feather.is_some() => "_feathered",
+        None if args.no_square => "_rect",
+        None => "_square",
+    }
+}
+
+/// Replaces `CROPPED_SUFFIX` in --face-region-export file names.
+const BBOX_SUFFIX: &str = "_bbox";
+
+
+/// `<stem><suffix>.<ext>` for `input_path`, with the stem reduced to safe
+/// characters if `normalize` is set (--normalize-filename).
+fn generate_cropped_filename(input_path: &Path, normalize: bool, suffix: &str) -> Result<PathBuf> {
+    let stem = input_path
+        .file_stem()
+        .context("Input file has no file name")?;
+
+    let mut new_filename = if normalize {
+        filename::normalize(stem).into()
+    } else {
+        stem.to_os_string()
+    };
+    new_filename.push(suffix);
+
+    if let Some(ext) = output_extension(input_path) {
+        new_filename.push(".");
+        new_filename.push(ext);
+    }
+
+    Ok(PathBuf::from(new_filename))
+}
+
+
+/// `path`, or the same name in --mask-format if the format it names would
+/// lose the crop's transparency.
+fn with_mask_format(path: PathBuf, args: &Args, ctx: &RunContext) -> PathBuf {
+    match output::mask_path(&ctx.save, &path, args.mask_format) {
+        Some((masked, format, option)) => {
+            ctx.mask_format_warning.call_once(|| {
+                eprintln!(
+                    "Warning: {:?} cannot store the transparency of {}; saving such crops as {:?} instead.",
+                    format, option, args.mask_format
+                )
+            });
+            masked
+        }
+        None => path,
+    }
+}
+
+
+/// `path` with a `.png` extension if it names a JPEG and
+/// --prefer-format-lossless-for-small-crops applies to `crop`. `None` if
+/// the crop is saved as named.
+fn with_small_crop_format(
+    path: &Path,
+    crop: &DynamicImage,
+    args: &Args,
+    ctx: &RunContext,
+) -> Option<PathBuf> {
+    let longer = crop.width().max(crop.height());
+    if !args.prefer_format_lossless_for_small_crops
+        || longer > args.lossless_threshold
+        || ctx.to_stdout
+        || ImageFormat::from_path(path).ok() != Some(ImageFormat::Jpeg)
+    {
+        return None;
+    }
+    ctx.lossless_warning.call_once(|| {
+        eprintln!(
+            "Warning: saving crops of at most {}px as PNG instead of JPEG \
+             (--prefer-format-lossless-for-small-crops); their names end in .png.",
+            args.lossless_threshold
+        )
+    });
+    if args.verbose {
+        eprintln!("  {}px crop saved as PNG", longer);
+    }
+    Some(path.with_extension("png"))
+}
+
+
+/// Per-run state shared by every image (and every worker thread).
+pub struct RunContext {
+    pub save: SaveOptions,
+    pub dedupe: Option<Deduper>,
+    /// Crops saved so far, with --cluster-by-similarity.
+    pub clusters: Option<Clusterer>,
+    pub burst: Option<BurstSmoother>,
+    pub chroma: Option<ChromaKey>,
+    /// Crop names handed out so far, with --normalize-filename.
+    pub names: Option<NameRegistry>,
+    /// Next crop number, with --number-outputs.
+    pub numbering: Option<Numbering>,
+    pub preview: Option<TermPreview>,
+    pub zip: Option<ZipOutput>,
+    pub tar: Option<TarOutput>,
+    pub s3: Option<S3Output>,
+    /// The crop goes to stdout (`--output -`) rather than to the output path,
+    /// which then only decides the format.
+    pub to_stdout: bool,
+    pub annotations: Option<Annotations>,
+    pub index: Option<OutputIndex>,
+    pub hook: Option<Hook>,
+    /// Size every crop is resized to, once --resize-crops-to-median has
+    /// measured the batch.
+    pub crop_size: OnceLock<(u32, u32)>,
+    /// Run start, for `--output-date now`.
+    pub started: DateTime<Local>,
+    /// Printed by the first crop switched to --mask-format.
+    pub mask_format_warning: Once,
+    /// Printed by the first crop switched to PNG for being small.
+    pub lossless_warning: Once,
+    /// Printed by the first image `hint_face_size` finds suspicious.
+    pub face_size_hint: Once,
+    /// The detector settings in effect, for --debug-pyramid and --heatmap.
+    pub detector: DetectorConfig,
+}
+
+impl RunContext {
+    /// Whether crops go into --output-zip, --output-tar or S3 rather than
+    /// into files.
+    fn collects_crops(&self) -> bool {
+        self.zip.is_some() || self.tar.is_some() || self.s3.is_some()
+    }
+}
+
+
+/// What a successful `process_image` call found, for run statistics.
+pub struct ImageOutcome {
+    pub faces_detected: usize,
+    /// BlurHash of the saved crop, with `--blurhash`.
+    pub blurhash: Option<String>,
+    /// No face was found and a placeholder was saved instead.
+    pub placeholder: bool,
+    /// The crop of the (largest) face, when it is saved under another name
+    /// than the requested output path: numbered with --largest-n or
+    /// --number-outputs, or with a `.png` extension by
+    /// --prefer-format-lossless-for-small-crops.
+    pub indexed_output: Option<PathBuf>,
+    /// Why the crop was saved but is of doubtful use (--crop-quality-check).
+    pub warning: Option<String>,
+    /// Detector score of the (first) cropped face.
+    pub score: Option<f64>,
+    /// Quality score of the (first) cropped face.
+    pub quality: Option<f64>,
+    /// --largest-n crops skipped by --reject-duplicate-output.
+    pub duplicate_crops: usize,
+}
+
+
+/// The grayscale image the detector scans for `img`.
+fn detection_gray(img: &DynamicImage, args: &Args) -> GrayImage {
+    if args.auto_white_balance {
+        balanced_gray(&detection::white_balance(img), args)
+    } else {
+        balanced_gray(img, args)
+    }
+}
+
+
+/// `detection_gray` for an image already white-balanced, or not to be.
+fn balanced_gray(img: &DynamicImage, args: &Args) -> GrayImage {
+    let gray = args.grayscale_conversion.apply(img);
+    let gray = if args.auto_contrast {
+        detection::equalize(&gray)
+    } else {
+        gray
+    };
+    upscale_small(gray, args)
+}
+
+
+/// `gray` enlarged by --detect-upscale-factor if its longer side is under
+/// the --detect-upscale-small size.
+fn upscale_small(gray: GrayImage, args: &Args) -> GrayImage {
+    match args.detect_upscale_small {
+        Some(limit) if gray.width().max(gray.height()) < limit => {
+            let scale = |side: u32| (side as f64 * args.detect_upscale_factor).round() as u32;
+            image::imageops::resize(
+                &gray,
+                scale(gray.width()),
+                scale(gray.height()),
+                FilterType::CatmullRom,
+            )
+        }
+        _ => gray,
+    }
+}
+
+
+/// `faces` found in a copy of an image `width` pixels wide scaled to
+/// `scaled_width`, moved back into the image's own coordinates.
+fn unscale_faces(mut faces: Vec<FaceInfo>, width: u32, scaled_width: u32) -> Vec<FaceInfo> {
+    if width == scaled_width {
+        return faces;
+    }
+    let ratio = width as f64 / scaled_width as f64;
+    let map = |v: f64| (v * ratio).round();
+    for face in &mut faces {
+        let b = *face.bbox();
+        *face.bbox_mut() = Rectangle::new(
+            map(b.x() as f64) as i32,
+            map(b.y() as f64) as i32,
+            (map(b.width() as f64) as u32).max(1),
+            (map(b.height() as f64) as u32).max(1),
+        );
+    }
+    faces
+}
+
+
+/// Fewest faces in an image for `hint_face_size` to consider the minimum
+/// face size too small.
+const HINT_MIN_FACES: usize = 10;
+
+/// Longer image side, in multiples of the minimum face size, from which
+/// `hint_face_size` considers the minimum too small for the image.
+const HINT_SIZE_RATIO: u32 = 100;
+
+
+/// Suggest a larger minimum face size, once per run and unless --no-hints
+/// is set, when `faces` in the image at `input_path` are many and mostly
+/// near the minimum, in an image far larger than it: the usual sign of a
+/// big photo scanned at a scale where texture passes for faces.
+fn hint_face_size(
+    input_path: &Path,
+    img: &DynamicImage,
+    faces: &[FaceInfo],
+    args: &Args,
+    ctx: &RunContext,
+) {
+    if args.no_hints || faces.len() < HINT_MIN_FACES || ctx.detector.min_face_relative.is_some() {
+        return;
+    }
+    let (width, height) = img.dimensions();
+    let min_face = ctx.detector.params_for(width, height).min_face_size;
+    let mut sizes: Vec<u32> = faces.iter().map(|face| face.bbox().width()).collect();
+    sizes.sort_unstable();
+    if width.max(height) < min_face * HINT_SIZE_RATIO || sizes[sizes.len() / 2] >= 2 * min_face {
+        return;
+    }
+
+    ctx.face_size_hint.call_once(|| {
+        eprintln!(
+            "Hint: {:?} ({}x{}) has {} faces, most under {}px, at a minimum face size of {}px; \
+             many are likely false detections. A larger --min-face-size, or \
+             --min-face-relative, suits images this large (--no-hints hides this).",
+            batch::display_name(args, input_path),
+            width,
+            height,
+            faces.len(),
+            2 * min_face,
+            min_face
+        )
+    });
+}
+
+
+/// Run the detector on `img` and apply the face filters.
+fn detect_faces(
+    img: &DynamicImage,
+    finder: &mut dyn FaceDetector,
+    args: &Args,
+) -> Result<Vec<FaceInfo>> {
+    let gray = profile::time(Phase::Grayscale, || detection_gray(img, args));
+    let scaled_width = gray.width();
+    let gray_size = gray.dimensions();
+    // Landmark lookups need the image again after detection consumes it.
+    let landmark_gray =
+        (args.min_eye_distance.is_some() || args.frame_from_landmarks).then(|| gray.clone());
+
+    let mut faces: Vec<FaceInfo> = profile::time(Phase::Detect, || match args.tile_size {
+        Some(tile) if gray.width().max(gray.height()) > tile => {
+            let (faces, tiles) = detection::detect_tiled(finder, &gray, tile, args.tile_overlap)?;
+            if args.verbose {
+                eprintln!("  scanned {} tiles: {} face(s)", tiles, faces.len());
+            }
+            Ok(faces)
+        }
+        _ if args.two_stage_detect => {
+            let (faces, regions) = detection::detect_two_stage(
+                finder,
+                &gray,
+                args.coarse_size,
+                args.candidate_padding,
+            )?;
+            if args.verbose {
+                eprintln!(
+                    "  two-stage: rescanned {} region(s): {} face(s)",
+                    regions,
+                    faces.len()
+                );
+            }
+            Ok(faces)
+        }
+        _ => finder.detect(gray),
+    })?;
+    if args.verbose && scaled_width != img.width() {
+        eprintln!(
+            "  upscaled {}x for detection: {} face(s)",
+            args.detect_upscale_factor,
+            faces.len()
+        );
+    }
+
+    if args.explain {
+        let mut found = format!(
+            "Detected {} face(s) on the {}x{} grayscale copy",
+            faces.len(),
+            gray_size.0,
+            gray_size.1
+        );
+        if scaled_width != img.width() {
+            found.push_str(" (upscaled for detection; boxes below are in image pixels)");
+        }
+        for face in unscale_faces(faces.clone(), img.width(), scaled_width) {
+            found.push_str(&format!("\n  face at {}", explain::face(&face)));
+        }
+        say(args, found);
+    }
+
+    if args.auto_white_balance && args.verbose {
+        let without = finder.detect(balanced_gray(img, args))?.len();
+        if without == faces.len() {
+            eprintln!("  auto-white-balance: no change ({} face(s))", without);
+        } else {
+            eprintln!(
+                "  auto-white-balance: {} face(s), {} without it",
+                faces.len(),
+                without
+            );
+        }
+    }
+
+    if args.auto_contrast && args.verbose {
+        let balanced = args
+            .auto_white_balance
+            .then(|| detection::white_balance(img));
+        let plain = upscale_small(
+            args.grayscale_conversion
+                .apply(balanced.as_ref().unwrap_or(img)),
+            args,
+        );
+        let without = finder.detect(plain)?.len();
+        if without == faces.len() {
+            eprintln!("  auto-contrast: no change ({} face(s))", without);
+        } else {
+            eprintln!(
+                "  auto-contrast: {} face(s), {} without it",
+                faces.len(),
+                without
+            );
+        }
+    }
+
+    if let Some(fraction) = args.relative_face_threshold {
+        let (kept, dropped) = selection::filter_relative_size(faces, fraction);
+        if args.verbose {
+            for face in &dropped {
+                let b = face.bbox();
+                eprintln!(
+                    "  dropped small face at ({}, {}) {}x{} (score {:.2})",
+                    b.x(),
+                    b.y(),
+                    b.width(),
+                    b.height(),
+                    face.score()
+                );
+            }
+        }
+        if args.explain {
+            for face in unscale_faces(dropped, img.width(), scaled_width) {
+                say(
+                    args,
+                    format!(
+                        "Dropped face at {}: under {} of the largest face's area \
+                         (--relative-face-threshold)",
+                        explain::face(&face),
+                        fraction
+                    ),
+                );
+            }
+        }
+        faces = kept;
+    }
+
+    if let Some(max) = args.max_face_size {
+        // Boxes are in the scanned copy; measure in image pixels.
+        let (kept, dropped): (Vec<FaceInfo>, Vec<FaceInfo>) = faces.into_iter().partition(|face| {
+            let side = face.bbox().width().max(face.bbox().height());
+            side as u64 * img.width() as u64 <= max as u64 * scaled_width as u64
+        });
+        let dropped = unscale_faces(dropped, img.width(), scaled_width);
+        for face in &dropped {
+            if args.verbose {
+                let b = face.bbox();
+                eprintln!(
+                    "  dropped large face at ({}, {}) {}x{} (score {:.2})",
+                    b.x(),
+                    b.y(),
+                    b.width(),
+                    b.height(),
+                    face.score()
+                );
+            }
+            if args.explain {
+                say(
+                    args,
+                    format!(
+                        "Dropped face at {}: over --max-face-size {}",
+                        explain::face(face),
+                        max
+                    ),
+                );
+            }
+        }
+        faces = kept;
+    }
+
+    if let (Some(min), Some(gray)) = (args.min_eye_distance, &landmark_gray) {
+        faces.retain(|face| match profile::time(Phase::Detect, || finder.eyes(gray, face)) {
+            Some(eyes) => {
+                // Eyes are found in the scanned copy; measure in image pixels.
+                let distance = eyes.distance() * img.width() as f64 / scaled_width as f64;
+                if args.verbose {
+                    let b = face.bbox();
+                    eprintln!(
+                        "  eye distance {:.1}px for face at ({}, {}){}",
+                        distance,
+                        b.x(),
+                        b.y(),
+                        if distance < min { ", dropped" } else { "" }
+                    );
+                }
+                if args.explain && distance < min {
+                    let face = &unscale_faces(vec![face.clone()], img.width(), scaled_width)[0];
+                    say(
+                        args,
+                        format!(
+                            "Dropped face at {}: eyes {:.1}px apart, under --min-eye-distance {}",
+                            explain::face(face),
+                            distance,
+                            min
+                        ),
+                    );
+                }
+                distance >= min
+            }
+            None => {
+                NO_LANDMARKS_WARNING.call_once(|| {
+                    eprintln!(
+                        "Warning: the {:?} backend does not locate eyes; --min-eye-distance is ignored.",
+                        args.backend
+                    )
+                });
+                true
+            }
+        });
+    }
+
+    if args.frame_from_landmarks
+        && let Some(gray) = &landmark_gray
+    {
+        for face in &mut faces {
+            let framed = profile::time(Phase::Detect, || finder.landmarks(gray, face))
+                .and_then(|landmarks| landmarks.framed(args.landmark_padding));
+            match framed {
+                Some(framed) => {
+                    if args.verbose {
+                        let b = face.bbox();
+                        eprintln!(
+                            "  landmark framing: ({}, {}) {}x{} -> ({}, {}) {}x{}",
+                            b.x(),
+                            b.y(),
+                            b.width(),
+                            b.height(),
+                            framed.x(),
+                            framed.y(),
+                            framed.width(),
+                            framed.height()
+                        );
+                    }
+                    *face.bbox_mut() = framed;
+                }
+                None => NO_FRAMING_LANDMARKS_WARNING.call_once(|| {
+                    eprintln!(
+                        "Warning: no landmarks from the {:?} backend; --frame-from-landmarks \
+                         falls back to the detector box.",
+                        args.backend
+                    )
+                }),
+            }
+        }
+    }
+
+    Ok(unscale_faces(faces, img.width(), scaled_width))
+}
+
+
+/// Apply the face-count validation and return the index of the face to crop.
+fn choose_face(faces: &[FaceInfo], dimensions: (u32, u32), args: &Args) -> Result<usize> {
+    if faces.is_empty() {
+        return Err(ValidationError::NoFaces.into());
+    }
+    if let Some(min) = args.min_faces.map(|min| min as usize)
+        && faces.len() < min
+    {
+        return Err(ValidationError::TooFewFaces {
+            found: faces.len(),
+            min,
+        }
+        .into());
+    }
+
+    if let Some(n) = args.face_index {
+        return selection::nth_from_left(faces, n).with_context(|| {
+            format!("--face-index {} is out of range ({} found)", n, faces.len())
+        });
+    }
 
-    /// Output path (optional).
-    /// If input is a file: this is the destination file path.
-    /// If input is a directory: this is the destination directory.
-    #[arg(short, long)]
-    output: Option<PathBuf>,
+    match args.select {
+        // Every face goes into the crop, so any count is fine.
+        _ if args.group_crop => Ok(0),
+        // Each of the largest N gets a crop; report the largest.
+        _ if args.largest_n.is_some() => Ok(selection::largest_n(faces, 1)[0]),
+        Some(strategy) => Ok(select_face(faces, strategy, dimensions, args)),
+        None if faces.len() > 1 && args.lenient => {
+            eprintln!(
+                "Warning: {} faces detected, cropping the largest (--lenient).",
+                faces.len()
+            );
+            Ok(select_face(
+                faces,
+                SelectStrategy::Largest,
+                dimensions,
+                args,
+            ))
+        }
+        None if faces.len() > 1 => Err(ValidationError::MultipleFaces(faces.len()).into()),
+        None => Ok(0),
+    }
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
 
-    // 2. Write the embedded model to a temporary file
-    let mut model_temp_file = tempfile::Builder::new()
-        .suffix(".bin")
-        .tempfile()
-        .context("Failed to create temp file for model")?;
+/// The face `strategy` picks from `faces`, which must not be empty, biased
+/// toward the center of the `dimensions` image by --center-weight.
+fn select_face(
+    faces: &[FaceInfo],
+    strategy: SelectStrategy,
+    dimensions: (u32, u32),
+    args: &Args,
+) -> usize {
+    if args.center_weight > 0.0 {
+        selection::select_centered(faces, strategy, args.center_weight, dimensions)
+    } else {
+        selection::select_face(faces, strategy)
+    }
+    .unwrap()
+}
 
-    model_temp_file
-        .write_all(MODEL_BYTES)
-        .context("Failed to write model bytes")?;
 
-    // 3. Get the path of the temp file
-    let model_path = model_temp_file.path();
+/// Decode `path`, with `fallback` giving files the usual decoder rejects a
+/// second try (--retry-decode-with-fallback-backend).
+fn open_image(path: &Path, fallback: bool) -> Result<DynamicImage> {
+    profile::time(Phase::Decode, || {
+        if raw::is_raw(path) {
+            raw::open(path)
+        } else {
+            let result = image::open(path);
+            let result = if fallback {
+                fallback::retry(path, result)
+            } else {
+                result
+            };
+            result.with_context(|| {
+                if is_cmyk_jpeg(path) {
+                    "Failed to open image (could not convert CMYK JPEG to RGB)"
+                } else {
+                    "Failed to open image"
+                }
+            })
+        }
+    })
+}
 
-    // 4. Initialize Detector ONCE
 
-    let mut detector = rustface::create_detector(model_path.to_str().unwrap())
-        .context("Failed to create face detector")?;
+/// Whether `path` is a JPEG with four colour components (CMYK or YCCK).
+///
+/// The decoder converts these to RGB itself; this only lets a failed decode
+/// say why.
+fn is_cmyk_jpeg(path: &Path) -> bool {
+    let Ok(data) = fs::read(path) else {
+        return false;
+    };
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return false;
+    }
 
-    detector.set_min_face_size(20);
-    detector.set_score_thresh(2.0);
-    detector.set_pyramid_scale_factor(0.8);
-    detector.set_slide_window_step(4, 4);
+    // Walk the marker segments up to the frame header.
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xFF {
+        let marker = data[pos + 1];
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let is_frame = (0xC0..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC);
+        if is_frame {
+            return data.get(pos + 9) == Some(&4);
+        }
+        pos += 2 + length;
+    }
+    false
+}
 
 
-    if args.input.is_dir() {
-        process_directory(&args, &mut *detector)?;
-    } else {
-        // Process single file
+/// Try detection at 0, 90, 180 and 270 degrees, keeping the first orientation
+/// that passes validation. On success `img` is replaced by the rotated image so
+/// the crop is taken from it.
+///
+/// If no orientation works, the error from the unrotated image is returned.
+fn detect_any_rotation(
+    img: &mut DynamicImage,
+    finder: &mut dyn FaceDetector,
+    args: &Args,
+) -> Result<(Vec<FaceInfo>, usize)> {
+    let mut first_error = None;
 
-        let output_path = match &args.output {
-            Some(p) => p.clone(),
-            None => generate_default_output_path(&args.input)?,
+    for degrees in [0, 90, 180, 270] {
+        let candidate = match degrees {
+            90 => img.rotate90(),
+            180 => img.rotate180(),
+            270 => img.rotate270(),
+            _ => img.clone(),
         };
 
-        match process_image(&args.input, output_path, &mut *detector) {
-            Ok(_) => println!("Successfully processed: {:?}", args.input),
-            Err(e) => eprintln!("Error processing {:?}: {}", args.input, e),
+        let faces = detect_faces(&candidate, finder, args)?;
+        match choose_face(&faces, candidate.dimensions(), args) {
+            Ok(index) => {
+                if args.verbose {
+                    eprintln!("  face found at rotation {} degrees", degrees);
+                }
+                *img = candidate;
+                return Ok((faces, index));
+            }
+            Err(e) => {
+                if args.verbose {
+                    eprintln!("  rotation {} degrees: {}", degrees, e);
+                }
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    Err(first_error.unwrap())
+}
+
+
+/// Detect, validate, crop and save one image.
+///
+/// Pipeline order: decode at full resolution, detect (on a luma copy, possibly
+/// rotated), validate, compute the crop rectangle in full-resolution pixel
+/// coordinates, then crop from the full-resolution `img` and convert/save.
+/// Any step that works on a reduced copy, such as detection, must map its
+/// results back to full-resolution coordinates before the crop so output
+/// quality never depends on it.
+fn process_image(
+    input_path: &Path,
+    output_path: PathBuf,
+    finder: &mut dyn FaceDetector,
+    args: &Args,
+    ctx: &RunContext,
+    prefetched: Prefetched,
+) -> Result<ImageOutcome> {
+    if !args.in_place
+        && !args.prints_only()
+        && !ctx.collects_crops()
+        && same_path(input_path, &output_path)
+    {
+        bail!(
+            "Refusing to overwrite the input {:?} with its crop; pass --in-place to replace it",
+            input_path
+        );
+    }
+
+    if video::is_video(input_path) {
+        if args.prints_only() || ctx.collects_crops() {
+            bail!(
+                "Video clips cannot be used with --coords-only, --data-uri, --output-zip, --output-tar or s3:// output"
+            );
         }
+        let output_path = numbered(output_path, ctx);
+        return video::process(input_path, &output_path, finder, args).map(|mut outcome| {
+            outcome.indexed_output.get_or_insert(output_path);
+            outcome
+        });
     }
 
-    // The temp file is automatically deleted when 'model_temp_file' goes out of scope here.
-    Ok(())
+    if !args.prints_only() {
+        output::check_format(&ctx.save, &output_path)?;
+    }
+    if args.all_frames && frames::is_animated(input_path) {
+        // All frames share the animation's number.
+        return process_frames(input_path, &numbered(output_path, ctx), finder, args, ctx);
+    }
+    let img = match prefetched {
+        Some(decoded) => decoded?,
+        None => open_image(input_path, args.retry_decode_with_fallback_backend)?,
+    };
+    process_decoded(input_path, output_path, img, finder, args, ctx)
 }
 
 
-fn process_directory(args: &Args, detector: &mut dyn rustface::Detector) -> Result<()> {
-    let entries = fs::read_dir(&args.input).context("Failed to read input directory")?;
+/// --all-frames: crop every frame of the animated image at `input_path`
+/// like an image of its own, saved as `output_path` with `_frame<N>`
+/// added. Frames that fail are skipped, and noted with --verbose. Succeeds
+/// with the outcome of the first frame cropped, or fails like the first
+/// frame if none is.
+fn process_frames(
+    input_path: &Path,
+    output_path: &Path,
+    finder: &mut dyn FaceDetector,
+    args: &Args,
+    ctx: &RunContext,
+) -> Result<ImageOutcome> {
+    let mut first: Option<ImageOutcome> = None;
+    let mut first_error = None;
+    let mut frame_count = 0;
+    let mut cropped = 0;
+    frames::for_each_frame(input_path, |n, frame| {
+        frame_count += 1;
+        let path = frames::frame_path(output_path, n);
+        match process_decoded(input_path, path.clone(), frame, finder, args, ctx) {
+            Ok(mut outcome) => {
+                cropped += 1;
+                if first.is_none() {
+                    outcome.indexed_output.get_or_insert(path);
+                    first = Some(outcome);
+                }
+            }
+            Err(e) => {
+                if args.verbose {
+                    eprintln!("  frame {}: {:#}", n, e);
+                }
+                first_error.get_or_insert(e);
+            }
+        }
+        Ok(())
+    })?;
 
-    // If output dir is specified, create it if it doesn't exist
-    if let Some(out_dir) = &args.output {
-        fs::create_dir_all(out_dir).context("Failed to create output directory")?;
+    let Some(mut outcome) = first else {
+        match first_error {
+            Some(e) => return Err(e),
+            None => bail!("Animation has no frames"),
+        }
+    };
+    if args.verbose {
+        eprintln!("  cropped {} of {} frame(s)", cropped, frame_count);
+    }
+    if cropped < frame_count {
+        let skipped = format!("{} of {} frame(s) cropped", cropped, frame_count);
+        outcome.warning = Some(match outcome.warning {
+            Some(warning) => format!("{}; {}", warning, skipped),
+            None => skipped,
+        });
     }
+    Ok(outcome)
+}
 
-    for entry in entries {
-        let entry = entry?;
-        let path = entry.path();
 
+/// `process_image` from the decoded image on.
+fn process_decoded(
+    input_path: &Path,
+    output_path: PathBuf,
+    mut img: DynamicImage,
+    finder: &mut dyn FaceDetector,
+    args: &Args,
+    ctx: &RunContext,
+) -> Result<ImageOutcome> {
+    if args.explain {
+        say(
+            args,
+            format!(
+                "Loaded {:?}: {}x{} ({:?})",
+                input_path,
+                img.width(),
+                img.height(),
+                img.color()
+            ),
+        );
+    }
+    if args.deskew {
+        img = deskew(img, args);
+    }
 
-        if path.is_file() && is_image_extension(&path) {
-            // Calculate output path
-            let output_path = if let Some(out_dir) = &args.output {
-                // If output dir specified: out_dir / filename_cropped.ext
-                let file_name = generate_cropped_filename(&path)?;
-                out_dir.join(file_name)
-            } else {
-                // If no output dir: input_dir / filename_cropped.ext
-                generate_default_output_path(&path)?
-            };
+    check_aspect(&img, args)?;
 
+    if let Some(original) = ctx.dedupe.as_ref().and_then(|d| d.check(&img, input_path)) {
+        return Err(ValidationError::Duplicate(original).into());
+    }
+
+    if args.debug_pyramid.is_some()
+        || args.save_grayscale_detection_image.is_some()
+        || args.heatmap.is_some()
+    {
+        let gray = detection_gray(&img, args);
+        let stem = input_path.file_stem().unwrap_or_default().to_string_lossy();
+        if let Some(dir) = &args.save_grayscale_detection_image {
+            debug::write_detection_image(&gray, dir, &stem)?;
+        }
+        if let Some(dir) = &args.debug_pyramid {
+            debug::write_pyramid(&gray, ctx.detector.pyramid_scale_factor, dir, &stem)?;
+        }
+        if let Some(dir) = &args.heatmap {
+            debug::write_heatmap(&ctx.detector, &gray, dir, &stem)?;
+        }
+    }
+
+    let detected = if args.try_rotations {
+        detect_any_rotation(&mut img, finder, args)
+    } else {
+        detect_faces(&img, finder, args).and_then(|faces| {
+            hint_face_size(input_path, &img, &faces, args, ctx);
+            if let Some(annotations) = &ctx.annotations {
+                annotations.add(input_path, &img, &faces)?;
+            }
+            choose_face(&faces, img.dimensions(), args).map(|index| (faces, index))
+        })
+    };
+    let (faces, face_index) = match detected {
+        Err(e) if args.write_empty_on_no_face && is_no_faces(&e) => {
+            return write_placeholder(&img, input_path, &output_path, args, ctx);
+        }
+        detected => detected?,
+    };
+
+    let targets = crop_targets(&img, &faces, face_index, args)?;
+    if args.explain {
+        let mut picked = format!("Cropping {}:", explain::selection(args, faces.len()));
+        for (index, bbox) in &targets {
+            picked.push_str(&format!(
+                "\n  {}, score {:.2}",
+                explain::region(bbox),
+                faces[*index].score()
+            ));
+        }
+        say(args, picked);
+    }
+
+    if let Some(margin) = args.reject_edge_faces {
+        let (width, height) = img.dimensions();
+        for (_, bbox) in &targets {
+            let overflow = quality::edge_overflow(bbox, width, height);
+            if overflow >= margin as i64 {
+                return Err(ValidationError::EdgeFace {
+                    overflow,
+                    faces: faces.len(),
+                }
+                .into());
+            }
+        }
+    }
+
+    if args.score_min.is_some() || args.score_max.is_some() {
+        for (index, _) in &targets {
+            let score = faces[*index].score();
+            if args.verbose {
+                eprintln!("  face score {:.2}", score);
+            }
+            if args.score_min.is_some_and(|min| score < min)
+                || args.score_max.is_some_and(|max| score > max)
+            {
+                return Err(ValidationError::ScoreOutOfBand {
+                    score,
+                    faces: faces.len(),
+                }
+                .into());
+            }
+        }
+    }
+
+    if args.min_sharpness.is_some() || args.verbose {
+        for (_, bbox) in &targets {
+            let sharpness = quality::sharpness(&img.to_luma8(), bbox);
+            if args.verbose {
+                eprintln!("  face sharpness {:.1}", sharpness);
+            }
+            if let Some(min) = args.min_sharpness.filter(|&min| sharpness < min) {
+                return Err(ValidationError::TooBlurry {
+                    sharpness,
+                    min,
+                    faces: faces.len(),
+                }
+                .into());
+            }
+        }
+    }
+
+    let mut warning = None;
+    if let Some(min) = args.crop_quality_check {
+        let (width, height) = img.dimensions();
+        for (_, bbox) in &targets {
+            let crop = crop_rect(width, height, &faces, bbox, args);
+            if crop.width < min || crop.height < min {
+                if args.reject_low_detail {
+                    return Err(ValidationError::LowDetail {
+                        width: crop.width,
+                        height: crop.height,
+                        min,
+                        faces: faces.len(),
+                    }
+                    .into());
+                }
+                let message = format!(
+                    "low-detail crop {}x{} (under {}px)",
+                    crop.width, crop.height, min
+                );
+                eprintln!("Warning: {:?}: {}", input_path, message);
+                warning.get_or_insert(message);
+            }
+        }
+    }
 
-            match process_image(&path, output_path, detector) {
-                Ok(_) => println!("Processed: {:?}", path.file_name().unwrap()),
-                Err(e) => eprintln!("Skipping {:?}: {}", path.file_name().unwrap(), e),
+    // Only images that get this far take a --number-outputs number, so
+    // rejected ones leave no gap.
+    let output_path = numbered(output_path, ctx);
+    let mut outcome = ImageOutcome {
+        faces_detected: faces.len(),
+        blurhash: None,
+        placeholder: false,
+        indexed_output: None,
+        warning,
+        score: targets.first().map(|&(index, _)| faces[index].score()),
+        quality: None,
+        duplicate_crops: 0,
+    };
+    let (width, height) = img.dimensions();
+    // Crop rectangles saved so far, for --reject-duplicate-output.
+    let mut written: Vec<Rectangle> = Vec::new();
+    let mut rank = 0;
+    for (index, bbox) in &targets {
+        if args.reject_duplicate_output {
+            let crop = crop_rect(width, height, &faces, bbox, args);
+            let crop = Rectangle::new(crop.x as i32, crop.y as i32, crop.width, crop.height);
+            if written
+                .iter()
+                .any(|saved| detection::iou(saved, &crop) > DUPLICATE_CROP_IOU)
+            {
+                if args.verbose {
+                    eprintln!("  skipped duplicate crop {}", explain::region(&crop));
+                }
+                outcome.duplicate_crops += 1;
+                continue;
             }
+            written.push(crop);
+        }
+
+        let path = match args.largest_n {
+            Some(_) => face_output_path(&output_path, rank + 1, args),
+            None => output_path.clone(),
+        };
+        let writes_files = !args.prints_only() && !ctx.collects_crops();
+        if let Some(dir) = path
+            .parent()
+            .filter(|_| args.group_by_source && writes_files)
+        {
+            fs::create_dir_all(dir).context("Failed to create output directory")?;
         }
+        let quality = quality::face_quality(&img, bbox, args.quality_weights);
+        if args.verbose {
+            eprintln!(
+                "  face quality {:.2} (sharpness {:.2}, size {:.2}, center {:.2})",
+                quality.score, quality.sharpness, quality.size, quality.center
+            );
+        }
+        let target = (*index, bbox, quality);
+        let (blurhash, switched) = crop_face(&img, input_path, &path, &faces, target, args, ctx)?;
+
+        if rank == 0 {
+            outcome.blurhash = blurhash;
+            outcome.quality = Some(quality.score);
+            outcome.indexed_output = switched
+                .or(args.largest_n.map(|_| path))
+                .or(ctx.numbering.as_ref().map(|_| output_path.clone()));
+        }
+        rank += 1;
+    }
+
+    Ok(outcome)
+}
+
+
+/// `output_path` with its --number-outputs number, for an image about to be
+/// written.
+fn numbered(output_path: PathBuf, ctx: &RunContext) -> PathBuf {
+    match &ctx.numbering {
+        Some(numbering) => numbering.claim(&output_path),
+        None => output_path,
+    }
+}
+
+
+/// Fail if `img` is outside --min-aspect / --max-aspect.
+fn check_aspect(img: &DynamicImage, args: &Args) -> Result<()> {
+    let ratio = img.width() as f64 / img.height().max(1) as f64;
+    if args.min_aspect.is_some_and(|min| ratio < min)
+        || args.max_aspect.is_some_and(|max| ratio > max)
+    {
+        return Err(ValidationError::AspectRatio(ratio).into());
     }
     Ok(())
 }
 
 
-fn is_image_extension(path: &Path) -> bool {
-    path.extension()
-        .and_then(OsStr::to_str)
-        .map(|ext| {
-            let e = ext.to_lowercase();
-            matches!(
-                e.as_str(),
-                "jpg" | "jpeg" | "png" | "bmp" | "tif" | "tiff" | "webp"
-            )
+/// The boxes to crop from `img`, each with the index of its face in
+/// `faces`: the chosen face, the union of all faces with --group-crop, or
+/// the largest few with --largest-n.
+fn crop_targets(
+    img: &DynamicImage,
+    faces: &[FaceInfo],
+    face_index: usize,
+    args: &Args,
+) -> Result<Vec<(usize, Rectangle)>> {
+    if args.group_crop {
+        let (width, height) = img.dimensions();
+        let group_box =
+            geometry::union_box(faces, width, height).context("Faces lie outside the image")?;
+        Ok(vec![(face_index, group_box)])
+    } else if let Some(n) = args.largest_n {
+        Ok(selection::largest_n(faces, n as usize)
+            .into_iter()
+            .map(|index| (index, *faces[index].bbox()))
+            .collect())
+    } else {
+        Ok(vec![(face_index, *faces[face_index].bbox())])
+    }
+}
+
+
+/// The crop around `bbox` in a `width` x `height` image, sized for the
+/// --crop-from-percentile-face face of `faces` if that is set.
+fn crop_rect(
+    width: u32,
+    height: u32,
+    faces: &[FaceInfo],
+    bbox: &Rectangle,
+    args: &Args,
+) -> CropRect {
+    let sized = args
+        .crop_from_percentile_face
+        .and_then(|p| selection::percentile_size(faces, p))
+        .map(|(w, h)| geometry::box_around(geometry::center_of(bbox), w, h));
+    geometry::compute_crop_rect(
+        width,
+        height,
+        sized.as_ref().unwrap_or(bbox),
+        &crop_options(args),
+    )
+}
+
+
+/// `img` straightened by --deskew, if it is found to be skewed.
+fn deskew(img: DynamicImage, args: &Args) -> DynamicImage {
+    match deskew::skew_angle(&img, args.deskew_max_angle) {
+        Some(angle) => {
+            if args.verbose {
+                eprintln!("  deskewed by {:.1} degrees", angle);
+            }
+            if args.explain {
+                say(
+                    args,
+                    format!("Straightened by {:.1} degrees (--deskew)", angle),
+                );
+            }
+            deskew::straighten(&img, angle)
+        }
+        None => {
+            if args.verbose {
+                eprintln!("  deskew: no skew found");
+            }
+            img
+        }
+    }
+}
+
+
+/// Sizes of the crops `process_image` would cut from `input_path`, found
+/// without writing anything: the first pass of --resize-crops-to-median.
+fn measure_crops(
+    input_path: &Path,
+    finder: &mut dyn FaceDetector,
+    args: &Args,
+) -> Result<Vec<(u32, u32)>> {
+    let mut img = open_image(input_path, args.retry_decode_with_fallback_backend)?;
+    if args.deskew {
+        img = deskew(img, args);
+    }
+    check_aspect(&img, args)?;
+
+    let (faces, face_index) = if args.try_rotations {
+        detect_any_rotation(&mut img, finder, args)?
+    } else {
+        let faces = detect_faces(&img, finder, args)?;
+        let index = choose_face(&faces, img.dimensions(), args)?;
+        (faces, index)
+    };
+
+    let (width, height) = img.dimensions();
+    Ok(crop_targets(&img, &faces, face_index, args)?
+        .iter()
+        .map(|(_, bbox)| {
+            let crop = crop_rect(width, height, &faces, bbox, args);
+            (crop.width, crop.height)
         })
-        .unwrap_or(false)
+        .collect())
 }
 
 
-fn generate_default_output_path(input_path: &Path) -> Result<PathBuf> {
-    let stem = input_path
-        .file_stem()
-        .context("Input file has no file name")?;
+/// Crop the face box `target` (with the index of its face in `faces` and
+/// its quality) out of `img` and write it to `output_path` with its
+/// sidecar, or just print its coordinates with --coords-only. Returns the
+/// crop's BlurHash, and the path it was saved under if
+/// --prefer-format-lossless-for-small-crops changed it.
+fn crop_face(
+    img: &DynamicImage,
+    input_path: &Path,
+    output_path: &Path,
+    faces: &[FaceInfo],
+    (face_index, bbox, quality): (usize, &Rectangle, FaceQuality),
+    args: &Args,
+    ctx: &RunContext,
+) -> Result<(Option<String>, Option<PathBuf>)> {
+    let (width, height) = img.dimensions();
+
+    let placed = match &ctx.burst {
+        Some(burst) => {
+            let (placed, shots) = burst.place(img, bbox);
+            if args.verbose && shots > 1 {
+                eprintln!(
+                    "  burst shot {}, crop centered at ({}, {})",
+                    shots,
+                    placed.x() + placed.width() as i32 / 2,
+                    placed.y() + placed.height() as i32 / 2
+                );
+            }
+            placed
+        }
+        None => *bbox,
+    };
 
-    let mut new_filename = stem.to_os_string();
-    new_filename.push("_cropped");
+    // Calculate Geometry
+    let crop = crop_rect(width, height, faces, &placed, args);
+    // With --pad-mode the crop stays centered and may reach past the edges.
+    let (crop_x, crop_y) = match args.pad_mode {
+        Some(_) => crop.padded_origin,
+        None => (crop.x as i64, crop.y as i64),
+    };
 
-    if let Some(ext) = input_path.extension() {
-        new_filename.push(".");
-        new_filename.push(ext);
+    if args.verbose {
+        eprintln!("  image {}x{}", width, height);
+        eprintln!(
+            "  bbox ({}, {}) {}x{}, center ({}, {})",
+            bbox.x(),
+            bbox.y(),
+            bbox.width(),
+            bbox.height(),
+            crop.center.0,
+            crop.center.1
+        );
+        eprintln!(
+            "  origin ({}, {}) -> ({}, {}), clamped x: {}, clamped y: {}, crop size {}x{}",
+            crop.unclamped_origin.0,
+            crop.unclamped_origin.1,
+            crop.x,
+            crop.y,
+            crop.x != crop.unclamped_origin.0,
+            crop.y != crop.unclamped_origin.1,
+            crop.width,
+            crop.height
+        );
+    }
+
+    if args.explain {
+        let mut framed = format!(
+            "Crop ({}, {}) {}x{} around the face center ({}, {})",
+            crop_x, crop_y, crop.width, crop.height, crop.center.0, crop.center.1
+        );
+        if let Some(mode) = args.pad_mode
+            && ((crop_x, crop_y) != (crop.x as i64, crop.y as i64)
+                || crop.width > width
+                || crop.height > height)
+        {
+            framed.push_str(&format!(
+                ", padded past the edge of the {}x{} image (--pad-mode {})",
+                width,
+                height,
+                format!("{:?}", mode).to_lowercase()
+            ));
+        } else if (crop.x, crop.y) != crop.unclamped_origin {
+            framed.push_str(&format!(
+                ", moved from ({}, {}) to stay inside the {}x{} image",
+                crop.unclamped_origin.0, crop.unclamped_origin.1, width, height
+            ));
+        }
+        say(args, framed);
+    }
+
+    if args.coords_only {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            input_path.display(),
+            crop_x,
+            crop_y,
+            crop.width,
+            crop.height
+        );
+        return Ok((None, None));
+    }
+
+    // Crop and Save. Always from the full-resolution image; resizing or
+    // format conversion only happens after this.
+    let cropped_img = profile::time(Phase::Crop, || {
+        let mut cropped_img = match args.pad_mode {
+            Some(mode) => padding::extend(
+                img,
+                (crop_x, crop_y),
+                (crop.width, crop.height),
+                mode,
+                args.pad_color,
+            ),
+            None => img.crop_imm(crop.x, crop.y, crop.width, crop.height),
+        };
+        if let Some(chroma) = &ctx.chroma {
+            let face = (
+                bbox.x() as i64 - crop_x,
+                bbox.y() as i64 - crop_y,
+                bbox.width(),
+                bbox.height(),
+            );
+            cropped_img = chroma.apply(&cropped_img, chroma::clip(&cropped_img, face));
+        }
+        if let Some(&(width, height)) = ctx.crop_size.get() {
+            cropped_img = cropped_img.resize_exact(width, height, FilterType::Lanczos3);
+        }
+        if args.debug_outline {
+            // The box in crop pixels, scaled along with a resized crop.
+            let sx = cropped_img.width() as f64 / crop.width as f64;
+            let sy = cropped_img.height() as f64 / crop.height as f64;
+            let face = (
+                ((bbox.x() as i64 - crop_x) as f64 * sx).round() as i64,
+                ((bbox.y() as i64 - crop_y) as f64 * sy).round() as i64,
+                (bbox.width() as f64 * sx).round() as u32,
+                (bbox.height() as f64 * sy).round() as u32,
+            );
+            debug::outline(
+                &mut cropped_img,
+                face,
+                args.debug_outline_color,
+                args.debug_outline_width,
+            );
+        }
+        if let Some(template) = &args.label {
+            let text = label::fill(template, input_path, face_index, faces[face_index].score());
+            let style = LabelStyle {
+                position: args.label_position,
+                color: args.label_color,
+                size: args.label_size,
+                background: args.label_background,
+            };
+            label::draw(&mut cropped_img, &text, &style);
+        }
+        cropped_img
+    });
+    if let Some(format) = args.data_uri {
+        let uri = output::data_uri(&cropped_img, format, &ctx.save)?;
+        println!("{}\t{}", input_path.display(), uri);
+        return Ok((None, None));
+    }
+    let switched = with_small_crop_format(output_path, &cropped_img, args, ctx);
+    let output_path = switched.as_deref().unwrap_or(output_path);
+    if args.explain {
+        let format = ImageFormat::from_path(output_path)
+            .map(|format| format!("{:?}", format))
+            .unwrap_or_else(|_| "image".to_string());
+        let (width, height) = cropped_img.dimensions();
+        let destination = if ctx.to_stdout {
+            "stdout".to_string()
+        } else if ctx.collects_crops() {
+            format!(
+                "{:?} in the archive or bucket",
+                archive::entry_name(input_root(args), output_path)
+            )
+        } else {
+            format!("{:?}", output_path)
+        };
+        say(
+            args,
+            format!(
+                "Writing a {}x{} {} to {}",
+                width, height, format, destination
+            ),
+        );
+    }
+    save_crop(&cropped_img, input_path, output_path, args, ctx)?;
+    if let Some(clusters) = &ctx.clusters {
+        clusters.add(&cropped_img, output_path);
+    }
+    if let Some(index) = &ctx.index {
+        let output = if ctx.to_stdout {
+            STDOUT.to_string()
+        } else if ctx.collects_crops() {
+            archive::entry_name(input_root(args), output_path)
+        } else {
+            output_path.display().to_string()
+        };
+        index.add(
+            &output,
+            &IndexEntry {
+                source: input_path.to_path_buf(),
+                face: face_index,
+                score: faces[face_index].score(),
+                quality: quality.score,
+                crop: Region {
+                    x: crop_x,
+                    y: crop_y,
+                    width: crop.width,
+                    height: crop.height,
+                },
+            },
+        )?;
+    }
+    if args.face_region_export {
+        save_face_region(img, output_path, bbox, args, ctx)?;
+    }
+    for &size in &args.sizes {
+        save_sized(&cropped_img, output_path, size, args, ctx)?;
+    }
+
+    let blurhash = args
+        .blurhash
+        .then(|| placeholder::blurhash(&cropped_img, args.blurhash_components))
+        .transpose()?;
+
+    if args.sidecar {
+        let sidecar = Sidecar {
+            input: input_path.to_path_buf(),
+            output: output_path.to_path_buf(),
+            faces_detected: faces.len(),
+            face: Region::from(bbox),
+            score: faces[face_index].score(),
+            quality,
+            crop: Region {
+                x: crop_x,
+                y: crop_y,
+                width: crop.width,
+                height: crop.height,
+            },
+            blurhash: blurhash.clone(),
+        };
+        sidecar::write(output_path, &sidecar)?;
+    }
+
+    if let Some(hook) = &ctx.hook {
+        hook.run(input_path, output_path, faces[face_index].score());
     }
 
-    Ok(input_path.with_file_name(new_filename))
+    Ok((blurhash, switched))
 }
 
 
-fn generate_cropped_filename(input_path: &Path) -> Result<PathBuf> {
-    let stem = input_path
-        .file_stem()
-        .context("Input file has no file name")?;
+/// Where the `rank`th --largest-n crop of an image goes, given the crop path
+/// `path` of the image: `path` numbered with `_<rank>`, or with
+/// --group-by-source `<rank>.<ext>` in a directory named like the crop
+/// without its crop suffix.
+fn face_output_path(path: &Path, rank: usize, args: &Args) -> PathBuf {
+    if !args.group_by_source {
+        return indexed_output_path(path, rank);
+    }
 
-    let mut new_filename = stem.to_os_string();
-    new_filename.push("_cropped");
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let dir = path.with_file_name(stem.strip_suffix(crop_suffix(args)).unwrap_or(&stem));
+    let mut name = PathBuf::from(rank.to_string());
+    if let Some(ext) = path.extension() {
+        name.set_extension(ext);
+    }
+    dir.join(name)
+}
 
-    if let Some(ext) = input_path.extension() {
-        new_filename.push(".");
-        new_filename.push(ext);
+
+/// `path` with `_<index>` appended to its file stem.
+fn indexed_output_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!("_{}", index));
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
     }
+    path.with_file_name(name)
+}
 
-    Ok(PathBuf::from(new_filename))
+
+/// The --face-region-export path for the crop at `path`: its crop suffix
+/// replaced by `_bbox`, or `_bbox` added to its stem.
+fn face_region_path(path: &Path, args: &Args) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = match stem.strip_suffix(crop_suffix(args)) {
+        Some(base) => format!("{}{}", base, BBOX_SUFFIX),
+        None => format!("{}{}", stem, BBOX_SUFFIX),
+    };
+    if let Some(ext) = path.extension() {
+        name.push('.');
+        name.push_str(&ext.to_string_lossy());
+    }
+    path.with_file_name(name)
 }
 
 
-fn process_image(
-    input_path: &Path,
-    output_path: PathBuf,
-    detector: &mut dyn rustface::Detector,
+/// Save the part of `img` inside `bbox`, exactly as detected, for
+/// --face-region-export. A box that lies entirely outside the image is
+/// skipped with a warning.
+fn save_face_region(
+    img: &DynamicImage,
+    output_path: &Path,
+    bbox: &Rectangle,
+    args: &Args,
+    ctx: &RunContext,
 ) -> Result<()> {
-    let mut img = image::open(input_path).context("Failed to open image")?;
     let (width, height) = img.dimensions();
+    let x0 = bbox.x().clamp(0, width as i32) as u32;
+    let y0 = bbox.y().clamp(0, height as i32) as u32;
+    let x1 = (bbox.x() as i64 + bbox.width() as i64).clamp(0, width as i64) as u32;
+    let y1 = (bbox.y() as i64 + bbox.height() as i64).clamp(0, height as i64) as u32;
+    let path = face_region_path(output_path, args);
+    if x1 <= x0 || y1 <= y0 {
+        eprintln!(
+            "Warning: face box of {:?} is empty inside the image; not writing {:?}.",
+            output_path, path
+        );
+        return Ok(());
+    }
 
-    let gray = img.to_luma8();
-    let image_data = ImageData::new(&gray, width, height);
+    let region = img.crop_imm(x0, y0, x1 - x0, y1 - y0);
+    profile::time(Phase::Save, || match collect(&region, &path, args, ctx) {
+        Some(result) => result,
+        None => save_with_retry(&region, &path, args, ctx).context("Failed to save face region"),
+    })
+}
 
-    let faces: Vec<FaceInfo> = detector.detect(&image_data);
 
-    // Validation
-    if faces.is_empty() {
-        bail!("Validation Failed: No faces detected.");
-    } else if faces.len() > 1 {
-        bail!(
-            "Validation Failed: Multiple faces detected (Found {}).",
-            faces.len()
-        );
+/// The --sizes path for the `size` variant of the crop at `path`.
+fn sized_path(path: &Path, size: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{}_{}px", stem, size);
+    if let Some(ext) = path.extension() {
+        name.push('.');
+        name.push_str(&ext.to_string_lossy());
     }
+    path.with_file_name(name)
+}
 
-    let face = &faces[0];
-    let bbox = face.bbox();
 
-    // Calculate Geometry
-    let crop_size = width.min(height);
+/// `(width, height)` scaled so the longer side is `size` pixels, rounded
+/// down to even sides for --even-dimensions.
+fn sized_dimensions((width, height): (u32, u32), size: u32, even: bool) -> (u32, u32) {
+    let scale = size as f64 / width.max(height) as f64;
+    let scaled = |side: u32| {
+        let side = ((side as f64 * scale).round() as u32).max(1);
+        if even {
+            geometry::even_down(side)
+        } else {
+            side
+        }
+    };
+    (scaled(width), scaled(height))
+}
+
+/// Save `cropped` scaled so its longer side is `size` pixels, for --sizes.
+fn save_sized(
+    cropped: &DynamicImage,
+    output_path: &Path,
+    size: u32,
+    args: &Args,
+    ctx: &RunContext,
+) -> Result<()> {
+    let (width, height) = sized_dimensions(cropped.dimensions(), size, args.even_dimensions);
+    let resized = cropped.resize_exact(width, height, FilterType::Lanczos3);
+    let path = sized_path(output_path, size);
+    profile::time(Phase::Save, || match collect(&resized, &path, args, ctx) {
+        Some(result) => result,
+        None => save_with_retry(&resized, &path, args, ctx).context("Failed to save sized crop"),
+    })
+}
 
-    let face_center_x = bbox.x() as u32 + (bbox.width() as u32 / 2);
-    let face_center_y = bbox.y() as u32 + (bbox.height() as u32 / 2);
 
-    let mut origin_x = face_center_x.saturating_sub(crop_size / 2);
-    let mut origin_y = face_center_y.saturating_sub(crop_size / 2);
+/// Add `img`, encoded for `path`, to --output-zip or --output-tar or upload
+/// it to S3 under its path relative to the input; `None` if it is to be
+/// saved as a file.
+fn collect(img: &DynamicImage, path: &Path, args: &Args, ctx: &RunContext) -> Option<Result<()>> {
+    let name = archive::entry_name(input_root(args), path);
+    let encoded = || output::encode_image(img, path, &ctx.save);
+    match (&ctx.zip, &ctx.tar, &ctx.s3) {
+        (Some(zip), _, _) => Some(encoded().and_then(|data| zip.add(&name, &data, false))),
+        (None, Some(tar), _) => Some(encoded().and_then(|data| tar.add(&name, &data))),
+        (None, None, Some(s3)) => Some(encoded().and_then(|data| s3.put(&name, &data))),
+        (None, None, None) => None,
+    }
+}
+
 
-    if origin_x + crop_size > width {
-        origin_x = width - crop_size;
+/// Write `cropped` to `output_path` (or into --output-zip or S3), then
+/// apply --keep-original and --term-preview.
+fn save_crop(
+    cropped: &DynamicImage,
+    input_path: &Path,
+    output_path: &Path,
+    args: &Args,
+    ctx: &RunContext,
+) -> Result<()> {
+    if ctx.to_stdout {
+        let data = output::encode_image(cropped, output_path, &ctx.save)?;
+        return std::io::stdout()
+            .write_all(&data)
+            .context("Failed to write crop to stdout");
     }
-    if origin_y + crop_size > height {
-        origin_y = height - crop_size;
+
+    profile::time(Phase::Save, || {
+        match collect(cropped, output_path, args, ctx) {
+            Some(result) => result,
+            None => {
+                save_with_retry(cropped, output_path, args, ctx).context("Failed to save output")
+            }
+        }
+    })?;
+
+    if args.keep_original {
+        keep_original(input_path, output_path)?;
     }
 
-    // Crop and Save
-    let cropped_img = img.crop(origin_x, origin_y, crop_size, crop_size);
-    cropped_img
-        .save(&output_path)
-        .context("Failed to save output")?;
+    if let Some(preview) = &ctx.preview {
+        preview.show(cropped, output_path);
+    }
 
     Ok(())
 }
+
+
+fn is_no_faces(e: &anyhow::Error) -> bool {
+    matches!(e.downcast_ref(), Some(ValidationError::NoFaces))
+}
+
+
+/// The --write-empty-on-no-face stand-in for a crop: the largest centered
+/// square of `img`.
+fn write_placeholder(
+    img: &DynamicImage,
+    input_path: &Path,
+    output_path: &Path,
+    args: &Args,
+    ctx: &RunContext,
+) -> Result<ImageOutcome> {
+    let (width, height) = img.dimensions();
+    let side = width.min(height);
+    let square = img.crop_imm((width - side) / 2, (height - side) / 2, side, side);
+    let output_path = numbered(output_path.to_path_buf(), ctx);
+    save_crop(&square, input_path, &output_path, args, ctx)?;
+
+    Ok(ImageOutcome {
+        faces_detected: 0,
+        blurhash: None,
+        placeholder: true,
+        indexed_output: ctx.numbering.as_ref().map(|_| output_path),
+        warning: None,
+        score: None,
+        quality: None,
+        duplicate_crops: 0,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// See tests/fixtures/README.md.
+    const CMYK_QUADRANTS: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/cmyk_quadrants.jpg"
+    );
+
+    #[test]
+    fn cmyk_jpeg_is_detected_and_converted() {
+        let path = Path::new(CMYK_QUADRANTS);
+        assert!(is_cmyk_jpeg(path));
+
+        let img = open_image(path, false).unwrap();
+        assert_eq!(img.color(), image::ColorType::Rgb8);
+        let rgb = img.to_rgb8();
+        let quadrants = [
+            ((4, 4), [0, 255, 255]),
+            ((12, 4), [255, 0, 255]),
+            ((4, 12), [255, 255, 0]),
+            ((12, 12), [255, 255, 255]),
+        ];
+        for ((x, y), expected) in quadrants {
+            assert_eq!(rgb.get_pixel(x, y).0, expected, "pixel ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn other_images_are_not_cmyk() {
+        let dir = tempfile::tempdir().unwrap();
+        let gray = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/assets/selftest_face.jpg"
+        ));
+        assert!(!is_cmyk_jpeg(gray));
+
+        let rgb = dir.path().join("rgb.jpg");
+        image::RgbImage::new(16, 16).save(&rgb).unwrap();
+        assert!(!is_cmyk_jpeg(&rgb));
+        let png = dir.path().join("image.png");
+        fs::write(&png, b"\x89PNG\r\n\x1a\n").unwrap();
+        assert!(!is_cmyk_jpeg(&png));
+        assert!(!is_cmyk_jpeg(&dir.path().join("missing.jpg")));
+    }
+
+    #[test]
+    fn truncated_headers_are_not_misreported() {
+        let data = fs::read(CMYK_QUADRANTS).unwrap();
+        let frame = data.windows(2).position(|w| w == [0xFF, 0xC0]).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cut.jpg");
+
+        // The component count is the ninth byte after the frame marker.
+        for len in 0..data.len() {
+            fs::write(&path, &data[..len]).unwrap();
+            assert_eq!(is_cmyk_jpeg(&path), len > frame + 9, "cut at {} bytes", len);
+        }
+
+        // Segment lengths running past the end of the file, or too short to
+        // cover their own length field, before an otherwise valid frame.
+        for length in [[0xFF, 0xFF], [0x00, 0x00], [0x00, 0x01]] {
+            let mut bogus = vec![0xFF, 0xD8, 0xFF, 0xE0];
+            bogus.extend(length);
+            bogus.extend(&data[frame..]);
+            fs::write(&path, &bogus).unwrap();
+            assert!(!is_cmyk_jpeg(&path), "length {:?}", length);
+        }
+    }
+
+    #[test]
+    fn sized_variants_follow_even_dimensions() {
+        assert_eq!(sized_dimensions((210, 210), 101, false), (101, 101));
+        assert_eq!(sized_dimensions((210, 210), 101, true), (100, 100));
+        assert_eq!(sized_dimensions((300, 200), 151, false), (151, 101));
+        assert_eq!(sized_dimensions((300, 200), 151, true), (150, 100));
+        // A side that scales to 1px cannot be made even.
+        assert_eq!(sized_dimensions((400, 2), 200, true), (200, 1));
+    }
+}
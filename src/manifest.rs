@@ -0,0 +1,185 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::stats::Status;
+
+/// Column names, in order, for the delimited formats.
+const COLUMNS: [&str; 8] = [
+    "input",
+    "output",
+    "status",
+    "faces",
+    "duration_ms",
+    "detail",
+    "blurhash",
+    "quality",
+];
+
+/// Closes the JSON array after the last row; overwritten by the next one.
+const CLOSING: &[u8] = b"]\n";
+
+/// File format of `--manifest`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ManifestFormat {
+    /// Comma-separated, with RFC 4180 quoting
+    Csv,
+    /// Tab-separated, with tabs, line breaks and backslashes escaped as
+    /// `\t`, `\n`, `\r` and `\\`
+    Tsv,
+    /// A JSON array with one object per image
+    Json,
+}
+
+/// One row of the manifest: what happened to a single input image.
+#[derive(Debug)]
+pub struct ManifestRecord {
+    pub input: PathBuf,
+    pub output: Option<PathBuf>,
+    pub status: Status,
+    pub faces: usize,
+    pub duration: Duration,
+    pub detail: String,
+    pub blurhash: Option<String>,
+    /// Quality score of the (first) cropped face.
+    pub quality: Option<f64>,
+}
+
+/// One manifest row as a JSON object. Missing values are `null`.
+#[derive(Serialize)]
+struct JsonRow<'a> {
+    input: &'a Path,
+    output: Option<&'a Path>,
+    status: &'a str,
+    faces: usize,
+    duration_ms: u128,
+    detail: &'a str,
+    blurhash: Option<&'a str>,
+    quality: Option<f64>,
+}
+
+/// Writer for per-image records, in any `ManifestFormat`.
+///
+/// Each row is flushed as it is written, so an interrupted run still leaves a
+/// usable manifest for everything processed so far. For JSON the array is
+/// closed again after every row, as `--index-json` does.
+pub struct ManifestWriter {
+    out: BufWriter<File>,
+    format: ManifestFormat,
+    rows: usize,
+}
+
+impl ManifestWriter {
+    pub fn create(path: &Path, format: ManifestFormat) -> Result<Self> {
+        let file = File::create(path).context("Failed to create manifest file")?;
+        let mut writer = ManifestWriter {
+            out: BufWriter::new(file),
+            format,
+            rows: 0,
+        };
+        match format {
+            ManifestFormat::Json => {
+                writer
+                    .out
+                    .write_all(b"[\n")
+                    .and_then(|()| writer.out.write_all(CLOSING))
+                    .and_then(|()| writer.out.flush())
+                    .context("Failed to write manifest file")?;
+            }
+            _ => writer.write_row(&COLUMNS)?,
+        }
+        Ok(writer)
+    }
+
+    pub fn write(&mut self, record: &ManifestRecord) -> Result<()> {
+        if self.format == ManifestFormat::Json {
+            return self.write_object(record);
+        }
+        let input = record.input.display().to_string();
+        let output = record
+            .output
+            .as_ref()
+            .map(|p| p.display().to_string())
+            .unwrap_or_default();
+        let faces = record.faces.to_string();
+        let duration_ms = record.duration.as_millis().to_string();
+        let quality = record
+            .quality
+            .map(|q| format!("{:.3}", q))
+            .unwrap_or_default();
+
+        self.write_row(&[
+            &input,
+            &output,
+            record.status.label(),
+            &faces,
+            &duration_ms,
+            &record.detail,
+            record.blurhash.as_deref().unwrap_or_default(),
+            &quality,
+        ])
+    }
+
+    fn write_row(&mut self, fields: &[&str]) -> Result<()> {
+        let (escape, separator): (fn(&str) -> String, &str) = match self.format {
+            ManifestFormat::Tsv => (tsv_escape, "\t"),
+            _ => (csv_escape, ","),
+        };
+        let line = fields
+            .iter()
+            .map(|f| escape(f))
+            .collect::<Vec<_>>()
+            .join(separator);
+        writeln!(self.out, "{}", line).context("Failed to write manifest row")?;
+        self.out.flush().context("Failed to write manifest row")?;
+        Ok(())
+    }
+
+    fn write_object(&mut self, record: &ManifestRecord) -> Result<()> {
+        let row = JsonRow {
+            input: &record.input,
+            output: record.output.as_deref(),
+            status: record.status.label(),
+            faces: record.faces,
+            duration_ms: record.duration.as_millis(),
+            detail: &record.detail,
+            blurhash: record.blurhash.as_deref(),
+            // Rounded like the delimited formats.
+            quality: record.quality.map(|q| (q * 1000.0).round() / 1000.0),
+        };
+        let value = serde_json::to_string(&row).context("Failed to encode manifest row")?;
+
+        // Write over the line break before the closing bracket, which the
+        // row ends with again.
+        let separator = if self.rows == 0 { "\n" } else { ",\n" };
+        let mut record = format!("{}  {}\n", separator, value).into_bytes();
+        record.extend(CLOSING);
+        self.out
+            .seek(SeekFrom::End(-(CLOSING.len() as i64 + 1)))
+            .and_then(|_| self.out.write_all(&record))
+            .and_then(|()| self.out.flush())
+            .context("Failed to write manifest row")?;
+        self.rows += 1;
+        Ok(())
+    }
+}
+
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn tsv_escape(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
@@ -0,0 +1,121 @@
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use crate::stats::RunStats;
+
+/// Render run counters in the Prometheus text exposition format.
+///
+/// Every value describes a single run, so they are all exposed as gauges; this
+/// keeps the output valid for the node_exporter textfile collector, which would
+/// otherwise see a "counter" reset on every scheduled run. None of them carry
+/// the `_total` suffix, which OpenMetrics reserves for counters, and the
+/// output ends with the `# EOF` marker OpenMetrics requires (a plain comment
+/// to Prometheus text format parsers).
+pub fn render(stats: &RunStats) -> String {
+    let gauges: [(&str, &str, String); 16] = [
+        (
+            "face_cropper_images_processed",
+            "Image files examined during the last run.",
+            stats.images_processed.to_string(),
+        ),
+        (
+            "face_cropper_faces_detected",
+            "Faces found by the detector across all images in the last run.",
+            stats.faces_detected.to_string(),
+        ),
+        (
+            "face_cropper_no_face_count",
+            "Images skipped because no face was detected.",
+            stats.no_face.to_string(),
+        ),
+        (
+            "face_cropper_multi_face_count",
+            "Images skipped because more than one face was detected.",
+            stats.multi_face.to_string(),
+        ),
+        (
+            "face_cropper_too_few_faces_count",
+            "Images skipped because fewer faces than --min-faces were detected.",
+            stats.too_few_faces.to_string(),
+        ),
+        (
+            "face_cropper_filtered_count",
+            "Images skipped by a quality filter such as --min-sharpness.",
+            stats.filtered.to_string(),
+        ),
+        (
+            "face_cropper_duplicate_count",
+            "Images skipped by --dedupe as duplicates of an earlier input.",
+            stats.duplicates.to_string(),
+        ),
+        (
+            "face_cropper_duplicate_crop_count",
+            "--largest-n crops skipped by --reject-duplicate-output.",
+            stats.duplicate_crops.to_string(),
+        ),
+        (
+            "face_cropper_aspect_count",
+            "Images skipped by --min-aspect / --max-aspect.",
+            stats.wrong_aspect.to_string(),
+        ),
+        (
+            "face_cropper_edge_face_count",
+            "Images skipped by --reject-edge-faces because the face reaches the image edge.",
+            stats.edge_faces.to_string(),
+        ),
+        (
+            "face_cropper_low_detail_count",
+            "Images skipped by --reject-low-detail because the crop is too small.",
+            stats.low_detail.to_string(),
+        ),
+        (
+            "face_cropper_score_count",
+            "Images skipped because the face score is outside --score-min / --score-max.",
+            stats.out_of_band.to_string(),
+        ),
+        (
+            "face_cropper_placeholder_count",
+            "Faceless images written as placeholders by --write-empty-on-no-face.",
+            stats.placeholders.to_string(),
+        ),
+        (
+            "face_cropper_errors",
+            "Images that failed for reasons other than face validation or timeouts.",
+            stats.errors.to_string(),
+        ),
+        (
+            "face_cropper_timeouts",
+            "Images abandoned because detection exceeded --timeout.",
+            stats.timeouts.to_string(),
+        ),
+        (
+            "face_cropper_duration_seconds",
+            "Wall-clock duration of the last run.",
+            format!("{:.6}", stats.duration.as_secs_f64()),
+        ),
+    ];
+
+    let mut out = String::new();
+    for (name, help, value) in gauges {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} gauge", name).unwrap();
+        writeln!(out, "{} {}", name, value).unwrap();
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+/// Write the metrics file via a temporary sibling and a rename, so a scraper
+/// never reads a half-written file.
+pub fn write_metrics_file(path: &Path, stats: &RunStats) -> Result<()> {
+    let mut tmp_name = path.as_os_str().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = Path::new(&tmp_name);
+
+    fs::write(tmp_path, render(stats)).context("Failed to write metrics file")?;
+    fs::rename(tmp_path, path).context("Failed to move metrics file into place")?;
+
+    Ok(())
+}
@@ -0,0 +1,113 @@
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the frontal model compiled into the binary. Always available
+/// and used when `--model-name` is not given.
+pub const EMBEDDED: &str = "embedded";
+
+/// Named detector models read from `--model-set`.
+///
+/// ```toml
+/// [models]
+/// frontal_v1 = "seeta_fd_frontal_v1.0.bin"
+/// experimental = "/opt/models/seeta_fd_frontal_v2.bin"
+/// ```
+///
+/// Relative paths are resolved against the directory of the set file.
+#[derive(Debug, Default)]
+pub struct ModelSet {
+    models: BTreeMap<String, PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModelSetFile {
+    models: BTreeMap<String, PathBuf>,
+}
+
+impl ModelSet {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read model set {:?}", path))?;
+        let file: ModelSetFile = toml::from_str(&text)
+            .with_context(|| format!("Failed to parse model set {:?}", path))?;
+
+        if file.models.contains_key(EMBEDDED) {
+            bail!(
+                "Model set: `{}` is reserved for the built-in model",
+                EMBEDDED
+            );
+        }
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        let models = file
+            .models
+            .into_iter()
+            .map(|(name, model)| (name, base.join(model)))
+            .collect();
+
+        Ok(ModelSet { models })
+    }
+
+    /// The model file registered as `name`, or `None` for the embedded model.
+    pub fn resolve(&self, name: &str) -> Result<Option<&Path>> {
+        if name == EMBEDDED {
+            return Ok(None);
+        }
+
+        match self.models.get(name) {
+            Some(path) => Ok(Some(path)),
+            None => {
+                let known: Vec<&str> = std::iter::once(EMBEDDED)
+                    .chain(self.models.keys().map(String::as_str))
+                    .collect();
+                bail!("Unknown model `{}` (available: {})", name, known.join(", "))
+            }
+        }
+    }
+}
+
+/// What `model-info` reports about a model.
+#[derive(Serialize)]
+struct ModelInfo<'a> {
+    name: &'a str,
+    /// `None` for the embedded model.
+    path: Option<&'a Path>,
+    bytes: usize,
+    sha256: String,
+    /// Structure read from the model itself; the SeetaFace format carries
+    /// no version number.
+    classifiers: usize,
+    hierarchies: usize,
+    stages: i64,
+}
+
+/// One line of JSON describing the model `name`, read from `path` or, for
+/// the embedded model, taken from `embedded`.
+pub fn info(name: &str, path: Option<&Path>, embedded: &[u8]) -> Result<String> {
+    let bytes = match path {
+        Some(path) => fs::read(path).with_context(|| format!("Failed to read model {:?}", path))?,
+        None => embedded.to_vec(),
+    };
+    let model = rustface::read_model(bytes.as_slice()).context("Failed to parse model")?;
+
+    let info = ModelInfo {
+        name,
+        path,
+        bytes: bytes.len(),
+        sha256: Sha256::digest(&bytes)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect(),
+        classifiers: model.get_classifiers().len(),
+        hierarchies: model.get_hierarchy_count(),
+        stages: (0..model.get_hierarchy_count())
+            .map(|i| model.get_num_stage(i) as i64)
+            .sum(),
+    };
+    Ok(serde_json::to_string(&info)?)
+}
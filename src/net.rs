@@ -0,0 +1,93 @@
+//! HTTP(S) URLs as `--input`, behind the `net` feature.
+//!
+//! The image is downloaded into memory, decoded and then processed like a
+//! local file; nothing is written to disk but the crop.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long a download may take when `--timeout` is not given.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub fn is_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|s| s.starts_with("http://") || s.starts_with("https://"))
+}
+
+/// File name for a download from `url` in `format`: the last segment of the
+/// URL path (`download` if there is none) with the format's extension.
+pub fn file_name(url: &str, format: image::ImageFormat) -> PathBuf {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let segment = path
+        .split_once("://")
+        .and_then(|(_, rest)| rest.split_once('/'))
+        .and_then(|(_, path)| path.rsplit('/').next())
+        .unwrap_or_default();
+    let stem = Path::new(segment)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .filter(|stem| !stem.is_empty())
+        .unwrap_or_else(|| "download".to_string());
+
+    PathBuf::from(stem).with_extension(format.extensions_str()[0])
+}
+
+#[cfg(feature = "net")]
+pub use http::fetch;
+
+#[cfg(not(feature = "net"))]
+pub fn fetch(
+    _url: &str,
+    _timeout: Duration,
+) -> anyhow::Result<(image::DynamicImage, image::ImageFormat)> {
+    anyhow::bail!("URL input not compiled in (rebuild with `--features net`)")
+}
+
+#[cfg(feature = "net")]
+mod http {
+    use anyhow::{Context, Result, anyhow};
+    use image::{DynamicImage, ImageFormat};
+    use std::time::Duration;
+
+    /// Largest download accepted, to keep a wrong URL from filling memory.
+    const MAX_BYTES: u64 = 200 * 1024 * 1024;
+
+    /// Download `url` within `timeout` and decode it, returning the image
+    /// and the format it was stored in.
+    pub fn fetch(url: &str, timeout: Duration) -> Result<(DynamicImage, ImageFormat)> {
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(timeout))
+            .build()
+            .into();
+        let mut response = agent
+            .get(url)
+            .call()
+            .with_context(|| format!("Failed to download {}", url))?;
+
+        let content_type = response
+            .headers()
+            .get("content-type")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string();
+        let bytes = response
+            .body_mut()
+            .with_config()
+            .limit(MAX_BYTES)
+            .read_to_vec()
+            .with_context(|| format!("Failed to download {}", url))?;
+
+        let not_an_image = || {
+            anyhow!(
+                "{} is not a supported image (content type {}, {} bytes)",
+                url,
+                content_type,
+                bytes.len()
+            )
+        };
+        let format = image::guess_format(&bytes).map_err(|_| not_an_image())?;
+        let img = image::load_from_memory_with_format(&bytes, format)
+            .with_context(|| format!("Failed to decode the image downloaded from {}", url))?;
+        Ok((img, format))
+    }
+}
@@ -0,0 +1,439 @@
+use anyhow::{Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use clap::ValueEnum;
+use image::codecs::jpeg::{JpegEncoder, PixelDensity};
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{DynamicImage, GenericImageView, ImageEncoder, ImageFormat};
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+
+/// Inches per meter, to convert --dpi to PNG's pixels per meter.
+const INCHES_PER_METER: f64 = 1.0 / 0.0254;
+
+/// Printed by the first crop whose format cannot carry --dpi.
+static NO_DPI_WARNING: Once = Once::new();
+
+/// Printed by the first crop --max-filesize cannot shrink.
+static NO_SIZE_CAP_WARNING: Once = Once::new();
+
+/// Quality of JPEG crops, and the highest --max-filesize tries.
+const JPEG_QUALITY: u8 = 75;
+
+/// How crops are written to disk.
+#[derive(Debug, Default)]
+pub struct SaveOptions {
+    /// Raw TIFF-structured EXIF block to embed, where the format allows it.
+    pub exif: Option<Vec<u8>>,
+    /// Color type to convert crops to before saving; `None` keeps the source's.
+    pub color: Option<ColorMode>,
+    /// Bits per channel to save with; `None` keeps the source's.
+    pub bit_depth: Option<BitDepth>,
+    /// Width in pixels of the transparent falloff at the crop border.
+    pub feather: Option<u32>,
+    /// Crops get a transparent background from --chroma-key.
+    pub keyed_transparent: bool,
+    /// Print resolution to tag crops with, in dots per inch.
+    pub dpi: Option<u16>,
+    /// Size JPEG crops must fit in, reached by lowering their quality.
+    pub max_bytes: Option<u64>,
+    /// Print the quality --max-filesize settles on.
+    pub verbose: bool,
+}
+
+/// Output color type for `--color`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ColorMode {
+    /// 8-bit single-channel luma
+    Grayscale,
+    /// 8-bit RGB
+    Rgb,
+    /// 8-bit RGB with an alpha channel
+    Rgba,
+}
+
+impl ColorMode {
+    /// Whether files of `format` can hold this color type.
+    fn supported_by(self, format: ImageFormat) -> bool {
+        !(self == ColorMode::Rgba && format == ImageFormat::Jpeg)
+    }
+}
+
+/// Whether files of `format` can hold a partly transparent image.
+fn stores_alpha(format: ImageFormat) -> bool {
+    matches!(
+        format,
+        ImageFormat::Png | ImageFormat::WebP | ImageFormat::Tiff
+    )
+}
+
+/// Format partly transparent crops are switched to (`--mask-format`) when
+/// the one their name picks would drop the alpha channel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum MaskFormat {
+    #[default]
+    Png,
+    Webp,
+    Tiff,
+}
+
+impl MaskFormat {
+    fn image_format(self) -> ImageFormat {
+        match self {
+            MaskFormat::Png => ImageFormat::Png,
+            MaskFormat::Webp => ImageFormat::WebP,
+            MaskFormat::Tiff => ImageFormat::Tiff,
+        }
+    }
+}
+
+/// `path` with the extension of `format` if `opts` makes crops partly
+/// transparent and the format `path` names cannot store that, together with
+/// the format replaced and the option responsible. `None` if the crop can
+/// be saved as named.
+pub fn mask_path(
+    opts: &SaveOptions,
+    path: &Path,
+    format: MaskFormat,
+) -> Option<(PathBuf, ImageFormat, &'static str)> {
+    let option = alpha_option(opts)?;
+    let named = ImageFormat::from_path(path).ok()?;
+    if stores_alpha(named) {
+        return None;
+    }
+    let extension = format.image_format().extensions_str()[0];
+    Some((path.with_extension(extension), named, option))
+}
+
+/// Image format embedded by `--data-uri`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum DataUriFormat {
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl DataUriFormat {
+    fn image_format(self) -> ImageFormat {
+        match self {
+            DataUriFormat::Png => ImageFormat::Png,
+            DataUriFormat::Jpeg => ImageFormat::Jpeg,
+            DataUriFormat::Webp => ImageFormat::WebP,
+        }
+    }
+}
+
+/// Output bits per channel for `--output-bit-depth`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+}
+
+impl BitDepth {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "8" => Ok(BitDepth::Eight),
+            "16" => Ok(BitDepth::Sixteen),
+            _ => Err(format!("`{}` is not 8 or 16", s)),
+        }
+    }
+
+    fn supported_by(self, format: ImageFormat) -> bool {
+        self == BitDepth::Eight
+            || matches!(
+                format,
+                ImageFormat::Png | ImageFormat::Tiff | ImageFormat::Pnm
+            )
+    }
+}
+
+/// Fail if the format picked by `path` cannot store the `--color` type,
+/// `--output-bit-depth` or transparency that `opts` asks for.
+pub fn check_format(opts: &SaveOptions, path: &Path) -> Result<()> {
+    if opts.color.is_none() && opts.bit_depth.is_none() && alpha_option(opts).is_none() {
+        return Ok(());
+    }
+    check_format_for(opts, ImageFormat::from_path(path)?)
+}
+
+fn check_format_for(opts: &SaveOptions, format: ImageFormat) -> Result<()> {
+    if let Some(mode) = opts.color.filter(|mode| !mode.supported_by(format)) {
+        bail!("{:?} output cannot store --color {:?}", format, mode);
+    }
+    if opts.bit_depth == Some(BitDepth::Sixteen) && !BitDepth::Sixteen.supported_by(format) {
+        bail!("{:?} output cannot store 16 bits per channel", format);
+    }
+    if let Some(option) = alpha_option(opts) {
+        if let Some(mode @ (ColorMode::Grayscale | ColorMode::Rgb)) = opts.color {
+            bail!(
+                "{} needs an alpha channel, which --color {:?} lacks",
+                option,
+                mode
+            );
+        }
+        if !stores_alpha(format) {
+            bail!(
+                "{:?} output cannot store the transparency of {} (use PNG, WebP or TIFF)",
+                format,
+                option
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The option in `opts` that makes crops partly transparent, if any.
+fn alpha_option(opts: &SaveOptions) -> Option<&'static str> {
+    if opts.feather.is_some() {
+        Some("--feather")
+    } else if opts.keyed_transparent {
+        Some("--chroma-key")
+    } else {
+        None
+    }
+}
+
+/// `img` converted to the color type and depth requested in `opts`, with
+/// whatever `opts` leaves unset taken from `img`. `None` if nothing is
+/// requested.
+fn convert(img: &DynamicImage, opts: &SaveOptions) -> Option<DynamicImage> {
+    if opts.color.is_none() && opts.bit_depth.is_none() {
+        return None;
+    }
+
+    let source = img.color();
+    let gray_alpha = match opts.color {
+        Some(ColorMode::Grayscale) => (true, false),
+        Some(ColorMode::Rgb) => (false, false),
+        Some(ColorMode::Rgba) => (false, true),
+        None => (source.channel_count() <= 2, source.has_alpha()),
+    };
+    let sixteen = match opts.bit_depth {
+        Some(depth) => depth == BitDepth::Sixteen,
+        None => source.bytes_per_pixel() > source.channel_count(),
+    };
+
+    Some(match (gray_alpha, sixteen) {
+        ((true, false), false) => DynamicImage::ImageLuma8(img.to_luma8()),
+        ((true, true), false) => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+        ((false, false), false) => DynamicImage::ImageRgb8(img.to_rgb8()),
+        ((false, true), false) => DynamicImage::ImageRgba8(img.to_rgba8()),
+        ((true, false), true) => DynamicImage::ImageLuma16(img.to_luma16()),
+        ((true, true), true) => DynamicImage::ImageLumaA16(img.to_luma_alpha16()),
+        ((false, false), true) => DynamicImage::ImageRgb16(img.to_rgb16()),
+        ((false, true), true) => DynamicImage::ImageRgba16(img.to_rgba16()),
+    })
+}
+
+/// Save `img` to `path`, picking the format from the extension like
+/// `DynamicImage::save` does.
+pub fn save_image(img: &DynamicImage, path: &Path, opts: &SaveOptions) -> Result<()> {
+    let format = ImageFormat::from_path(path)?;
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_image(img, format, opts, &mut writer, Some(path))?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Encode `img` in memory as `save_image` would write it to `path`.
+pub fn encode_image(img: &DynamicImage, path: &Path, opts: &SaveOptions) -> Result<Vec<u8>> {
+    let format = ImageFormat::from_path(path)?;
+    let mut buffer = Cursor::new(Vec::new());
+    write_image(img, format, opts, &mut buffer, Some(path))?;
+    Ok(buffer.into_inner())
+}
+
+/// `img` encoded as a `data:image/...;base64,...` URI.
+pub fn data_uri(img: &DynamicImage, format: DataUriFormat, opts: &SaveOptions) -> Result<String> {
+    let format = format.image_format();
+    let mut buffer = Cursor::new(Vec::new());
+    write_image(img, format, opts, &mut buffer, None)?;
+    Ok(format!(
+        "data:{};base64,{}",
+        format.to_mime_type(),
+        BASE64.encode(buffer.into_inner())
+    ))
+}
+
+/// Encode `img` as `format` into `writer`. `path` names the crop in
+/// warnings, where there is one.
+fn write_image(
+    img: &DynamicImage,
+    format: ImageFormat,
+    opts: &SaveOptions,
+    writer: &mut (impl Write + Seek),
+    path: Option<&Path>,
+) -> Result<()> {
+    check_format_for(opts, format)?;
+    let converted = convert(img, opts);
+    let img = converted.as_ref().unwrap_or(img);
+    let feathered = opts.feather.map(|width| feather(img, width));
+    let img = feathered.as_ref().unwrap_or(img);
+
+    let exif = opts.exif.as_deref();
+    if opts.dpi.is_some() && !matches!(format, ImageFormat::Jpeg | ImageFormat::Png) {
+        NO_DPI_WARNING.call_once(|| {
+            eprintln!(
+                "Warning: {:?} output cannot carry a --dpi tag; writing without it.",
+                format
+            )
+        });
+    }
+    if opts.max_bytes.is_some() && format != ImageFormat::Jpeg {
+        NO_SIZE_CAP_WARNING.call_once(|| {
+            eprintln!(
+                "Warning: {:?} output is lossless; --max-filesize only shrinks JPEG crops.",
+                format
+            )
+        });
+    }
+    match (format, opts.dpi) {
+        (ImageFormat::Jpeg, _) if opts.max_bytes.is_some() => {
+            writer.write_all(&fit_jpeg(img, opts, path)?)?
+        }
+        (ImageFormat::Jpeg, dpi) => {
+            write_with_metadata(img, jpeg_encoder(writer, JPEG_QUALITY, dpi), exif)?
+        }
+        (ImageFormat::Png, Some(dpi)) => {
+            // The PNG encoder has no setter for the density chunk.
+            let mut encoded = Vec::new();
+            write_with_metadata(img, PngEncoder::new(&mut encoded), exif)?;
+            writer.write_all(&with_png_density(&encoded, dpi))?;
+        }
+        (ImageFormat::Png, None) => write_with_metadata(img, PngEncoder::new(writer), exif)?,
+        (ImageFormat::WebP, _) => {
+            write_with_metadata(img, WebPEncoder::new_lossless(writer), exif)?
+        }
+        _ if exif.is_some() => {
+            eprintln!(
+                "Warning: {:?} output cannot carry EXIF metadata; writing without it.",
+                format
+            );
+            img.write_to(writer, format)?;
+        }
+        _ => img.write_to(writer, format)?,
+    }
+
+    Ok(())
+}
+
+fn jpeg_encoder<W: Write>(writer: W, quality: u8, dpi: Option<u16>) -> JpegEncoder<W> {
+    let mut encoder = JpegEncoder::new_with_quality(writer, quality);
+    if let Some(dpi) = dpi {
+        encoder.set_pixel_density(PixelDensity::dpi(dpi));
+    }
+    encoder
+}
+
+/// `img` as a JPEG of at most `opts.max_bytes`, at the highest quality up
+/// to the usual one that fits, found by bisection. If even quality 1 is
+/// too large, that is used anyway, with a warning.
+fn fit_jpeg(img: &DynamicImage, opts: &SaveOptions, path: Option<&Path>) -> Result<Vec<u8>> {
+    let limit = opts.max_bytes.unwrap_or(u64::MAX);
+    let encode = |quality: u8| -> Result<Vec<u8>> {
+        let mut encoded = Vec::new();
+        let encoder = jpeg_encoder(&mut encoded, quality, opts.dpi);
+        write_with_metadata(img, encoder, opts.exif.as_deref())?;
+        Ok(encoded)
+    };
+
+    let mut best = (JPEG_QUALITY, encode(JPEG_QUALITY)?);
+    if best.1.len() as u64 > limit {
+        // Sizes shrink with quality, so bisect for the largest that fits.
+        let (mut low, mut high) = (1, JPEG_QUALITY - 1);
+        best = (low, encode(low)?);
+        if best.1.len() as u64 > limit {
+            high = low;
+        }
+        while low < high {
+            let quality = (low + high).div_ceil(2);
+            let encoded = encode(quality)?;
+            if encoded.len() as u64 <= limit {
+                low = quality;
+                best = (quality, encoded);
+            } else {
+                high = quality - 1;
+            }
+        }
+    }
+
+    let (quality, encoded) = best;
+    let kb = |bytes: u64| bytes.div_ceil(1024);
+    if encoded.len() as u64 > limit {
+        let name = path.map(|p| format!("{:?}: ", p)).unwrap_or_default();
+        eprintln!(
+            "Warning: {}{} KB even at JPEG quality 1, over --max-filesize {} KB",
+            name,
+            kb(encoded.len() as u64),
+            kb(limit)
+        );
+    } else if opts.verbose {
+        eprintln!(
+            "  JPEG quality {} for --max-filesize ({} KB)",
+            quality,
+            kb(encoded.len() as u64)
+        );
+    }
+    Ok(encoded)
+}
+
+/// `png` with a pHYs chunk giving `dpi` in both directions inserted after
+/// its header, where decoders expect it.
+fn with_png_density(png: &[u8], dpi: u16) -> Vec<u8> {
+    // Signature, then the IHDR chunk: length, type, 13 data bytes and CRC.
+    const HEADER_END: usize = 8 + 4 + 4 + 13 + 4;
+    let per_meter = (dpi as f64 * INCHES_PER_METER).round() as u32;
+
+    let mut chunk = b"pHYs".to_vec();
+    chunk.extend(per_meter.to_be_bytes());
+    chunk.extend(per_meter.to_be_bytes());
+    // Unit: meters.
+    chunk.push(1);
+
+    let mut tagged = Vec::with_capacity(png.len() + 21);
+    tagged.extend(&png[..HEADER_END]);
+    tagged.extend(9u32.to_be_bytes());
+    tagged.extend(&chunk);
+    tagged.extend(crc32fast::hash(&chunk).to_be_bytes());
+    tagged.extend(&png[HEADER_END..]);
+    tagged
+}
+
+/// `img` with an alpha channel that rises linearly from transparent at its
+/// border to the image's own alpha `width` pixels in, keeping its gray or
+/// color type and its depth.
+fn feather(img: &DynamicImage, width: u32) -> DynamicImage {
+    let (w, h) = img.dimensions();
+    let mut faded = img.to_rgba32f();
+    for (x, y, pixel) in faded.enumerate_pixels_mut() {
+        let edge = x.min(y).min(w - 1 - x).min(h - 1 - y);
+        pixel.0[3] *= ((edge as f32 + 0.5) / width as f32).min(1.0);
+    }
+    let faded = DynamicImage::ImageRgba32F(faded);
+
+    let source = img.color();
+    let gray = source.channel_count() <= 2;
+    let sixteen = source.bytes_per_pixel() > source.channel_count();
+    match (gray, sixteen) {
+        (true, false) => DynamicImage::ImageLumaA8(faded.to_luma_alpha8()),
+        (true, true) => DynamicImage::ImageLumaA16(faded.to_luma_alpha16()),
+        (false, false) => DynamicImage::ImageRgba8(faded.to_rgba8()),
+        (false, true) => DynamicImage::ImageRgba16(faded.to_rgba16()),
+    }
+}
+
+fn write_with_metadata(
+    img: &DynamicImage,
+    mut encoder: impl ImageEncoder,
+    exif: Option<&[u8]>,
+) -> Result<()> {
+    if let Some(exif) = exif {
+        encoder.set_exif_metadata(exif.to_vec())?;
+    }
+    img.write_with_encoder(encoder)?;
+    Ok(())
+}
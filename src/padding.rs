@@ -0,0 +1,107 @@
+use clap::ValueEnum;
+use image::{DynamicImage, ImageBuffer, Pixel, Rgba, RgbaImage};
+
+/// How `--pad-mode` fills the part of a crop outside the image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PadMode {
+    /// A solid `--pad-color`
+    Color,
+    /// The nearest edge pixel, repeated outward
+    Edge,
+    /// The image mirrored at its edge
+    Mirror,
+}
+
+impl PadMode {
+    /// The source coordinate for `at` along a side of `len` pixels.
+    /// `None` for `Color`, where it lies outside.
+    fn source(self, at: i64, len: u32) -> Option<u32> {
+        let last = len as i64 - 1;
+        if (0..=last).contains(&at) {
+            return Some(at as u32);
+        }
+        match self {
+            PadMode::Color => None,
+            PadMode::Edge => Some(at.clamp(0, last) as u32),
+            // Reflect about the edge pixel, which is not repeated.
+            PadMode::Mirror if last == 0 => Some(0),
+            PadMode::Mirror => {
+                let folded = at.rem_euclid(2 * last);
+                Some(folded.min(2 * last - folded) as u32)
+            }
+        }
+    }
+}
+
+/// The `width` x `height` region of `img` at `origin`, which may reach past
+/// the image edges, with the pixels outside filled per `mode`. Keeps the
+/// gray or color type and depth of `img`.
+pub fn extend(
+    img: &DynamicImage,
+    origin: (i64, i64),
+    (width, height): (u32, u32),
+    mode: PadMode,
+    color: [u8; 3],
+) -> DynamicImage {
+    let [r, g, b] = color;
+    let fill = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([r, g, b, u8::MAX])));
+    let region = Region {
+        origin,
+        size: (width, height),
+        mode,
+    };
+    match img {
+        DynamicImage::ImageLuma8(buffer) => {
+            DynamicImage::ImageLuma8(region.cut(buffer, *fill.to_luma8().get_pixel(0, 0)))
+        }
+        DynamicImage::ImageLumaA8(buffer) => {
+            DynamicImage::ImageLumaA8(region.cut(buffer, *fill.to_luma_alpha8().get_pixel(0, 0)))
+        }
+        DynamicImage::ImageRgb8(buffer) => {
+            DynamicImage::ImageRgb8(region.cut(buffer, *fill.to_rgb8().get_pixel(0, 0)))
+        }
+        DynamicImage::ImageRgba8(buffer) => {
+            DynamicImage::ImageRgba8(region.cut(buffer, *fill.to_rgba8().get_pixel(0, 0)))
+        }
+        DynamicImage::ImageLuma16(buffer) => {
+            DynamicImage::ImageLuma16(region.cut(buffer, *fill.to_luma16().get_pixel(0, 0)))
+        }
+        DynamicImage::ImageLumaA16(buffer) => {
+            DynamicImage::ImageLumaA16(region.cut(buffer, *fill.to_luma_alpha16().get_pixel(0, 0)))
+        }
+        DynamicImage::ImageRgb16(buffer) => {
+            DynamicImage::ImageRgb16(region.cut(buffer, *fill.to_rgb16().get_pixel(0, 0)))
+        }
+        DynamicImage::ImageRgba16(buffer) => {
+            DynamicImage::ImageRgba16(region.cut(buffer, *fill.to_rgba16().get_pixel(0, 0)))
+        }
+        _ => DynamicImage::ImageRgba32F(
+            region.cut(&img.to_rgba32f(), *fill.to_rgba32f().get_pixel(0, 0)),
+        ),
+    }
+}
+
+/// The part of the image plane `extend` cuts out.
+struct Region {
+    origin: (i64, i64),
+    size: (u32, u32),
+    mode: PadMode,
+}
+
+impl Region {
+    fn cut<P: Pixel>(
+        &self,
+        src: &ImageBuffer<P, Vec<P::Subpixel>>,
+        fill: P,
+    ) -> ImageBuffer<P, Vec<P::Subpixel>> {
+        let (src_width, src_height) = src.dimensions();
+        ImageBuffer::from_fn(self.size.0, self.size.1, |x, y| {
+            let sx = self.mode.source(self.origin.0 + x as i64, src_width);
+            let sy = self.mode.source(self.origin.1 + y as i64, src_height);
+            match (sx, sy) {
+                (Some(sx), Some(sy)) => *src.get_pixel(sx, sy),
+                _ => fill,
+            }
+        })
+    }
+}
@@ -0,0 +1,34 @@
+use anyhow::{Result, anyhow};
+use image::DynamicImage;
+use image::imageops::FilterType;
+
+/// Longest side the crop is shrunk to before encoding. BlurHash only keeps a
+/// few low-frequency components, so more pixels just cost time.
+const THUMBNAIL_SIZE: u32 = 64;
+
+/// The BlurHash string of `img` with `components` (x, y) frequency
+/// components, each between 1 and 9.
+pub fn blurhash(img: &DynamicImage, components: (u32, u32)) -> Result<String> {
+    let thumb = img
+        .resize(THUMBNAIL_SIZE, THUMBNAIL_SIZE, FilterType::Triangle)
+        .to_rgba8();
+    blurhash::encode(
+        components.0,
+        components.1,
+        thumb.width(),
+        thumb.height(),
+        thumb.as_raw(),
+    )
+    .map_err(|e| anyhow!("Failed to compute blurhash: {}", e))
+}
+
+/// Parse `--blurhash-components`, e.g. `4x3`.
+pub fn parse_components(s: &str) -> Result<(u32, u32), String> {
+    let parsed = s
+        .split_once('x')
+        .and_then(|(x, y)| Some((x.parse().ok()?, y.parse().ok()?)));
+    match parsed {
+        Some((x, y)) if (1..=9).contains(&x) && (1..=9).contains(&y) => Ok((x, y)),
+        _ => Err(format!("`{}` is not XxY with X and Y from 1 to 9", s)),
+    }
+}
@@ -0,0 +1,44 @@
+use anyhow::Result;
+use image::DynamicImage;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::{open_image, video};
+
+/// An image decoded ahead of time by `for_each_decoded`, to hand to
+/// `process_image`. `None` for files it cannot decode up front (videos),
+/// which `process_image` opens itself.
+pub type Prefetched = Option<Result<DynamicImage>>;
+
+/// Call `each` with every path in `paths`, in order, and its decoded image.
+///
+/// Decoding runs on a background thread, so reading and decoding the next
+/// images overlaps with detection on the current one. At most `depth`
+/// decoded images wait in the queue, which bounds the extra memory. Stops at
+/// the first error returned by `each`. `fallback` is passed on to
+/// `open_image`.
+pub fn for_each_decoded(
+    paths: &[PathBuf],
+    depth: usize,
+    fallback: bool,
+    mut each: impl FnMut(&Path, Prefetched) -> Result<()>,
+) -> Result<()> {
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::sync_channel(depth);
+        scope.spawn(move || {
+            for path in paths {
+                let decoded = (!video::is_video(path)).then(|| open_image(path, fallback));
+                // The receiver is gone once `each` has failed.
+                if sender.send(decoded).is_err() {
+                    break;
+                }
+            }
+        });
+
+        for (path, decoded) in paths.iter().zip(receiver) {
+            each(path, decoded)?;
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,50 @@
+use image::DynamicImage;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Width of each preview in terminal columns.
+const PREVIEW_COLUMNS: u32 = 32;
+
+/// Prints saved crops to the terminal for `--term-preview`, up to a limit.
+///
+/// `viuer` uses iTerm inline images where available and otherwise colored
+/// half-block characters, so this works over plain SSH. Its Kitty probe is
+/// turned off: it waits on a terminal reply and hangs where none comes.
+/// Nothing is printed when stdout is not a terminal.
+pub struct TermPreview {
+    /// Previews still allowed; the lock also keeps parallel workers from
+    /// interleaving their output.
+    remaining: Mutex<usize>,
+}
+
+impl TermPreview {
+    pub fn new(limit: usize) -> Self {
+        TermPreview {
+            remaining: Mutex::new(limit),
+        }
+    }
+
+    pub fn show(&self, img: &DynamicImage, path: &Path) {
+        if !io::stdout().is_terminal() {
+            return;
+        }
+
+        let mut remaining = self.remaining.lock().unwrap();
+        if *remaining == 0 {
+            return;
+        }
+        *remaining -= 1;
+
+        let config = viuer::Config {
+            absolute_offset: false,
+            width: Some(PREVIEW_COLUMNS),
+            use_kitty: false,
+            ..Default::default()
+        };
+        println!("{}:", path.display());
+        if let Err(e) = viuer::print(img, &config) {
+            eprintln!("Warning: cannot preview {:?}: {}", path, e);
+        }
+    }
+}
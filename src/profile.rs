@@ -0,0 +1,161 @@
+//! `--profile`: where a run's time goes, by processing phase.
+//!
+//! Phases are timed wherever they happen (worker threads, the prefetch
+//! thread, retries) into one set of counters. Off by default, and then
+//! `time` only checks a flag before running the phase. Counting lasts as
+//! long as the `Session` returned by `enable`, so each input of a --job
+//! file gets its own breakdown, and inputs without --profile none.
+
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Root frame of the `--profile-folded` stacks.
+const ROOT_FRAME: &str = "face_cropper";
+
+#[derive(Clone, Copy, Debug)]
+pub enum Phase {
+    /// Reading and decoding source images.
+    Decode,
+    /// The grayscale copy the detector scans, with --auto-white-balance,
+    /// --auto-contrast and --detect-upscale-small.
+    Grayscale,
+    /// The detector itself, including landmark lookups.
+    Detect,
+    /// Cutting, keying and resizing the crop.
+    Crop,
+    /// Encoding and writing crops.
+    Save,
+}
+
+impl Phase {
+    const ALL: [Phase; 5] = [
+        Phase::Decode,
+        Phase::Grayscale,
+        Phase::Detect,
+        Phase::Crop,
+        Phase::Save,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Phase::Decode => "decode",
+            Phase::Grayscale => "grayscale",
+            Phase::Detect => "detect",
+            Phase::Crop => "crop",
+            Phase::Save => "save",
+        }
+    }
+}
+
+struct Totals {
+    nanos: [AtomicU64; Phase::ALL.len()],
+    calls: [AtomicU64; Phase::ALL.len()],
+}
+
+impl Totals {
+    /// Each phase's total time and number of calls, resetting both.
+    fn take(&self) -> Vec<(Phase, Duration, u64)> {
+        Phase::ALL
+            .iter()
+            .map(|&phase| {
+                let index = phase as usize;
+                let nanos = self.nanos[index].swap(0, Ordering::Relaxed);
+                let calls = self.calls[index].swap(0, Ordering::Relaxed);
+                (phase, Duration::from_nanos(nanos), calls)
+            })
+            .collect()
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static TOTALS: Totals = Totals {
+    nanos: [const { AtomicU64::new(0) }; Phase::ALL.len()],
+    calls: [const { AtomicU64::new(0) }; Phase::ALL.len()],
+};
+
+/// Counting for one run; stops and clears the totals when dropped, however
+/// the run ends.
+pub struct Session(());
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        ENABLED.store(false, Ordering::Relaxed);
+        TOTALS.take();
+    }
+}
+
+/// Start counting; until then, and after the session is dropped, `time`
+/// does nothing extra.
+pub fn enable() -> Session {
+    TOTALS.take();
+    ENABLED.store(true, Ordering::Relaxed);
+    Session(())
+}
+
+/// Run `phase` and add its duration to the run's totals.
+pub fn time<T>(phase: Phase, run: impl FnOnce() -> T) -> T {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return run();
+    }
+    let started = Instant::now();
+    let result = run();
+    let index = phase as usize;
+    TOTALS.nanos[index].fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    TOTALS.calls[index].fetch_add(1, Ordering::Relaxed);
+    result
+}
+
+/// The breakdown of the session's run as a table and as folded stacks (one
+/// `face_cropper;phase microseconds` line per phase, for flamegraph tools),
+/// ending the session.
+pub fn report(session: Session, wall: Duration) -> (String, String) {
+    let phases = TOTALS.take();
+    drop(session);
+    let timed: Duration = phases.iter().map(|(_, total, _)| *total).sum();
+
+    let mut table = format!(
+        "Profile: {:.3}s wall time, {:.3}s in timed phases (summed over all threads)\n",
+        wall.as_secs_f64(),
+        timed.as_secs_f64()
+    );
+    writeln!(
+        table,
+        "  {:<10} {:>10} {:>7} {:>7} {:>10}",
+        "phase", "total", "share", "calls", "per call"
+    )
+    .unwrap();
+    let mut folded = String::new();
+    for (phase, total, calls) in &phases {
+        let share = if timed.is_zero() {
+            0.0
+        } else {
+            total.as_secs_f64() / timed.as_secs_f64() * 100.0
+        };
+        let per_call = if *calls == 0 {
+            0.0
+        } else {
+            total.as_secs_f64() * 1000.0 / *calls as f64
+        };
+        writeln!(
+            table,
+            "  {:<10} {:>9.3}s {:>6.1}% {:>7} {:>8.2}ms",
+            phase.label(),
+            total.as_secs_f64(),
+            share,
+            calls,
+            per_call
+        )
+        .unwrap();
+        writeln!(
+            folded,
+            "{};{} {}",
+            ROOT_FRAME,
+            phase.label(),
+            total.as_micros()
+        )
+        .unwrap();
+    }
+    (table, folded)
+}
@@ -0,0 +1,77 @@
+/// Build a short, stable provenance string such as
+/// `face_cropper/0.1.0 params=3f9c1a0b7d2e4f61`.
+///
+/// The hash is FNV-1a over the `key=value` lines, so it is identical across
+/// runs, platforms and compiler versions for the same settings (unlike
+/// `std::hash::DefaultHasher`, whose output is not guaranteed).
+pub fn stamp(params: &[(&str, String)]) -> String {
+    let mut canonical = String::new();
+    for (key, value) in params {
+        canonical.push_str(key);
+        canonical.push('=');
+        canonical.push_str(value);
+        canonical.push('\n');
+    }
+
+    format!(
+        "{}/{} params={:016x}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        fnv1a_64(canonical.as_bytes())
+    )
+}
+
+fn fnv1a_64(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+/// A minimal little-endian TIFF/EXIF block holding `text` as the
+/// ImageDescription tag and the tool name as the Software tag.
+pub fn exif_description(text: &str) -> Vec<u8> {
+    const ASCII: u16 = 2;
+    const IMAGE_DESCRIPTION: u16 = 0x010E;
+    const SOFTWARE: u16 = 0x0131;
+
+    let software = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+    // Tags must be written in ascending order.
+    let entries: [(u16, &str); 2] = [(IMAGE_DESCRIPTION, text), (SOFTWARE, software)];
+
+    let ifd_offset: u32 = 8;
+    let ifd_len = 2 + entries.len() as u32 * 12 + 4;
+    let mut data_offset = ifd_offset + ifd_len;
+
+    let mut header = Vec::new();
+    header.extend_from_slice(b"II");
+    header.extend_from_slice(&42u16.to_le_bytes());
+    header.extend_from_slice(&ifd_offset.to_le_bytes());
+    header.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+
+    let mut data = Vec::new();
+    for (tag, value) in entries {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+
+        header.extend_from_slice(&tag.to_le_bytes());
+        header.extend_from_slice(&ASCII.to_le_bytes());
+        header.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+
+        if bytes.len() <= 4 {
+            bytes.resize(4, 0);
+            header.extend_from_slice(&bytes);
+        } else {
+            header.extend_from_slice(&data_offset.to_le_bytes());
+            data_offset += bytes.len() as u32;
+            data.extend_from_slice(&bytes);
+        }
+    }
+    // No next IFD.
+    header.extend_from_slice(&0u32.to_le_bytes());
+
+    header.extend_from_slice(&data);
+    header
+}
@@ -0,0 +1,157 @@
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GrayImage};
+use rustface::Rectangle;
+use serde::Serialize;
+
+use crate::geometry;
+
+/// Side the face is scaled to before `face_quality` measures its
+/// sharpness, so that faces of any size are compared at the same scale.
+const SHARPNESS_SAMPLE_SIZE: u32 = 64;
+
+/// Sharpness of that sample that rates 0.5; higher values approach 1.
+const SHARPNESS_MIDPOINT: f64 = 300.0;
+
+/// Face side, in pixels, that rates 0.5 for size; larger faces approach 1.
+const SIZE_MIDPOINT: f64 = 128.0;
+
+/// How far `bbox` reaches past the nearest edge of a `width` x `height`
+/// image, in pixels: 0 when it touches an edge, negative when it lies fully
+/// inside (the distance to the nearest edge).
+pub fn edge_overflow(bbox: &Rectangle, width: u32, height: u32) -> i64 {
+    let left = -(bbox.x() as i64);
+    let top = -(bbox.y() as i64);
+    let right = bbox.x() as i64 + bbox.width() as i64 - width as i64;
+    let bottom = bbox.y() as i64 + bbox.height() as i64 - height as i64;
+    left.max(top).max(right).max(bottom)
+}
+
+/// Clip `bbox` to a `width` x `height` image. Returns `None` if nothing of
+/// the box lies inside the image.
+pub fn clip_to_image(bbox: &Rectangle, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+    let left = bbox.x().max(0) as i64;
+    let top = bbox.y().max(0) as i64;
+    let right = (bbox.x() as i64 + bbox.width() as i64).min(width as i64);
+    let bottom = (bbox.y() as i64 + bbox.height() as i64).min(height as i64);
+
+    if right <= left || bottom <= top {
+        return None;
+    }
+
+    Some((
+        left as u32,
+        top as u32,
+        (right - left) as u32,
+        (bottom - top) as u32,
+    ))
+}
+
+/// Variance of the Laplacian over the face region of `gray`.
+///
+/// A classic focus measure: sharp edges produce a wide spread of Laplacian
+/// responses, blur flattens them towards zero. The absolute value depends on
+/// image content, so thresholds need calibrating per dataset.
+pub fn sharpness(gray: &GrayImage, bbox: &Rectangle) -> f64 {
+    let Some((x, y, w, h)) = clip_to_image(bbox, gray.width(), gray.height()) else {
+        return 0.0;
+    };
+    if w < 3 || h < 3 {
+        return 0.0;
+    }
+
+    let region = imageops::crop_imm(gray, x, y, w, h).to_image();
+
+    let mut sum = 0.0;
+    let mut sum_sq = 0.0;
+    let mut count = 0.0;
+
+    for py in 1..h - 1 {
+        for px in 1..w - 1 {
+            let at = |dx: i32, dy: i32| {
+                region.get_pixel((px as i32 + dx) as u32, (py as i32 + dy) as u32)[0] as f64
+            };
+            let lap = at(0, -1) + at(-1, 0) + at(1, 0) + at(0, 1) - 4.0 * at(0, 0);
+
+            sum += lap;
+            sum_sq += lap * lap;
+            count += 1.0;
+        }
+    }
+
+    let mean = sum / count;
+    sum_sq / count - mean * mean
+}
+
+/// Weights of the parts of `face_quality`, set with `--quality-weights`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QualityWeights {
+    pub sharpness: f64,
+    pub size: f64,
+    pub center: f64,
+}
+
+impl Default for QualityWeights {
+    fn default() -> Self {
+        QualityWeights {
+            sharpness: 1.0,
+            size: 1.0,
+            center: 1.0,
+        }
+    }
+}
+
+/// A face's composite quality rating, with the parts it is made of. Every
+/// value is between 0 and 1, higher being better.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct FaceQuality {
+    /// Weighted mean of the three parts.
+    pub score: f64,
+    /// Focus of the face, from the variance of the Laplacian.
+    pub sharpness: f64,
+    /// Size of the face box in pixels.
+    pub size: f64,
+    /// Closeness of the face to the image center.
+    pub center: f64,
+}
+
+/// The quality of the face `bbox` in `img`, for ranking crops of the same
+/// subject across photos.
+///
+/// Sharpness is measured on the face scaled to a fixed size, since the raw
+/// variance of the Laplacian grows as faces get smaller. Sharpness and size
+/// rate `v / (v + midpoint)`, so they rise quickly at first and level off
+/// instead of being capped.
+pub fn face_quality(img: &DynamicImage, bbox: &Rectangle, weights: QualityWeights) -> FaceQuality {
+    let sharpness = match clip_to_image(bbox, img.width(), img.height()) {
+        Some((x, y, w, h)) => {
+            let face = img.crop_imm(x, y, w, h).to_luma8();
+            let sample = imageops::resize(
+                &face,
+                SHARPNESS_SAMPLE_SIZE,
+                SHARPNESS_SAMPLE_SIZE,
+                FilterType::Triangle,
+            );
+            let whole = Rectangle::new(0, 0, SHARPNESS_SAMPLE_SIZE, SHARPNESS_SAMPLE_SIZE);
+            saturate(self::sharpness(&sample, &whole), SHARPNESS_MIDPOINT)
+        }
+        None => 0.0,
+    };
+    let size = saturate(bbox.width().max(bbox.height()) as f64, SIZE_MIDPOINT);
+    let center = geometry::centrality(bbox, (img.width(), img.height()));
+
+    let total = weights.sharpness + weights.size + weights.center;
+    let score =
+        (weights.sharpness * sharpness + weights.size * size + weights.center * center) / total;
+    FaceQuality {
+        score,
+        sharpness,
+        size,
+        center,
+    }
+}
+
+/// `value` mapped onto 0..1, reaching 0.5 at `midpoint`.
+fn saturate(value: f64, midpoint: f64) -> f64 {
+    let value = value.max(0.0);
+    value / (value + midpoint)
+}
@@ -0,0 +1,42 @@
+use anyhow::Result;
+use image::DynamicImage;
+use std::ffi::OsStr;
+use std::path::Path;
+
+/// Camera raw formats, opened through `imagepipe` when the `raw` feature is
+/// enabled.
+const RAW_EXTENSIONS: &[&str] = &[
+    "cr2", "nef", "nrw", "dng", "arw", "srf", "sr2", "orf", "rw2", "raf", "pef", "srw", "3fr",
+    "erf", "kdc", "dcr", "mrw", "mef", "mos",
+];
+
+/// Extension given to crops of raw inputs, which cannot be written back as
+/// raw.
+pub const OUTPUT_EXTENSION: &str = "jpg";
+
+pub fn is_raw(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .is_some_and(|ext| RAW_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Develop a raw file into an 8-bit sRGB image at full resolution.
+#[cfg(feature = "raw")]
+pub fn open(path: &Path) -> Result<DynamicImage> {
+    use anyhow::{Context, anyhow};
+
+    let developed = imagepipe::simple_decode_8bit(path, 0, 0)
+        .map_err(|e| anyhow!("Failed to decode raw file: {}", e))?;
+    image::RgbImage::from_raw(
+        developed.width as u32,
+        developed.height as u32,
+        developed.data,
+    )
+    .map(DynamicImage::ImageRgb8)
+    .context("Raw decoder returned a malformed image")
+}
+
+#[cfg(not(feature = "raw"))]
+pub fn open(_path: &Path) -> Result<DynamicImage> {
+    anyhow::bail!("Raw support not compiled in (rebuild with `--features raw`)")
+}
@@ -0,0 +1,158 @@
+use anyhow::{Context, Result};
+use image::DynamicImage;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::output::{self, DataUriFormat, SaveOptions};
+use crate::stats::Status;
+
+/// One image in the `--html-report` gallery.
+pub struct ReportEntry {
+    pub input: PathBuf,
+    pub status: Status,
+    /// Score of the cropped face, where one was cropped.
+    pub score: Option<f64>,
+    /// Warning or failure reason.
+    pub detail: String,
+    /// Thumbnail as a data URI: of the crop, or of the source for failures.
+    pub thumbnail: Option<String>,
+}
+
+/// `img` shrunk to fit `size` pixels and embedded as a data URI: PNG if it
+/// has transparency, JPEG otherwise. `None` if it cannot be encoded, which
+/// only costs the entry its picture.
+pub fn thumbnail(img: &DynamicImage, size: u32) -> Option<String> {
+    let thumb = img.thumbnail(size, size);
+    let (thumb, format) = if thumb.color().has_alpha() {
+        (
+            DynamicImage::ImageRgba8(thumb.to_rgba8()),
+            DataUriFormat::Png,
+        )
+    } else {
+        (
+            DynamicImage::ImageRgb8(thumb.to_rgb8()),
+            DataUriFormat::Jpeg,
+        )
+    };
+    output::data_uri(&thumb, format, &SaveOptions::default()).ok()
+}
+
+/// Self-contained HTML gallery of a directory run, written once at the end.
+pub struct HtmlReport {
+    path: PathBuf,
+    thumb_size: u32,
+    columns: u32,
+    entries: Vec<ReportEntry>,
+}
+
+impl HtmlReport {
+    pub fn new(path: &Path, thumb_size: u32, columns: u32) -> Self {
+        HtmlReport {
+            path: path.to_path_buf(),
+            thumb_size,
+            columns,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, entry: ReportEntry) {
+        self.entries.push(entry);
+    }
+
+    /// Write the gallery, sorted by input path so parallel runs give the
+    /// same page.
+    pub fn finish(mut self) -> Result<()> {
+        self.entries.sort_by(|a, b| a.input.cmp(&b.input));
+        fs::write(&self.path, self.render()).context("Failed to write HTML report")
+    }
+
+    fn render(&self) -> String {
+        let failed = self.entries.iter().filter(|e| !succeeded(e.status)).count();
+
+        let mut html = String::new();
+        writeln!(
+            html,
+            "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+<meta charset=\"utf-8\">
+<title>face_cropper report</title>
+<style>
+body {{ font-family: sans-serif; margin: 1.5em; background: #f4f4f4; }}
+.grid {{ display: grid; grid-template-columns: repeat({columns}, {card}px); gap: 12px; }}
+.card {{ background: #fff; padding: 8px; border-radius: 4px; font-size: 12px; overflow-wrap: anywhere; }}
+.card.failed {{ background: #fde8e8; }}
+.thumb {{ width: {size}px; height: {size}px; display: flex; align-items: center; justify-content: center; background: #ddd; }}
+.thumb img {{ max-width: 100%; max-height: 100%; }}
+.status {{ font-weight: bold; }}
+.detail {{ color: #a00; }}
+</style>
+</head>
+<body>
+<h1>face_cropper report</h1>
+<p>{total} image(s), {failed} failed.</p>
+<div class=\"grid\">",
+            columns = self.columns,
+            card = self.thumb_size + 16,
+            size = self.thumb_size,
+            total = self.entries.len(),
+            failed = failed,
+        )
+        .unwrap();
+
+        for entry in &self.entries {
+            let name = escape(&entry.input.display().to_string());
+            let class = if succeeded(entry.status) {
+                "card"
+            } else {
+                "card failed"
+            };
+            writeln!(html, "<div class=\"{}\">", class).unwrap();
+            match &entry.thumbnail {
+                Some(uri) => writeln!(
+                    html,
+                    "<div class=\"thumb\"><img src=\"{}\" alt=\"{}\"></div>",
+                    uri, name
+                )
+                .unwrap(),
+                None => writeln!(html, "<div class=\"thumb\">no preview</div>").unwrap(),
+            }
+            writeln!(html, "<div>{}</div>", name).unwrap();
+            let score = entry
+                .score
+                .map(|score| format!(", score {:.2}", score))
+                .unwrap_or_default();
+            writeln!(
+                html,
+                "<div class=\"status\">{}{}</div>",
+                entry.status.label(),
+                score
+            )
+            .unwrap();
+            if !entry.detail.is_empty() {
+                writeln!(
+                    html,
+                    "<div class=\"detail\">{}</div>",
+                    escape(&entry.detail)
+                )
+                .unwrap();
+            }
+            writeln!(html, "</div>").unwrap();
+        }
+
+        writeln!(html, "</div>\n</body>\n</html>").unwrap();
+        html
+    }
+}
+
+fn succeeded(status: Status) -> bool {
+    matches!(status, Status::Ok | Status::Placeholder)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
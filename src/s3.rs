@@ -0,0 +1,126 @@
+//! `s3://bucket/prefix` as `--output`, behind the `aws` feature.
+//!
+//! Crops are encoded in memory and uploaded one by one as they finish;
+//! nothing is written locally. Credentials and region come from the
+//! standard AWS chain (environment, shared config and credentials files,
+//! SSO, instance and container roles).
+
+use std::path::Path;
+
+pub fn is_s3(path: &Path) -> bool {
+    path.to_str().is_some_and(|s| s.starts_with("s3://"))
+}
+
+#[cfg(feature = "aws")]
+pub use upload::S3Output;
+
+/// Stand-in when the `aws` feature is off: it cannot be constructed, so the
+/// run stops before any image is processed.
+#[cfg(not(feature = "aws"))]
+pub enum S3Output {}
+
+#[cfg(not(feature = "aws"))]
+impl S3Output {
+    pub fn connect(_url: &str) -> anyhow::Result<Self> {
+        anyhow::bail!("S3 output not compiled in (rebuild with `--features aws`)")
+    }
+
+    pub fn put(&self, _name: &str, _data: &[u8]) -> anyhow::Result<()> {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "aws")]
+mod upload {
+    use anyhow::{Context, Result, anyhow, bail};
+    use aws_config::BehaviorVersion;
+    use aws_config::retry::RetryConfig;
+    use aws_sdk_s3::Client;
+    use aws_sdk_s3::error::DisplayErrorContext;
+    use aws_sdk_s3::primitives::ByteStream;
+    use std::path::Path;
+    use tokio::runtime::Runtime;
+
+    /// Attempts per upload, including the first; throttling, timeouts and
+    /// 5xx responses are retried with backoff.
+    const MAX_ATTEMPTS: u32 = 5;
+
+    /// Bucket and key prefix of an `s3://bucket/prefix` URL. The prefix has no
+    /// leading or trailing `/` and may be empty.
+    fn parse(url: &str) -> Result<(String, String)> {
+        let rest = url.strip_prefix("s3://").unwrap_or(url);
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            bail!("{} names no bucket (use s3://bucket/prefix)", url);
+        }
+        Ok((bucket.to_string(), prefix.trim_matches('/').to_string()))
+    }
+
+    /// `name` under `prefix`, joined with `/`.
+    fn key(prefix: &str, name: &str) -> String {
+        if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", prefix, name)
+        }
+    }
+
+    /// Uploads crops under an `s3://bucket/prefix` URL. The SDK is async, so
+    /// a small runtime of its own is kept to wait on; `put` is safe to call
+    /// from parallel workers.
+    pub struct S3Output {
+        runtime: Runtime,
+        client: Client,
+        bucket: String,
+        prefix: String,
+    }
+
+    impl S3Output {
+        pub fn connect(url: &str) -> Result<Self> {
+            let (bucket, prefix) = parse(url)?;
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .context("Failed to start the S3 upload runtime")?;
+            let config = runtime.block_on(
+                aws_config::defaults(BehaviorVersion::latest())
+                    .retry_config(RetryConfig::standard().with_max_attempts(MAX_ATTEMPTS))
+                    .load(),
+            );
+            Ok(S3Output {
+                runtime,
+                client: Client::new(&config),
+                bucket,
+                prefix,
+            })
+        }
+
+        /// Upload `data` as `name` under the prefix, with the content type
+        /// of its image format.
+        pub fn put(&self, name: &str, data: &[u8]) -> Result<()> {
+            let key = key(&self.prefix, name);
+            let content_type = image::ImageFormat::from_path(Path::new(name))
+                .map(|format| format.to_mime_type())
+                .unwrap_or("application/octet-stream");
+            self.runtime
+                .block_on(
+                    self.client
+                        .put_object()
+                        .bucket(&self.bucket)
+                        .key(&key)
+                        .content_type(content_type)
+                        .body(ByteStream::from(data.to_vec()))
+                        .send(),
+                )
+                .map_err(|e| {
+                    anyhow!(
+                        "Failed to upload s3://{}/{}: {}",
+                        self.bucket,
+                        key,
+                        DisplayErrorContext(&e)
+                    )
+                })?;
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::{Result, bail};
+use std::path::PathBuf;
+
+use crate::detection::{DetectorConfig, FaceDetector, FaceFinder, GrayConversion};
+use crate::sweep;
+
+/// Percentiles printed under the histogram.
+const PERCENTILES: [u32; 7] = [1, 5, 25, 50, 75, 95, 99];
+
+/// Width of the longest histogram bar, in characters.
+const BAR_WIDTH: usize = 40;
+
+/// Settings for the `scores` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct ScoresArgs {
+    /// Image, or directory of images (not searched recursively), to detect on
+    input: PathBuf,
+
+    /// Number of histogram buckets
+    #[arg(long, default_value_t = 10, value_name = "N", value_parser = clap::value_parser!(u32).range(1..=100))]
+    buckets: u32,
+
+    /// Lowest score the detector reports during the pass, so detections
+    /// below the usual --score-thresh show up too
+    #[arg(long, default_value_t = 0.01, value_name = "SCORE")]
+    score_floor: f64,
+}
+
+/// Detect on every sample image with the score threshold lowered to the
+/// floor and print a histogram and percentiles of the face scores, to pick
+/// a --score-thresh from. `base.score_thresh` is the threshold the counts
+/// are compared against.
+pub fn run(
+    args: &ScoresArgs,
+    base: &DetectorConfig,
+    gray_conversion: GrayConversion,
+    fallback: bool,
+) -> Result<()> {
+    // rustface panics on thresholds of 0 and below.
+    if args.score_floor <= 0.0 {
+        bail!("--score-floor must be greater than 0");
+    }
+
+    let config = DetectorConfig {
+        score_thresh: args.score_floor,
+        // Bands would raise the threshold again for some images.
+        size_bands: Vec::new(),
+        ..base.clone()
+    };
+    let mut finder = FaceFinder::inline(&config)?;
+
+    let images = sweep::load_images(&args.input, gray_conversion, fallback)?;
+    let mut scores = Vec::new();
+    for gray in &images {
+        scores.extend(finder.detect(gray.clone())?.iter().map(|face| face.score()));
+    }
+    scores.sort_by(f64::total_cmp);
+
+    let (Some(&lowest), Some(&highest)) = (scores.first(), scores.last()) else {
+        println!(
+            "{} image(s), no faces scored above {}",
+            images.len(),
+            args.score_floor
+        );
+        return Ok(());
+    };
+    println!(
+        "{} image(s), {} face(s), scores {:.2} to {:.2}",
+        images.len(),
+        scores.len(),
+        lowest,
+        highest
+    );
+    println!();
+
+    let buckets = args.buckets as usize;
+    let width = (highest - lowest) / buckets as f64;
+    let mut counts = vec![0usize; buckets];
+    for &score in &scores {
+        let bucket = if width > 0.0 {
+            (((score - lowest) / width) as usize).min(buckets - 1)
+        } else {
+            0
+        };
+        counts[bucket] += 1;
+    }
+    let most = counts.iter().copied().max().unwrap_or(1);
+    println!("{:>17}  {:>6}", "score", "faces");
+    for (i, &count) in counts.iter().enumerate() {
+        let from = lowest + i as f64 * width;
+        let bar = "#".repeat((count * BAR_WIDTH).div_ceil(most));
+        let line = format!(
+            "{:>7.2} - {:>7.2}  {:>6}  {}",
+            from,
+            from + width,
+            count,
+            bar
+        );
+        println!("{}", line.trim_end());
+    }
+    println!();
+
+    let ranked: Vec<String> = PERCENTILES
+        .iter()
+        .map(|&p| format!("p{} {:.2}", p, percentile(&scores, p)))
+        .collect();
+    println!("{}", ranked.join("  "));
+
+    let kept = scores
+        .iter()
+        .filter(|&&score| score >= base.score_thresh)
+        .count();
+    println!(
+        "{} of {} face(s) reach the current --score-thresh {}",
+        kept,
+        scores.len(),
+        base.score_thresh
+    );
+    Ok(())
+}
+
+/// Nearest-rank `p`th percentile of `sorted`, which must not be empty.
+fn percentile(sorted: &[f64], p: u32) -> f64 {
+    let rank = (p as f64 / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
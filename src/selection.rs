@@ -0,0 +1,132 @@
+use clap::ValueEnum;
+use rustface::FaceInfo;
+
+use crate::geometry;
+
+/// Faces whose areas are within this fraction of the largest are treated as
+/// the same size, and the detector score decides between them.
+const AREA_TIE_TOLERANCE: f64 = 0.05;
+
+/// How to pick a single face when the detector finds several.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SelectStrategy {
+    /// The face with the largest bounding box (ties broken by score)
+    Largest,
+    /// The face with the highest detector score
+    Score,
+}
+
+fn area(face: &FaceInfo) -> f64 {
+    face.bbox().width() as f64 * face.bbox().height() as f64
+}
+
+/// Index of the chosen face, or `None` if `faces` is empty.
+///
+/// The result does not depend on the order `faces` arrives in: remaining ties
+/// after area and score are broken by top-most, then left-most position.
+pub fn select_face(faces: &[FaceInfo], strategy: SelectStrategy) -> Option<usize> {
+    let tie_break = |&a: &usize, &b: &usize| by_score_then_position(faces, a, b);
+
+    match strategy {
+        SelectStrategy::Largest => {
+            let max_area = faces.iter().map(area).fold(0.0, f64::max);
+            (0..faces.len())
+                .filter(|&i| area(&faces[i]) >= max_area * (1.0 - AREA_TIE_TOLERANCE))
+                .max_by(tie_break)
+        }
+        SelectStrategy::Score => (0..faces.len()).max_by(tie_break),
+    }
+}
+
+/// Like `select_face`, but each face is rated by what `strategy` goes by,
+/// relative to the best face, plus `weight` times its closeness to the
+/// center of the `width` x `height` image:
+///
+/// `value / best value + weight * (1 - distance to center / half diagonal)`
+///
+/// where the value is the box area for `Largest` and the detector score
+/// for `Score`. The highest rating wins; ties go by score, then position.
+pub fn select_centered(
+    faces: &[FaceInfo],
+    strategy: SelectStrategy,
+    weight: f64,
+    dimensions: (u32, u32),
+) -> Option<usize> {
+    let value = |face: &FaceInfo| match strategy {
+        SelectStrategy::Largest => area(face),
+        SelectStrategy::Score => face.score(),
+    };
+    let best = faces.iter().map(value).fold(0.0, f64::max);
+    let rating = |face: &FaceInfo| {
+        let centrality = geometry::centrality(face.bbox(), dimensions);
+        let relative = if best > 0.0 { value(face) / best } else { 0.0 };
+        relative + weight * centrality
+    };
+
+    (0..faces.len()).max_by(|&a, &b| {
+        rating(&faces[a])
+            .total_cmp(&rating(&faces[b]))
+            .then_with(|| by_score_then_position(faces, a, b))
+    })
+}
+
+/// Orders face `a` against face `b` by score, with the top-most, then
+/// left-most one greater where scores are equal.
+fn by_score_then_position(faces: &[FaceInfo], a: usize, b: usize) -> std::cmp::Ordering {
+    let (fa, fb) = (&faces[a], &faces[b]);
+    fa.score()
+        .total_cmp(&fb.score())
+        .then_with(|| fb.bbox().y().cmp(&fa.bbox().y()))
+        .then_with(|| fb.bbox().x().cmp(&fa.bbox().x()))
+}
+
+/// Indices of the `n` largest faces, largest first. Equal areas are ordered
+/// by score, then top-most and left-most position, so the result does not
+/// depend on detector order.
+pub fn largest_n(faces: &[FaceInfo], n: usize) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..faces.len()).collect();
+    order.sort_by(|&a, &b| {
+        let (fa, fb) = (&faces[a], &faces[b]);
+        area(fb)
+            .total_cmp(&area(fa))
+            .then_with(|| fb.score().total_cmp(&fa.score()))
+            .then_with(|| fa.bbox().y().cmp(&fb.bbox().y()))
+            .then_with(|| fa.bbox().x().cmp(&fb.bbox().x()))
+    });
+    order.truncate(n);
+    order
+}
+
+/// Width and height of the `p`th percentile face by size (nearest rank,
+/// each side ranked on its own), or `None` if `faces` is empty.
+pub fn percentile_size(faces: &[FaceInfo], p: u32) -> Option<(u32, u32)> {
+    let ranked = |side: fn(&FaceInfo) -> u32| {
+        let mut sides: Vec<u32> = faces.iter().map(side).collect();
+        sides.sort_unstable();
+        let rank = (p as f64 / 100.0 * sides.len() as f64).ceil() as usize;
+        sides.get(rank.clamp(1, sides.len()) - 1).copied()
+    };
+    Some((
+        ranked(|face| face.bbox().width())?,
+        ranked(|face| face.bbox().height())?,
+    ))
+}
+
+/// Index of the `n`th face (0-based) counting left to right by the left edge
+/// of its box, top to bottom where edges are equal. `None` if there are not
+/// that many faces.
+pub fn nth_from_left(faces: &[FaceInfo], n: usize) -> Option<usize> {
+    let mut order: Vec<usize> = (0..faces.len()).collect();
+    order.sort_by_key(|&i| (faces[i].bbox().x(), faces[i].bbox().y()));
+    order.get(n).copied()
+}
+
+/// Drop faces whose area is below `fraction` of the largest face's area.
+///
+/// Returns the kept faces and the dropped ones, both in detector order.
+pub fn filter_relative_size(faces: Vec<FaceInfo>, fraction: f64) -> (Vec<FaceInfo>, Vec<FaceInfo>) {
+    let max_area = faces.iter().map(area).fold(0.0, f64::max);
+    faces
+        .into_iter()
+        .partition(|face| area(face) >= max_area * fraction)
+}
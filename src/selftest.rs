@@ -0,0 +1,115 @@
+use anyhow::{Context, Result, bail};
+use image::{DynamicImage, Rgba};
+use rustface::{FaceInfo, Rectangle};
+use std::path::Path;
+
+use crate::detection::{self, DetectorConfig, FaceDetector, FaceFinder, GrayConversion};
+use crate::models;
+
+/// A small portrait with one clearly frontal face, cut from a public domain
+/// photograph (see `assets/README.md` for its source).
+const TEST_IMAGE: &[u8] = include_bytes!("../assets/selftest_face.jpg");
+
+/// Where the default detector finds the face in `TEST_IMAGE` (x, y, side).
+const EXPECTED_FACE: (i32, i32, u32) = (58, 36, 97);
+
+/// Detections must overlap `EXPECTED_FACE` at least this much to pass.
+const MIN_IOU: f64 = 0.5;
+
+/// Run the embedded model written to `model_path` over `TEST_IMAGE` with the
+/// default detector settings, printing each step, then over the same image
+/// in other color types. Fails if the model cannot be loaded or the known
+/// face is not found in any of them.
+pub fn run(model_path: &Path) -> Result<()> {
+    let model_size = std::fs::metadata(model_path)
+        .context("Model file missing")?
+        .len();
+    println!("model:    {} ({} bytes)", model_path.display(), model_size);
+
+    let config = DetectorConfig::new(models::EMBEDDED.to_string(), model_path.to_path_buf());
+    let mut finder = FaceFinder::inline(&config).context("selftest: FAIL (detector)")?;
+    println!("detector: ok");
+
+    let img = image::load_from_memory(TEST_IMAGE).context("selftest: FAIL (test image)")?;
+    let gray = GrayConversion::default().apply(&img);
+    println!("image:    {}x{}", gray.width(), gray.height());
+
+    let faces = finder.detect(gray)?;
+    for face in &faces {
+        let bbox = face.bbox();
+        println!(
+            "face:     ({}, {}) {}x{} score {:.2}",
+            bbox.x(),
+            bbox.y(),
+            bbox.width(),
+            bbox.height(),
+            face.score()
+        );
+    }
+    if !finds_expected(&faces) {
+        let (x, y, side) = EXPECTED_FACE;
+        bail!(
+            "selftest: FAIL (expected a face near ({}, {}) {}x{}, found {})",
+            x,
+            y,
+            side,
+            side,
+            faces.len()
+        );
+    }
+
+    // The same image in every other color type a decoder can hand over.
+    for (name, variant) in color_variants(&img) {
+        let faces = finder.detect(GrayConversion::default().apply(&variant))?;
+        if !finds_expected(&faces) {
+            bail!("selftest: FAIL (face not found in {} image)", name);
+        }
+        println!("color:    {} ok", name);
+    }
+
+    println!("selftest: PASS");
+    Ok(())
+}
+
+/// Whether one of `faces` overlaps `EXPECTED_FACE` enough to pass.
+fn finds_expected(faces: &[FaceInfo]) -> bool {
+    let (x, y, side) = EXPECTED_FACE;
+    let expected = Rectangle::new(x, y, side, side);
+    faces
+        .iter()
+        .any(|face| detection::iou(face.bbox(), &expected) >= MIN_IOU)
+}
+
+/// `img` converted to gray, alpha, 16-bit and float color types. The alpha
+/// variants have everything outside the face's box transparent, stored as
+/// black.
+fn color_variants(img: &DynamicImage) -> Vec<(&'static str, DynamicImage)> {
+    let (x, y, side) = EXPECTED_FACE;
+    let (margin, side) = (side as i64 / 2, side as i64);
+    let mut cut_out = img.to_rgba8();
+    for (px, py, pixel) in cut_out.enumerate_pixels_mut() {
+        let inside = |at: u32, from: i32| {
+            (from as i64 - margin..from as i64 + side + margin).contains(&(at as i64))
+        };
+        if !(inside(px, x) && inside(py, y)) {
+            *pixel = Rgba([0, 0, 0, 0]);
+        }
+    }
+    let cut_out = DynamicImage::ImageRgba8(cut_out);
+
+    vec![
+        ("gray", DynamicImage::ImageLuma8(img.to_luma8())),
+        (
+            "gray+alpha",
+            DynamicImage::ImageLumaA8(cut_out.to_luma_alpha8()),
+        ),
+        ("gray 16-bit", DynamicImage::ImageLuma16(img.to_luma16())),
+        ("RGBA", cut_out.clone()),
+        ("RGB 16-bit", DynamicImage::ImageRgb16(img.to_rgb16())),
+        (
+            "RGBA 16-bit",
+            DynamicImage::ImageRgba16(cut_out.to_rgba16()),
+        ),
+        ("RGB float", DynamicImage::ImageRgb32F(img.to_rgb32f())),
+    ]
+}
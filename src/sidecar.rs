@@ -0,0 +1,57 @@
+use anyhow::{Context, Result};
+use rustface::Rectangle;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::quality::FaceQuality;
+
+/// Per-crop metadata written next to the crop with `--sidecar`, as
+/// `<crop stem>.json`.
+#[derive(Debug, Serialize)]
+pub struct Sidecar {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub faces_detected: usize,
+    /// The face box the crop was centered on (the union box with
+    /// `--group-crop`), in source image pixels.
+    pub face: Region,
+    /// Detector score of the chosen face.
+    pub score: f64,
+    /// Composite quality of the chosen face, with its parts.
+    pub quality: FaceQuality,
+    /// The cropped rectangle, in source image pixels.
+    pub crop: Region,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blurhash: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Region {
+    pub x: i64,
+    pub y: i64,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl From<&Rectangle> for Region {
+    fn from(rect: &Rectangle) -> Self {
+        Region {
+            x: rect.x() as i64,
+            y: rect.y() as i64,
+            width: rect.width(),
+            height: rect.height(),
+        }
+    }
+}
+
+/// Where the sidecar for the crop at `output_path` goes.
+pub fn path_for(output_path: &Path) -> PathBuf {
+    output_path.with_extension("json")
+}
+
+pub fn write(output_path: &Path, sidecar: &Sidecar) -> Result<()> {
+    let path = path_for(output_path);
+    let json = serde_json::to_string_pretty(sidecar).context("Failed to encode sidecar")?;
+    fs::write(&path, json + "\n").with_context(|| format!("Failed to write sidecar {:?}", path))
+}
@@ -0,0 +1,278 @@
+use anyhow::Result;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::ImageOutcome;
+use crate::detection::DetectionTimeout;
+
+/// Reasons an image is rejected by the validation in `process_image`: the
+/// face-count rule and the content-quality filters.
+///
+/// These are carried through `anyhow::Error` so callers can still print a plain
+/// message, while the directory loop can `downcast_ref` to tell them apart.
+#[derive(Debug)]
+pub enum ValidationError {
+    NoFaces,
+    MultipleFaces(usize),
+    /// Fewer faces than --min-faces.
+    TooFewFaces {
+        found: usize,
+        min: usize,
+    },
+    TooBlurry {
+        sharpness: f64,
+        min: f64,
+        faces: usize,
+    },
+    /// Perceptually identical to the given, earlier input.
+    Duplicate(PathBuf),
+    /// Width / height outside --min-aspect / --max-aspect.
+    AspectRatio(f64),
+    /// The face box reaches `overflow` pixels past the image edge
+    /// (0 = touching it), rejected by --reject-edge-faces.
+    EdgeFace {
+        overflow: i64,
+        faces: usize,
+    },
+    /// Detector score of the chosen face outside --score-min / --score-max.
+    ScoreOutOfBand {
+        score: f64,
+        faces: usize,
+    },
+    /// The crop is smaller than --crop-quality-check on some side, rejected
+    /// by --reject-low-detail.
+    LowDetail {
+        width: u32,
+        height: u32,
+        min: u32,
+        faces: usize,
+    },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::NoFaces => write!(f, "Validation Failed: No faces detected."),
+            ValidationError::MultipleFaces(n) => {
+                write!(
+                    f,
+                    "Validation Failed: Multiple faces detected (Found {}).",
+                    n
+                )
+            }
+            ValidationError::TooFewFaces { found, min } => write!(
+                f,
+                "Validation Failed: Too few faces detected (Found {}, --min-faces {}).",
+                found, min
+            ),
+            ValidationError::TooBlurry { sharpness, min, .. } => write!(
+                f,
+                "Validation Failed: Face too blurry (sharpness {:.1} < {:.1}).",
+                sharpness, min
+            ),
+            ValidationError::Duplicate(original) => {
+                write!(f, "Duplicate of {}.", original.display())
+            }
+            ValidationError::AspectRatio(ratio) => write!(
+                f,
+                "Validation Failed: Aspect ratio {:.2} is outside --min-aspect/--max-aspect.",
+                ratio
+            ),
+            ValidationError::EdgeFace { overflow: 0, .. } => {
+                write!(f, "Validation Failed: Face touches the image edge.")
+            }
+            ValidationError::EdgeFace { overflow, .. } => write!(
+                f,
+                "Validation Failed: Face is cut off by the image edge ({}px outside).",
+                overflow
+            ),
+            ValidationError::ScoreOutOfBand { score, .. } => write!(
+                f,
+                "Validation Failed: Face score {:.2} is outside --score-min/--score-max.",
+                score
+            ),
+            ValidationError::LowDetail {
+                width, height, min, ..
+            } => write!(
+                f,
+                "Validation Failed: Crop is only {}x{} (under {}px).",
+                width, height, min
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Aggregate counters for a directory run.
+#[derive(Debug, Default)]
+pub struct RunStats {
+    pub images_processed: u64,
+    pub faces_detected: u64,
+    pub no_face: u64,
+    pub multi_face: u64,
+    pub too_few_faces: u64,
+    pub errors: u64,
+    pub timeouts: u64,
+    pub filtered: u64,
+    pub duplicates: u64,
+    pub wrong_aspect: u64,
+    pub edge_faces: u64,
+    pub low_detail: u64,
+    pub out_of_band: u64,
+    /// Faceless images written as placeholders by --write-empty-on-no-face.
+    pub placeholders: u64,
+    /// --largest-n crops skipped by --reject-duplicate-output.
+    pub duplicate_crops: u64,
+    /// Files in the input directory left out for not being images.
+    pub non_images: u64,
+    pub duration: Duration,
+}
+
+/// Coarse outcome of one image, shared by the counters and the manifest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// No face; a placeholder was written instead (--write-empty-on-no-face).
+    Placeholder,
+    NoFace,
+    MultiFace,
+    TooFewFaces,
+    Blurry,
+    Duplicate,
+    Aspect,
+    Edge,
+    LowDetail,
+    Score,
+    Timeout,
+    Error,
+}
+
+impl Status {
+    pub fn of(result: &Result<ImageOutcome>) -> Self {
+        match result {
+            Ok(outcome) if outcome.placeholder => Status::Placeholder,
+            Ok(_) => Status::Ok,
+            Err(e) => match e.downcast_ref::<ValidationError>() {
+                Some(ValidationError::NoFaces) => Status::NoFace,
+                Some(ValidationError::MultipleFaces(_)) => Status::MultiFace,
+                Some(ValidationError::TooFewFaces { .. }) => Status::TooFewFaces,
+                Some(ValidationError::TooBlurry { .. }) => Status::Blurry,
+                Some(ValidationError::Duplicate(_)) => Status::Duplicate,
+                Some(ValidationError::AspectRatio(_)) => Status::Aspect,
+                Some(ValidationError::EdgeFace { .. }) => Status::Edge,
+                Some(ValidationError::LowDetail { .. }) => Status::LowDetail,
+                Some(ValidationError::ScoreOutOfBand { .. }) => Status::Score,
+                None if e.is::<DetectionTimeout>() => Status::Timeout,
+                None => Status::Error,
+            },
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Placeholder => "placeholder",
+            Status::NoFace => "no_face",
+            Status::MultiFace => "multi_face",
+            Status::TooFewFaces => "too_few_faces",
+            Status::Blurry => "blurry",
+            Status::Duplicate => "duplicate",
+            Status::Aspect => "aspect",
+            Status::Edge => "edge",
+            Status::LowDetail => "low_detail",
+            Status::Score => "score",
+            Status::Timeout => "timeout",
+            Status::Error => "error",
+        }
+    }
+}
+
+/// Number of faces the detector reported for an image, where known.
+pub fn faces_found(result: &Result<ImageOutcome>) -> usize {
+    match result {
+        Ok(outcome) => outcome.faces_detected,
+        Err(e) => match e.downcast_ref::<ValidationError>() {
+            Some(ValidationError::MultipleFaces(n)) => *n,
+            Some(ValidationError::TooFewFaces { found, .. }) => *found,
+            Some(
+                ValidationError::TooBlurry { faces, .. }
+                | ValidationError::EdgeFace { faces, .. }
+                | ValidationError::ScoreOutOfBand { faces, .. }
+                | ValidationError::LowDetail { faces, .. },
+            ) => *faces,
+            _ => 0,
+        },
+    }
+}
+
+impl RunStats {
+    pub fn record(&mut self, result: &Result<ImageOutcome>) {
+        self.images_processed += 1;
+        self.faces_detected += faces_found(result) as u64;
+        if let Ok(outcome) = result {
+            self.duplicate_crops += outcome.duplicate_crops as u64;
+        }
+
+        match Status::of(result) {
+            Status::Ok => {}
+            Status::Placeholder => self.placeholders += 1,
+            Status::NoFace => self.no_face += 1,
+            Status::MultiFace => self.multi_face += 1,
+            Status::TooFewFaces => self.too_few_faces += 1,
+            Status::Blurry => self.filtered += 1,
+            Status::Duplicate => self.duplicates += 1,
+            Status::Aspect => self.wrong_aspect += 1,
+            Status::Edge => self.edge_faces += 1,
+            Status::LowDetail => self.low_detail += 1,
+            Status::Score => self.out_of_band += 1,
+            Status::Timeout => self.timeouts += 1,
+            Status::Error => self.errors += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejected(error: ValidationError) -> Result<ImageOutcome> {
+        Err(error.into())
+    }
+
+    #[test]
+    fn filtered_images_keep_their_face_count() {
+        let results = [
+            rejected(ValidationError::TooBlurry {
+                sharpness: 3.0,
+                min: 50.0,
+                faces: 2,
+            }),
+            rejected(ValidationError::EdgeFace {
+                overflow: 0,
+                faces: 2,
+            }),
+            rejected(ValidationError::ScoreOutOfBand {
+                score: 4.0,
+                faces: 2,
+            }),
+            rejected(ValidationError::LowDetail {
+                width: 20,
+                height: 20,
+                min: 64,
+                faces: 2,
+            }),
+        ];
+        let mut stats = RunStats::default();
+        for result in &results {
+            assert_eq!(faces_found(result), 2);
+            stats.record(result);
+        }
+        assert_eq!(stats.faces_detected, 8);
+        assert_eq!((stats.filtered, stats.edge_faces), (1, 1));
+        assert_eq!((stats.out_of_band, stats.low_detail), (1, 1));
+
+        assert_eq!(faces_found(&rejected(ValidationError::NoFaces)), 0);
+    }
+}
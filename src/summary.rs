@@ -0,0 +1,61 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::stats::{RunStats, Status};
+
+/// End-of-run report for `--json-summary` / `--summary-file`.
+#[derive(Debug, Serialize)]
+struct Summary {
+    images_processed: u64,
+    succeeded: u64,
+    faces_detected: u64,
+    /// Failed images by manifest status label (`no_face`, `error`, ...).
+    failures: BTreeMap<&'static str, u64>,
+    /// Faceless images written as placeholders by --write-empty-on-no-face.
+    placeholders: u64,
+    /// --largest-n crops skipped by --reject-duplicate-output.
+    duplicate_crops: u64,
+    /// Files left out for not being images; not part of images_processed.
+    non_images: u64,
+    /// Inputs skipped by --skip-on-existing-sidecar.
+    up_to_date: usize,
+    duration_seconds: f64,
+    parameters: BTreeMap<&'static str, String>,
+}
+
+/// The summary as a single line of JSON.
+pub fn render(
+    stats: &RunStats,
+    up_to_date: usize,
+    parameters: Vec<(&'static str, String)>,
+) -> String {
+    let failures = BTreeMap::from([
+        (Status::NoFace.label(), stats.no_face),
+        (Status::MultiFace.label(), stats.multi_face),
+        (Status::TooFewFaces.label(), stats.too_few_faces),
+        (Status::Blurry.label(), stats.filtered),
+        (Status::Duplicate.label(), stats.duplicates),
+        (Status::Aspect.label(), stats.wrong_aspect),
+        (Status::Edge.label(), stats.edge_faces),
+        (Status::LowDetail.label(), stats.low_detail),
+        (Status::Score.label(), stats.out_of_band),
+        (Status::Timeout.label(), stats.timeouts),
+        (Status::Error.label(), stats.errors),
+    ]);
+
+    let summary = Summary {
+        images_processed: stats.images_processed,
+        succeeded: stats.images_processed - stats.placeholders - failures.values().sum::<u64>(),
+        faces_detected: stats.faces_detected,
+        failures,
+        placeholders: stats.placeholders,
+        duplicate_crops: stats.duplicate_crops,
+        non_images: stats.non_images,
+        up_to_date,
+        duration_seconds: stats.duration.as_secs_f64(),
+        parameters: parameters.into_iter().collect(),
+    };
+
+    // Only plain numbers and strings, so this cannot fail.
+    serde_json::to_string(&summary).unwrap()
+}
@@ -0,0 +1,186 @@
+use anyhow::{Context, Result, bail};
+use image::GrayImage;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::detection::{DetectorConfig, FaceDetector, FaceFinder, GrayConversion};
+use crate::{is_image_extension, open_image, video};
+
+/// Settings for the `sweep` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct SweepArgs {
+    /// Image, or directory of images (not searched recursively), to detect on
+    input: PathBuf,
+
+    /// Minimum face sizes to try (at least 20)
+    #[arg(long, default_value = "20", value_name = "LIST", value_delimiter = ',', value_parser = parse_steps)]
+    min_face_size: Vec<Steps>,
+
+    /// Score thresholds to try
+    #[arg(long, default_value = "2", value_name = "LIST", value_delimiter = ',', value_parser = parse_steps)]
+    score_thresh: Vec<Steps>,
+
+    /// Pyramid scale factors to try (0.01 to 0.99)
+    #[arg(long, default_value = "0.8", value_name = "LIST", value_delimiter = ',', value_parser = parse_steps)]
+    pyramid_scale: Vec<Steps>,
+}
+
+/// One item of a sweep list: a single value, or every `step` from `start`
+/// up to and including `end` (written `START:END:STEP`).
+#[derive(Clone, Copy, Debug)]
+struct Steps {
+    start: f64,
+    end: f64,
+    step: f64,
+}
+
+impl Steps {
+    fn values(self) -> impl Iterator<Item = f64> {
+        // Count the steps up front so float error cannot drop the last one.
+        let count = ((self.end - self.start) / self.step + 1e-9).floor() as u32 + 1;
+        (0..count).map(move |i| self.start + i as f64 * self.step)
+    }
+}
+
+fn parse_steps(s: &str) -> Result<Steps, String> {
+    let number = |part: &str| {
+        part.trim()
+            .parse::<f64>()
+            .map_err(|_| format!("`{}` is not a number", part))
+    };
+
+    match s.split(':').collect::<Vec<_>>()[..] {
+        [value] => {
+            let value = number(value)?;
+            Ok(Steps {
+                start: value,
+                end: value,
+                step: 1.0,
+            })
+        }
+        [start, end, step] => {
+            let (start, end, step) = (number(start)?, number(end)?, number(step)?);
+            if step <= 0.0 || end < start {
+                return Err(format!("`{}` is not an increasing START:END:STEP range", s));
+            }
+            Ok(Steps { start, end, step })
+        }
+        _ => Err(format!("`{}` is neither a number nor START:END:STEP", s)),
+    }
+}
+
+fn expand(lists: &[Steps]) -> Vec<f64> {
+    lists.iter().flat_map(|steps| steps.values()).collect()
+}
+
+/// Detect on every sample image once per combination of the swept settings
+/// (on top of `base`) and print a table of what each combination finds.
+pub fn run(
+    args: &SweepArgs,
+    base: &DetectorConfig,
+    gray_conversion: GrayConversion,
+    fallback: bool,
+) -> Result<()> {
+    let min_face_sizes = expand(&args.min_face_size);
+    let score_threshes = expand(&args.score_thresh);
+    let pyramid_scales = expand(&args.pyramid_scale);
+
+    // rustface panics on values outside these ranges.
+    if min_face_sizes.iter().any(|&size| size < 20.0) {
+        bail!("--min-face-size values must be at least 20");
+    }
+    if score_threshes.iter().any(|&thresh| thresh <= 0.0) {
+        bail!("--score-thresh values must be greater than 0");
+    }
+    if pyramid_scales
+        .iter()
+        .any(|scale| !(0.01..=0.99).contains(scale))
+    {
+        bail!("--pyramid-scale values must be between 0.01 and 0.99");
+    }
+
+    let images = load_images(&args.input, gray_conversion, fallback)?;
+    println!(
+        "{} image(s), {} combination(s)",
+        images.len(),
+        min_face_sizes.len() * score_threshes.len() * pyramid_scales.len()
+    );
+    println!();
+    println!(
+        "{:>13}  {:>12}  {:>13}  {:>6}  {:>11}  {:>8}",
+        "min-face-size", "score-thresh", "pyramid-scale", "faces", "single-face", "time-ms"
+    );
+
+    for &min_face_size in &min_face_sizes {
+        for &score_thresh in &score_threshes {
+            for &pyramid_scale in &pyramid_scales {
+                let config = DetectorConfig {
+                    min_face_size: min_face_size.round() as u32,
+                    score_thresh,
+                    pyramid_scale_factor: pyramid_scale as f32,
+                    // The swept values must apply to every image.
+                    size_bands: Vec::new(),
+                    ..base.clone()
+                };
+                let mut finder = FaceFinder::inline(&config)?;
+
+                let started = Instant::now();
+                let mut faces = 0;
+                let mut single = 0;
+                for gray in &images {
+                    let found = finder.detect(gray.clone())?.len();
+                    faces += found;
+                    single += usize::from(found == 1);
+                }
+
+                println!(
+                    "{:>13}  {:>12}  {:>13}  {:>6}  {:>11}  {:>8}",
+                    config.min_face_size,
+                    score_thresh,
+                    format!("{:.2}", pyramid_scale),
+                    faces,
+                    format!("{}/{}", single, images.len()),
+                    started.elapsed().as_millis()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// `input`, or the images directly inside it, converted for the detector.
+/// `fallback` is passed on to `open_image`.
+pub fn load_images(
+    input: &Path,
+    gray_conversion: GrayConversion,
+    fallback: bool,
+) -> Result<Vec<GrayImage>> {
+    let paths = if input.is_dir() {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(input).context("Failed to read input directory")? {
+            let path = entry?.path();
+            if path.is_file() && is_image_extension(&path) && !video::is_video(&path) {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        paths
+    } else {
+        vec![input.to_path_buf()]
+    };
+
+    let mut images = Vec::new();
+    for path in paths {
+        match open_image(&path, fallback) {
+            Ok(img) => images.push(gray_conversion.apply(&img)),
+            Err(e) => eprintln!("Skipping {:?}: {}", path, e),
+        }
+    }
+
+    if images.is_empty() {
+        bail!("No images to detect on in {:?}", input);
+    }
+    Ok(images)
+}
@@ -0,0 +1,53 @@
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Tokens expanded in `--output`, with their `chrono` formats.
+const TOKENS: &[(&str, &str)] = &[
+    ("{Y}", "%Y"),
+    ("{m}", "%m"),
+    ("{d}", "%d"),
+    ("{H}", "%H"),
+    ("{M}", "%M"),
+];
+
+/// Which date fills the `--output` tokens.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum DateSource {
+    /// The input file's modification time
+    #[default]
+    Mtime,
+    /// When the run started
+    Now,
+}
+
+pub fn has_tokens(path: &Path) -> bool {
+    let text = path.to_string_lossy();
+    TOKENS.iter().any(|(token, _)| text.contains(token))
+}
+
+/// `path` with every date token replaced from `date`, in local time.
+pub fn expand(path: &Path, date: &DateTime<Local>) -> PathBuf {
+    if !has_tokens(path) {
+        return path.to_path_buf();
+    }
+
+    let mut text = path.to_string_lossy().into_owned();
+    for (token, format) in TOKENS {
+        text = text.replace(token, &date.format(format).to_string());
+    }
+    PathBuf::from(text)
+}
+
+/// The date to expand tokens with for `input`. Falls back to `now` if the
+/// modification time cannot be read.
+pub fn date_for(source: DateSource, input: &Path, now: &DateTime<Local>) -> DateTime<Local> {
+    match source {
+        DateSource::Mtime => fs::metadata(input)
+            .and_then(|meta| meta.modified())
+            .map(DateTime::from)
+            .unwrap_or(*now),
+        DateSource::Now => *now,
+    }
+}
@@ -0,0 +1,205 @@
+//! Face-following crops of video clips, behind the `video` feature.
+//!
+//! Frames are decoded and encoded by the `ffmpeg` and `ffprobe` command-line
+//! tools, which must be on `PATH`; nothing is linked into the binary.
+
+use std::ffi::OsStr;
+use std::path::Path;
+
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "m4v", "mov", "mkv", "webm", "avi"];
+
+pub fn is_video(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .is_some_and(|ext| VIDEO_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+#[cfg(feature = "video")]
+pub use ffmpeg::process;
+
+#[cfg(not(feature = "video"))]
+pub fn process(
+    _input: &Path,
+    _output: &Path,
+    _finder: &mut dyn crate::detection::FaceDetector,
+    _args: &crate::Args,
+) -> anyhow::Result<crate::ImageOutcome> {
+    anyhow::bail!("Video support not compiled in (rebuild with `--features video`)")
+}
+
+#[cfg(feature = "video")]
+mod ffmpeg {
+    use anyhow::{Context, Result, bail};
+    use image::{DynamicImage, RgbImage};
+    use rustface::FaceInfo;
+    use std::io::{ErrorKind, Read, Write};
+    use std::path::Path;
+    use std::process::{Command, Stdio};
+
+    use crate::detection::FaceDetector;
+    use crate::geometry::{self, SquareBasis, box_around, center_of};
+    use crate::{Args, ImageOutcome, choose_face, crop_options, detect_faces};
+
+    /// Crop `input` to a clip that follows the face, written to `output`.
+    ///
+    /// The face is chosen in the first frame (which must show one) using the
+    /// usual selection options, and the crop size is fixed from it. Every
+    /// later frame is searched again and the crop moves towards the face
+    /// nearest to the current center, smoothed by an exponential moving
+    /// average (`--smoothing`) to keep per-frame detection jitter out of the
+    /// output; frames without a face keep the last position. Audio is dropped.
+    pub fn process(
+        input: &Path,
+        output: &Path,
+        finder: &mut dyn FaceDetector,
+        args: &Args,
+    ) -> Result<ImageOutcome> {
+        let probe = probe(input)?;
+        let (width, height) = (probe.width, probe.height);
+
+        let mut decoder = Command::new("ffmpeg")
+            .args(["-v", "error", "-i"])
+            .arg(input)
+            .args(["-f", "rawvideo", "-pix_fmt", "rgb24", "-"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("Failed to run ffmpeg (is FFmpeg installed?)")?;
+        let mut frames = decoder.stdout.take().unwrap();
+        let frame_len = width as usize * height as usize * 3;
+
+        let mut frame =
+            read_frame(&mut frames, width, height, frame_len)?.context("Video has no frames")?;
+        let faces = detect_faces(&frame, finder, args)?;
+        let chosen = &faces[choose_face(&faces, (width, height), args)?];
+        let face = *chosen.bbox();
+
+        // yuv420p, the widely playable pixel format, needs even dimensions.
+        // Frames are not padded, so the crop stays within them.
+        let mut opts = crop_options(args);
+        opts.even = true;
+        opts.basis = SquareBasis::Short;
+        let first = geometry::compute_crop_rect(width, height, &face, &opts);
+        let mut center = center_of(&face);
+
+        let mut encoder = Command::new("ffmpeg")
+            .args(["-v", "error", "-y", "-f", "rawvideo", "-pix_fmt", "rgb24"])
+            .arg("-s")
+            .arg(format!("{}x{}", first.width, first.height))
+            .arg("-r")
+            .arg(&probe.frame_rate)
+            .args(["-i", "-", "-an", "-pix_fmt", "yuv420p"])
+            .arg(output)
+            .stdin(Stdio::piped())
+            .spawn()
+            .context("Failed to run ffmpeg (is FFmpeg installed?)")?;
+        let mut sink = encoder.stdin.take().unwrap();
+
+        let mut frame_count = 0;
+        loop {
+            let target = box_around(center, face.width(), face.height());
+            let rect = geometry::compute_crop_rect(width, height, &target, &opts);
+            let cropped = frame.crop_imm(rect.x, rect.y, rect.width, rect.height);
+            sink.write_all(cropped.to_rgb8().as_raw())
+                .context("Failed to write frame to ffmpeg")?;
+            frame_count += 1;
+
+            let Some(next) = read_frame(&mut frames, width, height, frame_len)? else {
+                break;
+            };
+            frame = next;
+
+            if let Some(found) = nearest(&detect_faces(&frame, finder, args)?, center) {
+                center.0 += args.smoothing * (found.0 - center.0);
+                center.1 += args.smoothing * (found.1 - center.1);
+            }
+        }
+
+        drop(sink);
+        if !encoder.wait()?.success() {
+            bail!("ffmpeg failed to encode {:?}", output);
+        }
+        if !decoder.wait()?.success() {
+            bail!("ffmpeg failed to decode {:?}", input);
+        }
+
+        if args.verbose {
+            eprintln!(
+                "  {} frames, crop {}x{}",
+                frame_count, first.width, first.height
+            );
+        }
+
+        Ok(ImageOutcome {
+            faces_detected: faces.len(),
+            blurhash: None,
+            placeholder: false,
+            indexed_output: None,
+            warning: None,
+            score: Some(chosen.score()),
+            quality: None,
+            duplicate_crops: 0,
+        })
+    }
+
+    struct Probe {
+        width: u32,
+        height: u32,
+        /// As reported by ffprobe, e.g. `30000/1001`; passed back to ffmpeg.
+        frame_rate: String,
+    }
+
+    fn probe(input: &Path) -> Result<Probe> {
+        let out = Command::new("ffprobe")
+            .args(["-v", "error", "-select_streams", "v:0"])
+            .args(["-show_entries", "stream=width,height,r_frame_rate"])
+            .args(["-of", "csv=p=0"])
+            .arg(input)
+            .output()
+            .context("Failed to run ffprobe (is FFmpeg installed?)")?;
+        if !out.status.success() {
+            bail!(
+                "ffprobe failed: {}",
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+        }
+
+        let text = String::from_utf8_lossy(&out.stdout);
+        let fields: Vec<&str> = text.trim().split(',').collect();
+        let [width, height, frame_rate] = fields[..] else {
+            bail!("No video stream found");
+        };
+
+        Ok(Probe {
+            width: width.parse().context("Bad width from ffprobe")?,
+            height: height.parse().context("Bad height from ffprobe")?,
+            frame_rate: frame_rate.to_string(),
+        })
+    }
+
+    /// The next decoded frame, or `None` at the end of the stream.
+    fn read_frame(
+        frames: &mut impl Read,
+        width: u32,
+        height: u32,
+        frame_len: usize,
+    ) -> Result<Option<DynamicImage>> {
+        let mut buf = vec![0; frame_len];
+        match frames.read_exact(&mut buf) {
+            Ok(()) => Ok(RgbImage::from_raw(width, height, buf).map(DynamicImage::ImageRgb8)),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e).context("Failed to read frame from ffmpeg"),
+        }
+    }
+
+    /// Center of the face in `faces` closest to `center`.
+    fn nearest(faces: &[FaceInfo], center: (f64, f64)) -> Option<(f64, f64)> {
+        faces
+            .iter()
+            .map(|face| center_of(face.bbox()))
+            .min_by(|a, b| distance(*a, center).total_cmp(&distance(*b, center)))
+    }
+
+    fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+        (a.0 - b.0).hypot(a.1 - b.1)
+    }
+}
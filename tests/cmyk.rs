@@ -0,0 +1,21 @@
+//! Faces are found in CMYK JPEGs, which are converted to RGB on decoding.
+
+use std::process::Command;
+
+/// See tests/fixtures/README.md.
+const CMYK_FACE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/cmyk_face.jpg");
+
+#[test]
+fn face_is_found_in_a_cmyk_jpeg() {
+    let result = Command::new(env!("CARGO_BIN_EXE_face_cropper"))
+        .args(["--coords-only", "--input", CMYK_FACE])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(result.status.success(), "{}", stderr);
+
+    let stdout = String::from_utf8(result.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 1, "stdout {:?}, stderr {}", stdout, stderr);
+    assert!(lines[0].starts_with(CMYK_FACE), "{}", lines[0]);
+}
@@ -0,0 +1,32 @@
+//! `--copy-unprocessed` never copies a file onto itself, even when a
+//! templated `--output` expands back to the input directory.
+
+use std::fs;
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn templated_output_over_the_input_is_not_copied() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("2001");
+    fs::create_dir(&input).unwrap();
+    let notes = input.join("notes.txt");
+    fs::write(&notes, "hello").unwrap();
+    // Mid-2001, so `{Y}` is 2001 in every time zone.
+    let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(994_000_000);
+    fs::File::options()
+        .write(true)
+        .open(&notes)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+
+    let result = Command::new(env!("CARGO_BIN_EXE_face_cropper"))
+        .current_dir(dir.path())
+        .args(["--input", "2001", "--output", "{Y}", "--copy-unprocessed"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("would overwrite itself"), "{}", stderr);
+    assert_eq!(fs::read_to_string(&notes).unwrap(), "hello");
+}
@@ -0,0 +1,127 @@
+//! Crops are cut from the full-resolution image and only then scaled, even
+//! when detection runs on a scaled copy.
+
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, Rgb, RgbImage};
+use std::path::Path;
+use std::process::Command;
+
+const FACE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/selftest_face.jpg");
+
+/// A 2400x1800 image: the self-test portrait enlarged three times on a
+/// smooth gradient, so the crop region holds fine detail that a scaled
+/// copy would lose.
+fn high_resolution_source(path: &Path) -> DynamicImage {
+    let face = image::open(FACE).unwrap().to_rgb8();
+    let face = image::imageops::resize(&face, 630, 630, FilterType::Lanczos3);
+    let mut img = RgbImage::from_fn(2400, 1800, |x, y| {
+        Rgb([(x / 10) as u8, (y / 8) as u8, ((x + y) / 20) as u8])
+    });
+    image::imageops::overlay(&mut img, &face, 900, 500);
+    img.save(path).unwrap();
+    DynamicImage::ImageRgb8(img)
+}
+
+/// The `crop` rectangle of the sidecar next to `crop_path`.
+fn crop_region(crop_path: &Path) -> (u32, u32, u32, u32) {
+    let text = std::fs::read_to_string(crop_path.with_extension("json")).unwrap();
+    let sidecar: serde_json::Value = serde_json::from_str(&text).unwrap();
+    let field = |name: &str| sidecar["crop"][name].as_u64().unwrap() as u32;
+    (field("x"), field("y"), field("width"), field("height"))
+}
+
+fn crop(source: &Path, output: &Path, detection: &[&str]) {
+    let result = Command::new(env!("CARGO_BIN_EXE_face_cropper"))
+        .arg("--input")
+        .arg(source)
+        .arg("--output")
+        .arg(output)
+        .args(["--face-crop-strategy", "tightest-square", "--sidecar"])
+        .args(["--sizes", "1024,128"])
+        .args(detection)
+        .output()
+        .unwrap();
+    assert!(
+        result.status.success(),
+        "{}",
+        String::from_utf8_lossy(&result.stderr)
+    );
+}
+
+#[test]
+fn scaled_detection_crops_from_the_original_pixels() {
+    let dir = tempfile::tempdir().unwrap();
+    let source_path = dir.path().join("large.png");
+    let source = high_resolution_source(&source_path);
+
+    // Detection on a 300px copy, then on full-resolution regions.
+    let output = dir.path().join("crop.png");
+    crop(
+        &source_path,
+        &output,
+        &["--two-stage-detect", "--coarse-size", "300"],
+    );
+
+    let (x, y, width, height) = crop_region(&output);
+    assert!(
+        width > 300,
+        "crop {}x{} is not from the 2400px image",
+        width,
+        height
+    );
+    let expected = source.crop_imm(x, y, width, height);
+    let saved = image::open(&output).unwrap();
+    assert_eq!(saved.dimensions(), (width, height));
+    assert!(
+        saved.to_rgb8() == expected.to_rgb8(),
+        "crop differs from the source pixels"
+    );
+
+    // Upscaled and downscaled variants come from the full-resolution crop.
+    for size in [1024, 128] {
+        let scale = |side: u32| {
+            ((side as f64 * size as f64 / width.max(height) as f64).round() as u32).max(1)
+        };
+        let expected = expected.resize_exact(scale(width), scale(height), FilterType::Lanczos3);
+        let sized = image::open(dir.path().join(format!("crop_{}px.png", size))).unwrap();
+        assert!(
+            sized.to_rgb8() == expected.to_rgb8(),
+            "{}px variant is not scaled from the source crop",
+            size
+        );
+    }
+
+    // The same region taken from a copy at the coarse pass's scale is
+    // visibly different, so the checks above would notice.
+    let coarse = source.resize_exact(300, 225, FilterType::Triangle);
+    let from_coarse = coarse
+        .crop_imm(x / 8, y / 8, width / 8, height / 8)
+        .resize_exact(width, height, FilterType::Lanczos3);
+    assert!(from_coarse.to_rgb8() != expected.to_rgb8());
+}
+
+#[test]
+fn upscaled_detection_crops_from_the_original_pixels() {
+    let dir = tempfile::tempdir().unwrap();
+    let source_path = dir.path().join("small.png");
+    let face = image::open(FACE).unwrap().to_rgb8();
+    face.save(&source_path).unwrap();
+
+    // Detection on a copy enlarged to 420px.
+    let output = dir.path().join("crop.png");
+    crop(&source_path, &output, &["--detect-upscale-small", "400"]);
+
+    let (x, y, width, height) = crop_region(&output);
+    let expected = DynamicImage::ImageRgb8(face).crop_imm(x, y, width, height);
+    let saved = image::open(&output).unwrap();
+    assert!(
+        saved.to_rgb8() == expected.to_rgb8(),
+        "crop differs from the source pixels"
+    );
+
+    let scale =
+        |side: u32| ((side as f64 * 1024.0 / width.max(height) as f64).round() as u32).max(1);
+    let expected = expected.resize_exact(scale(width), scale(height), FilterType::Lanczos3);
+    let sized = image::open(dir.path().join("crop_1024px.png")).unwrap();
+    assert!(sized.to_rgb8() == expected.to_rgb8());
+}
@@ -0,0 +1,119 @@
+//! An output naming the input is refused unless --in-place asks for it.
+
+use image::{GenericImageView, Rgb, RgbImage};
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+const FACE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/selftest_face.jpg");
+
+/// The self-test portrait on a wider canvas, so its crop is smaller than
+/// the input and an overwrite shows in the dimensions.
+fn write_input(path: &Path) {
+    let face = image::open(FACE).unwrap().to_rgb8();
+    let mut img = RgbImage::from_pixel(420, face.height(), Rgb([200, 200, 200]));
+    image::imageops::overlay(&mut img, &face, 105, 0);
+    img.save(path).unwrap();
+}
+
+fn run(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_face_cropper"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn assert_refused(result: &Output) {
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(
+        stderr.contains("Refusing to overwrite the input"),
+        "not refused: {}",
+        stderr
+    );
+}
+
+#[test]
+fn output_naming_the_input_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("portrait.png");
+    write_input(&input);
+    let original = fs::read(&input).unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+
+    for output in [
+        input.to_str().unwrap(),
+        "./portrait.png",
+        "sub/../portrait.png",
+    ] {
+        let result = run(dir.path(), &["--input", "portrait.png", "--output", output]);
+        assert_refused(&result);
+        assert!(
+            fs::read(&input).unwrap() == original,
+            "{} changed the input",
+            output
+        );
+    }
+
+    // A directory run whose crops would land on their sources.
+    let result = run(
+        dir.path(),
+        &["--input", ".", "--output", ".", "--suffix", ""],
+    );
+    assert_refused(&result);
+    assert!(fs::read(&input).unwrap() == original);
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_to_the_input_are_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("portrait.png");
+    write_input(&input);
+    let original = fs::read(&input).unwrap();
+    let link = dir.path().join("link.png");
+    std::os::unix::fs::symlink("portrait.png", &link).unwrap();
+
+    let result = run(
+        dir.path(),
+        &["--input", "portrait.png", "--output", "link.png"],
+    );
+    assert_refused(&result);
+    assert!(fs::read(&input).unwrap() == original);
+
+    // The input given through the symlink, the output as the file itself.
+    let input_arg = input.to_str().unwrap();
+    let result = run(dir.path(), &["--input", "link.png", "--output", input_arg]);
+    assert_refused(&result);
+    assert!(fs::read(&input).unwrap() == original);
+    assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+}
+
+#[test]
+fn in_place_overwrites_the_input() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("portrait.png");
+    write_input(&input);
+
+    let result = run(
+        dir.path(),
+        &[
+            "--input",
+            "portrait.png",
+            "--output",
+            "./portrait.png",
+            "--in-place",
+        ],
+    );
+    assert!(
+        result.status.success(),
+        "{}",
+        String::from_utf8_lossy(&result.stderr)
+    );
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(!stderr.contains("Refusing"), "{}", stderr);
+
+    let (width, height) = image::open(&input).unwrap().dimensions();
+    assert_eq!((width, height), (210, 210));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
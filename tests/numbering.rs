@@ -0,0 +1,133 @@
+//! `--number-outputs` with many `--jobs` workers: every crop gets its own
+//! number, rejected images take none, and the manifest and sidecars agree
+//! with the files on disk.
+
+use image::{Rgb, RgbImage};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const FACE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/selftest_face.jpg");
+
+const FACES: usize = 16;
+const BLANKS: usize = 8;
+const JOBS: &str = "16";
+
+/// `FACES` copies of the self-test portrait and `BLANKS` faceless gray
+/// images in `dir`, interleaved in sorted order.
+fn write_inputs(dir: &Path) {
+    fs::create_dir(dir).unwrap();
+    for i in 0..FACES {
+        fs::copy(FACE, dir.join(format!("img{:02}_face.jpg", i * 2))).unwrap();
+    }
+    let blank = RgbImage::from_pixel(64, 64, Rgb([128, 128, 128]));
+    for i in 0..BLANKS {
+        blank
+            .save(dir.join(format!("img{:02}_blank.png", i * 2 + 1)))
+            .unwrap();
+    }
+}
+
+fn run(input: &Path, output: &Path, manifest: &Path) {
+    let result = Command::new(env!("CARGO_BIN_EXE_face_cropper"))
+        .args(["--jobs", JOBS, "--number-outputs", "--sidecar", "--input"])
+        .arg(input)
+        .arg("--output")
+        .arg(output)
+        .arg("--manifest")
+        .arg(manifest)
+        .output()
+        .unwrap();
+    assert!(
+        result.status.success(),
+        "{}",
+        String::from_utf8_lossy(&result.stderr)
+    );
+}
+
+/// Manifest rows as input -> (output, status).
+fn manifest_rows(path: &Path) -> BTreeMap<PathBuf, (String, String)> {
+    let text = fs::read_to_string(path).unwrap();
+    let mut lines = text.lines();
+    assert!(lines.next().unwrap().starts_with("input,output,status,"));
+    let mut rows = BTreeMap::new();
+    for line in lines {
+        let fields: Vec<&str> = line.splitn(4, ',').collect();
+        assert_eq!(fields.len(), 4, "torn manifest row {:?}", line);
+        let row = (fields[1].to_string(), fields[2].to_string());
+        assert!(
+            rows.insert(PathBuf::from(fields[0]), row).is_none(),
+            "input listed twice: {:?}",
+            line
+        );
+    }
+    rows
+}
+
+#[test]
+fn parallel_numbers_are_unique_and_gapless() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("in");
+    let output = dir.path().join("out");
+    let manifest = dir.path().join("manifest.csv");
+    write_inputs(&input);
+
+    run(&input, &output, &manifest);
+
+    let expected: BTreeSet<String> = (1..=FACES)
+        .flat_map(|n| [format!("{:06}.jpg", n), format!("{:06}.json", n)])
+        .collect();
+    let written: BTreeSet<String> = fs::read_dir(&output)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(written, expected);
+
+    let rows = manifest_rows(&manifest);
+    assert_eq!(rows.len(), FACES + BLANKS);
+    let mut claimed = BTreeSet::new();
+    for (source, (crop, status)) in &rows {
+        let name = source.file_name().unwrap().to_str().unwrap();
+        if name.ends_with("_blank.png") {
+            assert_eq!((crop.as_str(), status.as_str()), ("", "no_face"));
+            continue;
+        }
+        assert_eq!(status, "ok", "{:?}", source);
+        assert!(claimed.insert(crop.clone()), "{} given out twice", crop);
+
+        let sidecar = Path::new(crop).with_extension("json");
+        let sidecar: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(sidecar).unwrap()).unwrap();
+        assert_eq!(sidecar["input"].as_str(), source.to_str());
+        assert_eq!(sidecar["output"].as_str(), Some(crop.as_str()));
+    }
+    assert_eq!(claimed.len(), FACES);
+}
+
+#[test]
+fn continue_numbering_picks_up_after_a_parallel_run() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("in");
+    let output = dir.path().join("out");
+    write_inputs(&input);
+
+    run(&input, &output, &dir.path().join("first.csv"));
+    let result = Command::new(env!("CARGO_BIN_EXE_face_cropper"))
+        .args(["--jobs", JOBS, "--number-outputs", "--continue-numbering"])
+        .arg("--input")
+        .arg(&input)
+        .arg("--output")
+        .arg(&output)
+        .output()
+        .unwrap();
+    assert!(result.status.success());
+
+    let crops: BTreeSet<String> = fs::read_dir(&output)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".jpg"))
+        .collect();
+    let expected: BTreeSet<String> = (1..=2 * FACES).map(|n| format!("{:06}.jpg", n)).collect();
+    assert_eq!(crops, expected);
+}